@@ -0,0 +1,24 @@
+//! Minimal consumer of the logging API, linked by the `disabled_build` integration test to check which parts of the
+//! crate a built binary retains. It is not meant to be run.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+use rust_advanced_logger_dxe::{
+  debugln, init_debug, log_level_filter, register_exit_boot_services_handler, set_log_level_filter, DEBUG_INFO,
+  DEBUG_WARN,
+};
+
+fn main() {
+  //opaque to the optimizer, so that the initialization paths are not folded away for a null pointer.
+  let boot_services = std::hint::black_box(core::ptr::null_mut());
+  if init_debug(boot_services).is_ok() {
+    let _ = register_exit_boot_services_handler(boot_services);
+  }
+  set_log_level_filter(DEBUG_WARN);
+  debugln!(DEBUG_WARN, "level filter {:#x}", log_level_filter());
+  debugln!(DEBUG_INFO, "filtered");
+}
@@ -0,0 +1,187 @@
+//! Columnar Output
+//!
+//! Allocation-free helper for logging aligned columns, e.g. register dumps or handle lists. Each field is padded to
+//! the width of its column as it is streamed to the underlying writer; fields that are too wide for their column are
+//! cut short with an ellipsis.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+use core::fmt::{self, Write};
+
+use crate::{writer, LogWriter};
+
+// written in place of the end of a field that does not fit in its column.
+const ELLIPSIS: &str = "...";
+
+/// Writes rows of fixed-width columns to the AdvancedLogger (or any other [`fmt::Write`]) without allocating.
+///
+/// Fields are left-aligned and padded with spaces to the width of their column, and columns are separated by a single
+/// space. A field with more characters than its column is truncated, with `...` in place of its last characters.
+/// Fields beyond the configured columns are written as-is. The padding of the last field in each row is omitted, so
+/// rows do not end in trailing spaces.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::{ColumnWriter, DEBUG_INFO};
+///
+/// fn dump_registers(registers: &[(&str, u64)]) -> core::fmt::Result {
+///   let mut columns = ColumnWriter::new(DEBUG_INFO, &[8, 18]);
+///   for (name, value) in registers {
+///     columns.field(name)?;
+///     columns.field(format_args!("{:#018x}", value))?;
+///     columns.end_row()?;
+///   }
+///   Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ColumnWriter<'a, W = LogWriter> {
+  writer: W,
+  widths: &'a [usize],
+  column: usize,
+  //padding owed by the previous field, written once it is known not to be the last field in the row.
+  padding: usize,
+}
+
+impl<'a> ColumnWriter<'a, LogWriter> {
+  /// Creates a column writer that writes to the AdvancedLogger at the specified level, with the given column widths
+  /// (in characters).
+  pub fn new(level: usize, widths: &'a [usize]) -> Self {
+    Self::with_writer(writer(level), widths)
+  }
+}
+
+impl<'a, W: fmt::Write> ColumnWriter<'a, W> {
+  /// Creates a column writer that writes to `writer`, with the given column widths (in characters).
+  pub fn with_writer(writer: W, widths: &'a [usize]) -> Self {
+    ColumnWriter { writer, widths, column: 0, padding: 0 }
+  }
+
+  /// Writes `value` as the next field of the current row.
+  pub fn field(&mut self, value: impl fmt::Display) -> fmt::Result {
+    if self.column > 0 {
+      write!(self.writer, "{:1$}", "", self.padding + 1)?;
+    }
+    let column = self.column;
+    self.column += 1;
+    self.padding = 0;
+    let Some(&width) = self.widths.get(column) else { return write!(self.writer, "{:}", value) };
+
+    let mut field = FieldWriter::new(&mut self.writer, width);
+    write!(field, "{:}", value)?;
+    self.padding = field.finish()?;
+    Ok(())
+  }
+
+  /// Ends the current row, and starts a new one.
+  pub fn end_row(&mut self) -> fmt::Result {
+    self.column = 0;
+    self.padding = 0;
+    self.writer.write_str("\n")
+  }
+}
+
+// Private fmt::Write adapter that writes at most `width` characters of a field to the inner writer. Whether the field
+// fits is only known once it is complete, so the characters that would be replaced by the ellipsis are held back
+// until the field is finished.
+struct FieldWriter<'a, W> {
+  inner: &'a mut W,
+  width: usize,
+  written: usize,
+  held: [char; ELLIPSIS.len()],
+  held_count: usize,
+  overflowed: bool,
+}
+
+impl<'a, W: fmt::Write> FieldWriter<'a, W> {
+  fn new(inner: &'a mut W, width: usize) -> Self {
+    FieldWriter { inner, width, written: 0, held: ['\0'; ELLIPSIS.len()], held_count: 0, overflowed: false }
+  }
+
+  // completes the field: writes the ellipsis if it overflowed, or otherwise the held characters. Returns the number of
+  // spaces needed to pad the field out to the column width.
+  fn finish(self) -> Result<usize, fmt::Error> {
+    if self.overflowed && self.width >= ELLIPSIS.len() {
+      self.inner.write_str(ELLIPSIS)?;
+      return Ok(0);
+    }
+    for &c in &self.held[..self.held_count] {
+      self.inner.write_char(c)?;
+    }
+    Ok(self.width - self.written - self.held_count)
+  }
+}
+
+impl<W: fmt::Write> fmt::Write for FieldWriter<'_, W> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    //characters up to `width - ELLIPSIS.len()` are always written, so they are passed straight through.
+    let visible = self.width.saturating_sub(ELLIPSIS.len());
+    let passthrough =
+      s.char_indices().nth(visible.saturating_sub(self.written)).map(|(index, _)| index).unwrap_or(s.len());
+    if passthrough > 0 {
+      self.inner.write_str(&s[..passthrough])?;
+      self.written += s[..passthrough].chars().count();
+    }
+    for c in s[passthrough..].chars() {
+      if self.written + self.held_count == self.width {
+        self.overflowed = true;
+        break;
+      }
+      self.held[self.held_count] = c;
+      self.held_count += 1;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+  use super::ColumnWriter;
+  use std::string::String;
+
+  fn row(widths: &[usize], fields: &[&str]) -> String {
+    let mut columns = ColumnWriter::with_writer(String::new(), widths);
+    for field in fields {
+      columns.field(field).unwrap();
+    }
+    columns.end_row().unwrap();
+    columns.writer
+  }
+
+  #[test]
+  fn fields_should_be_padded_to_column_width() {
+    assert_eq!(row(&[6, 4, 3], &["rax", "0x1", "ok"]), "rax    0x1  ok\n");
+    assert_eq!(row(&[6, 4], &["exact!", "abcd"]), "exact! abcd\n");
+    //fields beyond the configured columns are written as-is.
+    assert_eq!(row(&[4], &["a", "extra"]), "a    extra\n");
+  }
+
+  #[test]
+  fn wide_fields_should_be_truncated_with_ellipsis() {
+    assert_eq!(row(&[6, 4], &["too wide", "x"]), "too... x\n");
+    assert_eq!(row(&[5, 2], &["\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}", "x"]), "\u{e9}\u{e9}... x\n");
+    //columns too narrow for an ellipsis are cut short.
+    assert_eq!(row(&[2, 2], &["wide", "x"]), "wi x\n");
+  }
+
+  #[test]
+  fn fields_written_in_fragments_should_be_measured_as_a_whole() {
+    let mut columns = ColumnWriter::with_writer(String::new(), &[8, 1]);
+    columns.field(format_args!("{:}{:}{:}", "ab", "cd", "efg")).unwrap();
+    columns.field("x").unwrap();
+    columns.field(format_args!("{:}{:}{:}", "ab", "cd", "efghi")).unwrap();
+    columns.end_row().unwrap();
+    assert_eq!(columns.writer, "abcdefg  x abcdefghi\n");
+
+    let mut columns = ColumnWriter::with_writer(String::new(), &[8, 1]);
+    columns.field(format_args!("{:}{:}{:}", "ab", "cd", "efghi")).unwrap();
+    columns.end_row().unwrap();
+    assert_eq!(columns.writer, "abcde...\n");
+  }
+}
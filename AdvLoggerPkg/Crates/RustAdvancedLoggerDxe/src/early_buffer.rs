@@ -0,0 +1,180 @@
+//! Early Log Buffer
+//!
+//! Fixed-capacity, allocation-free FIFO of `(level, bytes)` records used to capture log output that is written before
+//! the AdvancedLogger protocol has been located. When the buffer is full, the oldest records are evicted to make room
+//! for new ones and a dropped-record count is incremented.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+/// Capacity in bytes of the early log buffer, including per-record bookkeeping.
+pub const EARLY_LOG_BUFFER_SIZE: usize = 4096;
+
+// Each record is stored as a header (level, then length) followed by the record bytes.
+const LEVEL_SIZE: usize = core::mem::size_of::<usize>();
+const LENGTH_SIZE: usize = core::mem::size_of::<u16>();
+const HEADER_SIZE: usize = LEVEL_SIZE + LENGTH_SIZE;
+const MAX_RECORD_LEN: usize = u16::MAX as usize;
+
+#[derive(Debug)]
+pub(crate) struct EarlyLogBuffer {
+  data: [u8; EARLY_LOG_BUFFER_SIZE],
+  used: usize,
+  newest_record: Option<usize>,
+  dropped: usize,
+}
+
+impl EarlyLogBuffer {
+  // creates a new, empty buffer. const fn to allow static initialization.
+  pub(crate) const fn new() -> Self {
+    EarlyLogBuffer { data: [0; EARLY_LOG_BUFFER_SIZE], used: 0, newest_record: None, dropped: 0 }
+  }
+
+  // appends `bytes` at the given level. Consecutive writes at the same level (e.g. the fragments of a single log call)
+  // are coalesced into one record where space permits.
+  pub(crate) fn push(&mut self, level: usize, bytes: &[u8]) {
+    if bytes.is_empty() {
+      return;
+    }
+
+    if let Some(offset) = self.newest_record {
+      let (newest_level, newest_len) = self.header(offset);
+      if newest_level == level
+        && newest_len + bytes.len() <= MAX_RECORD_LEN
+        && self.used + bytes.len() <= EARLY_LOG_BUFFER_SIZE
+      {
+        self.data[self.used..self.used + bytes.len()].copy_from_slice(bytes);
+        self.used += bytes.len();
+        self.set_header(offset, level, newest_len + bytes.len());
+        return;
+      }
+    }
+
+    let record_size = HEADER_SIZE + bytes.len();
+    if bytes.len() > MAX_RECORD_LEN || record_size > EARLY_LOG_BUFFER_SIZE {
+      self.dropped += 1;
+      return;
+    }
+
+    while self.used + record_size > EARLY_LOG_BUFFER_SIZE {
+      self.evict_oldest();
+    }
+
+    let offset = self.used;
+    self.set_header(offset, level, bytes.len());
+    self.data[offset + HEADER_SIZE..offset + record_size].copy_from_slice(bytes);
+    self.used += record_size;
+    self.newest_record = Some(offset);
+  }
+
+  // passes each buffered record to `f` from oldest to newest, and empties the buffer.
+  pub(crate) fn drain(&mut self, mut f: impl FnMut(usize, &[u8])) {
+    let mut offset = 0;
+    while offset < self.used {
+      let (level, len) = self.header(offset);
+      f(level, &self.data[offset + HEADER_SIZE..offset + HEADER_SIZE + len]);
+      offset += HEADER_SIZE + len;
+    }
+    self.used = 0;
+    self.newest_record = None;
+  }
+
+  // returns the number of records that were evicted or could not be stored.
+  pub(crate) fn dropped(&self) -> usize {
+    self.dropped
+  }
+
+  // removes the oldest record from the buffer to make space for new records.
+  fn evict_oldest(&mut self) {
+    let (_, len) = self.header(0);
+    let record_size = HEADER_SIZE + len;
+    self.data.copy_within(record_size..self.used, 0);
+    self.used -= record_size;
+    //if the newest record was the one evicted, there is nothing left to coalesce with.
+    self.newest_record = self.newest_record.and_then(|offset| offset.checked_sub(record_size));
+    self.dropped += 1;
+  }
+
+  fn header(&self, offset: usize) -> (usize, usize) {
+    let mut level = [0u8; LEVEL_SIZE];
+    level.copy_from_slice(&self.data[offset..offset + LEVEL_SIZE]);
+    let mut len = [0u8; LENGTH_SIZE];
+    len.copy_from_slice(&self.data[offset + LEVEL_SIZE..offset + HEADER_SIZE]);
+    (usize::from_ne_bytes(level), u16::from_ne_bytes(len) as usize)
+  }
+
+  fn set_header(&mut self, offset: usize, level: usize, len: usize) {
+    self.data[offset..offset + LEVEL_SIZE].copy_from_slice(&level.to_ne_bytes());
+    self.data[offset + LEVEL_SIZE..offset + HEADER_SIZE].copy_from_slice(&(len as u16).to_ne_bytes());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+  use super::{EarlyLogBuffer, EARLY_LOG_BUFFER_SIZE, HEADER_SIZE};
+  use std::{vec, vec::Vec};
+
+  fn drain_to_vec(buffer: &mut EarlyLogBuffer) -> Vec<(usize, Vec<u8>)> {
+    let mut records = Vec::new();
+    buffer.drain(|level, bytes| records.push((level, bytes.to_vec())));
+    records
+  }
+
+  #[test]
+  fn records_should_drain_in_order() {
+    let mut buffer = EarlyLogBuffer::new();
+    buffer.push(1, b"first ");
+    buffer.push(1, b"line\n");
+    buffer.push(2, b"second line\n");
+    buffer.push(1, b"third line\n");
+
+    assert_eq!(
+      drain_to_vec(&mut buffer),
+      vec![(1, b"first line\n".to_vec()), (2, b"second line\n".to_vec()), (1, b"third line\n".to_vec())]
+    );
+    assert_eq!(buffer.dropped(), 0);
+    assert!(drain_to_vec(&mut buffer).is_empty());
+  }
+
+  #[test]
+  fn full_buffer_should_evict_oldest_records() {
+    let mut buffer = EarlyLogBuffer::new();
+    let record = [b'x'; 100];
+    let records_that_fit = EARLY_LOG_BUFFER_SIZE / (HEADER_SIZE + record.len());
+
+    //alternate levels so that records are not coalesced.
+    for index in 0..records_that_fit + 3 {
+      buffer.push(index, &record);
+    }
+    assert_eq!(buffer.dropped(), 3);
+
+    let records = drain_to_vec(&mut buffer);
+    assert_eq!(records.len(), records_that_fit);
+    assert_eq!(records.first().unwrap().0, 3);
+    assert_eq!(records.last().unwrap().0, records_that_fit + 2);
+  }
+
+  #[test]
+  fn coalescing_with_evicted_record_should_start_a_new_record() {
+    let mut buffer = EarlyLogBuffer::new();
+    let record = [b'y'; EARLY_LOG_BUFFER_SIZE - HEADER_SIZE];
+    buffer.push(1, &record);
+    buffer.push(1, b"z");
+    assert_eq!(buffer.dropped(), 1);
+    assert_eq!(drain_to_vec(&mut buffer), vec![(1, b"z".to_vec())]);
+  }
+
+  #[test]
+  fn oversized_record_should_be_dropped() {
+    let mut buffer = EarlyLogBuffer::new();
+    buffer.push(1, b"kept\n");
+    buffer.push(2, &[b'z'; EARLY_LOG_BUFFER_SIZE]);
+    assert_eq!(buffer.dropped(), 1);
+    assert_eq!(drain_to_vec(&mut buffer), vec![(1, b"kept\n".to_vec())]);
+  }
+}
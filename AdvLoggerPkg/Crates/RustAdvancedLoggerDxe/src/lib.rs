@@ -13,7 +13,7 @@
 //!  ) -> u64 {
 //!
 //!    //Initialize debug logging - no output without this.
-//!    init_debug(unsafe { (*_system_table).boot_services});
+//!    let _ = init_debug(unsafe { (*_system_table).boot_services});
 //!
 //!    debugln!(DEBUG_INFO, "Hello, World. This is {:} in {:}.", "rust", "UEFI");
 //!
@@ -21,6 +21,27 @@
 //! }
 //! ```
 //!
+//! ## Compile-time maximum level
+//!
+//! The `max_level_off`, `max_level_error`, `max_level_warn`, `max_level_info` and `max_level_verbose` features set the
+//! most verbose standard level that the logging macros ([`debug!`], [`debugln!`], [`debug_to!`], [`debug_static!`],
+//! [`try_debug!`], [`debug_kv!`], [`hexdump!`], [`debug_escaped!`], [`span!`] and [`debug_assert_log!`]) are compiled
+//! for. Calls at levels above it expand to a
+//! constant-false condition, so neither their arguments nor the runtime level filter check remain in the binary.
+//! [`DEBUG_INIT`] is ranked between [`DEBUG_WARN`] and [`DEBUG_INFO`]. Custom levels are only stripped by
+//! `max_level_off`. If several of the features are enabled, the most restrictive applies.
+//!
+//! ## Disabled builds
+//!
+//! The `disabled` feature removes logging entirely, for size-constrained consumers that depend on this crate
+//! (possibly transitively) but do not want any logging code. It implies `max_level_off`, so the logging macros expand
+//! to nothing; in addition, the initialization routines ([`init_debug`], [`init_debug_retry`], [`reinit_debug`],
+//! [`init_debug_with_protocol`], [`init_debug_with_guid`] and [`register_exit_boot_services_handler`], and those of
+//! [`LockedAdvancedLogger`] instances) succeed without locating the AdvancedLogger protocol, and the non-macro logging
+//! routines discard their output. The protocol is never
+//! used, so the code that locates it and writes to it is unreachable and can be discarded by the linker. The public API
+//! is unchanged, so dependent code builds either way.
+//!
 //! ## License
 //!
 //! Copyright (C) Microsoft Corporation. All rights reserved.
@@ -32,13 +53,27 @@
 #[cfg(doc)]
 extern crate std; //allow rustdoc links to reference std (e.g. println docs below).
 
+mod columns;
+#[cfg(feature = "early-buffer")]
+mod early_buffer;
+mod lock;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use columns::ColumnWriter;
+pub use lock::RawMutex;
+
+#[cfg(feature = "early-buffer")]
+pub use early_buffer::EARLY_LOG_BUFFER_SIZE;
+
 use core::{
   ffi::c_void,
   fmt::{self, Write},
+  sync::atomic::{AtomicUsize, Ordering},
 };
 use r_efi::{
-  efi::{Guid, Status},
-  system::BootServices,
+  efi::{Event, Guid, Status},
+  system::{BootServices, EVT_SIGNAL_EXIT_BOOT_SERVICES, TPL_NOTIFY},
 };
 
 //Global static logger instance - this is a singleton.
@@ -55,10 +90,49 @@ pub const DEBUG_VERBOSE: usize = 0x00400000;
 /// Standard UEFI DEBUG_ERROR level.
 pub const DEBUG_ERROR: usize = 0x80000000;
 
+/// Maximum number of sinks that may be registered with [`add_sink`].
+pub const MAX_LOG_SINKS: usize = 4;
+
+/// Maximum number of custom levels that may be registered with [`register_custom_level`].
+pub const MAX_CUSTOM_LEVELS: usize = 8;
+
+/// Default maximum number of bytes passed to a single `write_log` call, see [`set_max_chunk`].
+pub const DEFAULT_MAX_CHUNK: usize = 4096;
+
+/// Default number of digits of the sequence number prefix, see [`set_sequence_numbers`].
+pub const DEFAULT_SEQUENCE_WIDTH: usize = 5;
+
+// largest sequence number width; 10^19 is the largest power of 10 that fits in a u64.
+const MAX_SEQUENCE_WIDTH: usize = 19;
+
+// set by the `disabled` feature: the logger is never initialized and all output is discarded.
+const DISABLED: bool = cfg!(feature = "disabled");
+
+/// A secondary destination for log output, see [`add_sink`].
+pub type LogSink = spin::Mutex<dyn fmt::Write + Send>;
+
+/// The reason the output of a [`try_log`] call was not written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+  /// There is no AdvancedLogger protocol to write to: the logger has not been initialized, or boot services have
+  /// exited.
+  Uninitialized,
+  /// The level is excluded by the level filter (see [`set_log_level_filter`]) or the compile-time maximum level.
+  Filtered,
+  /// The logger is initialized, but the output could not be written in full. The protocol's `write_log` does not
+  /// return a status, so this is currently only reported (with `Status::ABORTED`) when formatting the arguments fails,
+  /// i.e. when a `Display` or `Debug` implementation returns an error.
+  WriteFailed(Status),
+}
+
 // AdvancedLogger protocol definition. Mirrors C definition in AdvLoggerPkg/Include/Protocol/AdvancedLogger.h
 const ADVANCED_LOGGER_PROTOCOL_GUID: Guid =
   Guid::from_fields(0x434f695c, 0xef26, 0x4a12, 0x9e, 0xba, &[0xdd, 0xef, 0x00, 0x97, 0x49, 0x7c]);
 
+// signature and version of the protocol definition in AdvancedLogger.h.
+const ADVANCED_LOGGER_PROTOCOL_SIGNATURE: u32 = u32::from_le_bytes(*b"LOGP");
+const ADVANCED_LOGGER_PROTOCOL_VERSION: u32 = 2;
+
 type AdvancedLoggerWriteProtocol = extern "efiapi" fn(*const AdvancedLoggerProtocol, usize, *const u8, usize);
 
 #[repr(C)]
@@ -68,235 +142,4213 @@ struct AdvancedLoggerProtocol {
   write_log: AdvancedLoggerWriteProtocol,
 }
 
-// Private un-synchronized AdvancedLogger wrapper. Provides implementation of fmt::Write for AdvancedLogger.
+// Extended protocol layout that adds a read-back interface for the accumulated log buffer. The read-back interface is
+// appended to the end of the version 2 definition in AdvancedLogger.h, and is only present if the protocol reports a
+// version of at least ADVANCED_LOGGER_PROTOCOL_READ_VERSION.
+const ADVANCED_LOGGER_PROTOCOL_READ_VERSION: u32 = 3;
+
+// Copies accumulated log bytes into the buffer. On input, the size is the size of the buffer; on output, it is the
+// number of bytes copied (or the number of bytes required, on EFI_BUFFER_TOO_SMALL).
+type AdvancedLoggerReadProtocol = extern "efiapi" fn(*const AdvancedLoggerProtocol, *mut u8, *mut usize) -> Status;
+
+#[repr(C)]
+struct AdvancedLoggerReadableProtocol {
+  protocol: AdvancedLoggerProtocol,
+  read_log: AdvancedLoggerReadProtocol,
+}
+
+// Extended protocol layout that adds an entry to commit any output buffered internally by the AdvancedLogger
+// implementation. The flush interface is appended to the end of the readable definition, and is only present if the
+// protocol reports a version of at least ADVANCED_LOGGER_PROTOCOL_FLUSH_VERSION.
+const ADVANCED_LOGGER_PROTOCOL_FLUSH_VERSION: u32 = 4;
+
+type AdvancedLoggerFlushProtocol = extern "efiapi" fn(*const AdvancedLoggerProtocol);
+
+#[repr(C)]
+struct AdvancedLoggerFlushableProtocol {
+  readable: AdvancedLoggerReadableProtocol,
+  flush: AdvancedLoggerFlushProtocol,
+}
+
+// Private un-synchronized AdvancedLogger wrapper. Provides the write path to the AdvancedLogger protocol.
 #[derive(Debug)]
 struct AdvancedLogger {
   protocol: Option<*mut AdvancedLoggerProtocol>,
-  level: usize,
+  //GUID the protocol is located by, if other than ADVANCED_LOGGER_PROTOCOL_GUID. The default is only named on the
+  //locate path, so that logger statics do not hold the GUID and disabled builds reference it nowhere.
+  protocol_guid: Option<Guid>,
+  filter: usize,
+  timestamp_source: Option<fn() -> u64>,
+  exit_boot_services_event: Option<Event>,
+  //name written before the output of each log call, to tell the output of separate logger instances apart.
+  name: Option<&'static str>,
+  level_prefix: bool,
+  severity_prefix: bool,
+  severity_map: &'static [(usize, u8)],
+  sequence_numbers: bool,
+  sequence_width: usize,
+  //sequence number of the next log call, counting from 1.
+  next_sequence: u64,
+  crlf: bool,
+  last_write_ended_with_cr: bool,
+  max_line_len: usize,
+  max_chunk: usize,
+  line_buffered: bool,
+  //number of columns output lines are wrapped at, or 0 if they are not wrapped.
+  wrap_width: usize,
+  //kept in the logger rather than in each LevelWriter, so that the column carries over from one log call to the next
+  //and the word buffer does not take up stack space in every log call.
+  wrap: WrapState,
+  dropped_messages: u64,
+  //set once a protocol has been installed; until then, discarded output is counted in discarded_before_init so that
+  //it can be reported once the logger is initialized.
+  installed_once: bool,
+  discarded_before_init: usize,
+  custom_levels: [Option<(usize, &'static str)>; MAX_CUSTOM_LEVELS],
+  sinks: LogSinks,
+  #[cfg(feature = "early-buffer")]
+  early_buffer: early_buffer::EarlyLogBuffer,
+  //if set, output is also held in the early buffer while the protocol is unavailable after it has been installed.
+  #[cfg(feature = "early-buffer")]
+  offline_buffering: bool,
 }
 impl AdvancedLogger {
   // creates a new AdvancedLogger
   const fn new() -> Self {
-    AdvancedLogger { protocol: None, level: DEBUG_INFO }
+    AdvancedLogger {
+      protocol: None,
+      protocol_guid: None,
+      filter: usize::MAX,
+      timestamp_source: None,
+      exit_boot_services_event: None,
+      name: None,
+      level_prefix: false,
+      severity_prefix: false,
+      severity_map: DEFAULT_SEVERITY_MAP,
+      sequence_numbers: false,
+      sequence_width: DEFAULT_SEQUENCE_WIDTH,
+      next_sequence: 1,
+      crlf: false,
+      last_write_ended_with_cr: false,
+      max_line_len: usize::MAX,
+      max_chunk: DEFAULT_MAX_CHUNK,
+      line_buffered: false,
+      wrap_width: 0,
+      wrap: WrapState::new(),
+      dropped_messages: 0,
+      installed_once: false,
+      discarded_before_init: 0,
+      custom_levels: [None; MAX_CUSTOM_LEVELS],
+      sinks: LogSinks([None; MAX_LOG_SINKS]),
+      #[cfg(feature = "early-buffer")]
+      early_buffer: early_buffer::EarlyLogBuffer::new(),
+      #[cfg(feature = "early-buffer")]
+      offline_buffering: false,
+    }
   }
 
-  // initialize the AdvancedLogger by acquiring a pointer to the AdvancedLogger protocol.
-  fn init(&mut self, bs: *mut BootServices) {
-    let boot_services = unsafe { bs.as_mut().expect("Boot Services Pointer is NULL") };
+  // initialize the AdvancedLogger by acquiring a pointer to the AdvancedLogger protocol. Returns the status from
+  // locate_protocol as an error if the protocol could not be located, or Status::INVALID_PARAMETER if `bs` is null.
+  fn init(&mut self, bs: *mut BootServices) -> Result<(), Status> {
+    let Some(boot_services) = (unsafe { bs.as_mut() }) else {
+      self.protocol = None;
+      return Err(Status::INVALID_PARAMETER);
+    };
+    let mut guid = self.protocol_guid.unwrap_or(ADVANCED_LOGGER_PROTOCOL_GUID);
     let mut ptr: *mut c_void = core::ptr::null_mut();
-    let status = (boot_services.locate_protocol)(
-      &ADVANCED_LOGGER_PROTOCOL_GUID as *const Guid as *mut Guid,
-      core::ptr::null_mut(),
-      core::ptr::addr_of_mut!(ptr),
-    );
+    let status =
+      (boot_services.locate_protocol)(&mut guid as *mut Guid, core::ptr::null_mut(), core::ptr::addr_of_mut!(ptr));
     match status {
-      Status::SUCCESS => self.protocol = Some(ptr as *mut AdvancedLoggerProtocol),
-      _ => self.protocol = None,
+      Status::SUCCESS => {
+        self.install_protocol(ptr as *mut AdvancedLoggerProtocol);
+        Ok(())
+      }
+      _ => {
+        self.protocol = None;
+        Err(status)
+      }
+    }
+  }
+
+  // start writing to the given AdvancedLogger protocol instance.
+  fn install_protocol(&mut self, protocol: *mut AdvancedLoggerProtocol) {
+    self.protocol = Some(protocol);
+    #[cfg(feature = "early-buffer")]
+    self.flush_early_buffer();
+    if !self.installed_once {
+      self.installed_once = true;
+      let discarded = core::mem::take(&mut self.discarded_before_init);
+      if discarded > 0 {
+        self.log(DEBUG_WARN, format_args!("{:} messages were logged before init and discarded\n", discarded));
+      }
+    }
+  }
+
+  // counts a log call whose output could not be written.
+  fn count_dropped(&mut self) {
+    self.dropped_messages += 1;
+    if !self.installed_once {
+      self.discarded_before_init += 1;
     }
   }
 
-  // log the debug output in `args` at the given log level.
+  // log the debug output in `args` at the given log level. Output at levels excluded by the filter is discarded
+  // before `args` is formatted.
   fn log(&mut self, level: usize, args: fmt::Arguments) {
-    self.level = level;
-    self.write_fmt(args).expect("Printing to log failed.");
+    let _ = self.log_checked(level, args);
   }
-}
 
-impl fmt::Write for AdvancedLogger {
-  fn write_str(&mut self, s: &str) -> fmt::Result {
-    let Some(logger) = self.protocol else { return Err(fmt::Error) };
-    let logger = unsafe { logger.as_mut().expect("advanced logger protocol is null") };
-    (logger.write_log)(self.protocol.unwrap(), self.level, s.as_ptr(), s.as_bytes().len());
+  // log the debug output in `args` at the given log level, returning the reason if it could not be written.
+  fn log_checked(&mut self, level: usize, args: fmt::Arguments) -> Result<(), LogError> {
+    if level & self.filter == 0 {
+      return Err(LogError::Filtered);
+    }
+    let mut writer = LevelWriter::new(self, level);
+    //a write fails if there is no protocol to write to (e.g. before init or after ExitBootServices), or if formatting
+    //fails; the output is discarded and counted in that case.
+    if writer.write_prefix().and_then(|_| writer.write_fmt(args)).and_then(|_| writer.finish()).is_ok() {
+      return Ok(());
+    }
+    self.count_dropped();
+    match self.protocol {
+      Some(_) => Err(LogError::WriteFailed(Status::ABORTED)),
+      None => Err(LogError::Uninitialized),
+    }
+  }
+
+  // log the string `s` at the given log level, as for a log call with no format arguments but without going through
+  // core::fmt. Prefixes and other options apply as for `log`.
+  fn log_str(&mut self, level: usize, s: &str) {
+    if level & self.filter == 0 {
+      return;
+    }
+    let mut writer = LevelWriter::new(self, level);
+    if writer.write_prefix().and_then(|_| writer.write_str(s)).and_then(|_| writer.finish()).is_err() {
+      self.count_dropped();
+    }
+  }
+
+  // write pre-formatted bytes to the log at the given log level, without any prefix. Output at levels excluded by the
+  // filter is discarded.
+  fn write_bytes(&mut self, level: usize, bytes: &[u8]) {
+    if level & self.filter == 0 {
+      return;
+    }
+    if self.write_at_level(level, bytes).is_err() {
+      self.count_dropped();
+    }
+  }
+
+  // write the given bytes to the log at the given log level, rewriting lone '\n' line endings to "\r\n" if CRLF
+  // normalization is enabled. A '\r' at the end of the previous write is tracked so that a "\r\n" split across writes
+  // is left alone.
+  fn write_at_level(&mut self, level: usize, bytes: &[u8]) -> fmt::Result {
+    if !self.crlf {
+      return self.write_raw(level, bytes);
+    }
+    let mut start = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+      let previous_cr = if index == 0 { self.last_write_ended_with_cr } else { bytes[index - 1] == b'\r' };
+      if *byte == b'\n' && !previous_cr {
+        self.write_raw(level, &bytes[start..index])?;
+        self.write_raw(level, b"\r\n")?;
+        start = index + 1;
+      }
+    }
+    self.write_raw(level, &bytes[start..])?;
+    if let Some(last) = bytes.last() {
+      self.last_write_ended_with_cr = *last == b'\r';
+    }
+    Ok(())
+  }
+
+  // write the given bytes to the AdvancedLogger protocol at the given log level, then mirror them to any registered
+  // sinks. If the protocol has not yet been located (or is unavailable and offline buffering is enabled), the bytes are
+  // held in the early buffer (if enabled) until it is.
+  fn write_raw(&mut self, level: usize, bytes: &[u8]) -> fmt::Result {
+    if bytes.is_empty() {
+      return Ok(());
+    }
+    let result = match self.protocol {
+      Some(protocol) => {
+        write_protocol(protocol, level, bytes, self.max_chunk);
+        Ok(())
+      }
+      #[cfg(feature = "early-buffer")]
+      None if !self.installed_once || self.offline_buffering => {
+        self.early_buffer.push(level, bytes);
+        Ok(())
+      }
+      None => Err(fmt::Error),
+    };
+    self.write_sinks(bytes);
+    result
+  }
+
+  // mirror the given bytes to the registered sinks. Sink failures are ignored so that they cannot affect the protocol
+  // output.
+  fn write_sinks(&self, bytes: &[u8]) {
+    //output is always written in fragments of the formatted str, split only at ASCII line endings.
+    let Ok(str) = core::str::from_utf8(bytes) else { return };
+    for sink in self.sinks.0.iter().flatten() {
+      let _ = sink.lock().write_str(str);
+    }
+  }
+
+  // registers a name for a custom level, replacing any name already registered for it. Returns false if the level is
+  // one of the standard levels, or if MAX_CUSTOM_LEVELS other levels are already registered.
+  fn register_custom_level(&mut self, level: usize, name: &'static str) -> bool {
+    if standard_level_name(level).is_some() {
+      return false;
+    }
+    let slot = self
+      .custom_levels
+      .iter()
+      .position(|entry| entry.is_some_and(|(registered, _)| registered == level))
+      .or_else(|| self.custom_levels.iter().position(Option::is_none));
+    match slot {
+      Some(index) => {
+        self.custom_levels[index] = Some((level, name));
+        true
+      }
+      None => false,
+    }
+  }
+
+  // returns the name of the given level if it is a standard level or a registered custom level.
+  fn level_name(&self, level: usize) -> Option<&'static str> {
+    standard_level_name(level).or_else(|| {
+      self.custom_levels.iter().flatten().find(|(registered, _)| *registered == level).map(|(_, name)| *name)
+    })
+  }
+
+  // registers a sink to mirror output to. Returns Status::OUT_OF_RESOURCES if MAX_LOG_SINKS sinks are already
+  // registered.
+  fn add_sink(&mut self, sink: &'static LogSink) -> Result<(), Status> {
+    let slot = self.sinks.0.iter_mut().find(|slot| slot.is_none()).ok_or(Status::OUT_OF_RESOURCES)?;
+    *slot = Some(sink);
     Ok(())
   }
+
+  // copies the accumulated log out of the AdvancedLogger protocol, if the located protocol supports reading it back.
+  fn read_log_buffer(&self, out: &mut [u8]) -> Result<usize, Status> {
+    let protocol = self.protocol.ok_or(Status::NOT_READY)?;
+    let protocol = unsafe { protocol.as_ref().expect("advanced logger protocol is null") };
+    if protocol.version < ADVANCED_LOGGER_PROTOCOL_READ_VERSION {
+      return Err(Status::UNSUPPORTED);
+    }
+    let readable = unsafe { &*(protocol as *const AdvancedLoggerProtocol as *const AdvancedLoggerReadableProtocol) };
+    let mut size = out.len();
+    match (readable.read_log)(protocol, out.as_mut_ptr(), core::ptr::addr_of_mut!(size)) {
+      Status::SUCCESS => Ok(size),
+      status => Err(status),
+    }
+  }
+
+  // returns the revision reported by the located AdvancedLogger protocol.
+  fn protocol_version(&self) -> Option<u32> {
+    let protocol = self.protocol?;
+    Some(unsafe { protocol.as_ref().expect("advanced logger protocol is null") }.version)
+  }
+
+  // commits output buffered by the AdvancedLogger protocol, if the located protocol supports it.
+  fn flush(&self) {
+    let Some(protocol) = self.protocol else { return };
+    let protocol = unsafe { protocol.as_ref().expect("advanced logger protocol is null") };
+    if protocol.version < ADVANCED_LOGGER_PROTOCOL_FLUSH_VERSION {
+      return;
+    }
+    let flushable = unsafe { &*(protocol as *const AdvancedLoggerProtocol as *const AdvancedLoggerFlushableProtocol) };
+    (flushable.flush)(protocol);
+  }
+
+  // replays output captured in the early buffer to the AdvancedLogger protocol.
+  #[cfg(feature = "early-buffer")]
+  fn flush_early_buffer(&mut self) {
+    let Some(protocol) = self.protocol else { return };
+    let max_chunk = self.max_chunk;
+    self.early_buffer.drain(|level, bytes| write_protocol(protocol, level, bytes, max_chunk));
+  }
 }
 
-// private locked wrapper type to provide thread-safety for AdvancedLogger.
+// table of registered sinks. Sinks are not required to implement Debug, so only the number of sinks is formatted.
+struct LogSinks([Option<&'static LogSink>; MAX_LOG_SINKS]);
+
+impl fmt::Debug for LogSinks {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LogSinks").field("count", &self.0.iter().flatten().count()).finish()
+  }
+}
+
+// write the given bytes to the given AdvancedLogger protocol instance at the given log level, in as many write_log
+// calls as needed so that no single call is passed more than `max_chunk` bytes.
+fn write_protocol(protocol: *mut AdvancedLoggerProtocol, level: usize, bytes: &[u8], max_chunk: usize) {
+  let logger = unsafe { protocol.as_mut().expect("advanced logger protocol is null") };
+  for chunk in bytes.chunks(max_chunk.max(1)) {
+    (logger.write_log)(logger, level, chunk.as_ptr(), chunk.len());
+  }
+}
+
+// marker written in place of the remainder of a log call that exceeds the maximum line length.
+const TRUNCATION_MARKER: &str = "...<truncated>\n";
+
+// size of the stack buffer used to accumulate the output of a log call in line-buffered mode.
+const LINE_BUFFER_SIZE: usize = 256;
+
+// Fixed buffer that accumulates the fragments of a log call in line-buffered mode.
+struct LineBuffer {
+  bytes: [u8; LINE_BUFFER_SIZE],
+  len: usize,
+}
+
+// largest supported wrap width, in columns.
+const MAX_WRAP_WIDTH: usize = 256;
+
+// size of the pieces the held-back word is copied out of the logger state in to be written.
+const WRAP_WORD_CHUNK: usize = 64;
+
+// Word wrapping state of a logger. Spaces and the word being written are held back until the next space or line
+// ending, when it is known whether they fit on the current line. A word is written as soon as it fills a line by
+// itself, so that tokens longer than the width are broken across lines. Held-back output is written at the end of each
+// log call, so only the column carries over between calls.
 #[derive(Debug)]
-struct LockedAdvancedLogger {
-  inner: spin::Mutex<AdvancedLogger>,
+struct WrapState {
+  //columns written on the current line.
+  column: usize,
+  //spaces since the last word, not yet written.
+  spaces: usize,
+  word: [u8; MAX_WRAP_WIDTH * 4],
+  word_len: usize,
+  word_columns: usize,
 }
 
-impl LockedAdvancedLogger {
-  // creates a new LockedAdvancedLogger instance.
+impl WrapState {
   const fn new() -> Self {
-    LockedAdvancedLogger { inner: spin::Mutex::new(AdvancedLogger::new()) }
+    WrapState { column: 0, spaces: 0, word: [0; MAX_WRAP_WIDTH * 4], word_len: 0, word_columns: 0 }
+  }
+}
+
+// Private fmt::Write implementation that writes to the AdvancedLogger at a fixed level. A LevelWriter is created for
+// each log call, so the level, the number of bytes written so far and (in line-buffered mode) the buffered output
+// travel with the write rather than being stored in the shared logger state.
+struct LevelWriter<'a> {
+  logger: &'a mut AdvancedLogger,
+  level: usize,
+  written: usize,
+  truncated: bool,
+  line: Option<LineBuffer>,
+}
+
+impl<'a> LevelWriter<'a> {
+  // creates a writer for a single log call at the given level.
+  fn new(logger: &'a mut AdvancedLogger, level: usize) -> Self {
+    let line = logger.line_buffered.then_some(LineBuffer { bytes: [0; LINE_BUFFER_SIZE], len: 0 });
+    LevelWriter { logger, level, written: 0, truncated: false, line }
+  }
+
+  // completes the log call, writing any output held back for wrapping or in the line buffer.
+  fn finish(&mut self) -> fmt::Result {
+    self.flush_word()?;
+    self.flush_spaces()?;
+    self.flush()
+  }
+
+  // writes the given fragment of the log call, or adds it to the line buffer in line-buffered mode. When the line
+  // buffer fills, it is flushed at a character boundary, so that each write carries whole UTF-8 sequences.
+  fn emit(&mut self, mut s: &str) -> fmt::Result {
+    loop {
+      let Some(buffer) = self.line.as_mut() else { return self.logger.write_at_level(self.level, s.as_bytes()) };
+      //copy as much of the fragment as fits without splitting a character; the remainder is deferred until the buffer
+      //has been flushed.
+      let mut end = s.len().min(LINE_BUFFER_SIZE - buffer.len);
+      while !s.is_char_boundary(end) {
+        end -= 1;
+      }
+      buffer.bytes[buffer.len..buffer.len + end].copy_from_slice(&s.as_bytes()[..end]);
+      buffer.len += end;
+      s = &s[end..];
+      if s.is_empty() {
+        return Ok(());
+      }
+      self.flush()?;
+    }
+  }
+
+  // writes any output held in the line buffer.
+  fn flush(&mut self) -> fmt::Result {
+    match self.line.as_mut() {
+      Some(buffer) if buffer.len > 0 => {
+        let len = core::mem::take(&mut buffer.len);
+        self.logger.write_at_level(self.level, &buffer.bytes[..len])
+      }
+      _ => Ok(()),
+    }
+  }
+
+  // writes the given fragment of the log call, inserting line breaks in place of spaces to keep lines within the wrap
+  // width, if set.
+  fn write_wrapped(&mut self, s: &str) -> fmt::Result {
+    let width = self.logger.wrap_width;
+    if width == 0 {
+      return self.emit(s);
+    }
+    for c in s.chars() {
+      match c {
+        '\n' | '\r' => {
+          self.flush_word()?;
+          self.flush_spaces()?;
+          self.emit(c.encode_utf8(&mut [0; 4]))?;
+          self.logger.wrap.column = 0;
+        }
+        ' ' => {
+          self.flush_word()?;
+          self.logger.wrap.spaces += 1;
+        }
+        _ => {
+          let wrap = &mut self.logger.wrap;
+          let len = c.encode_utf8(&mut wrap.word[wrap.word_len..]).len();
+          wrap.word_len += len;
+          wrap.word_columns += 1;
+          if wrap.word_columns == width {
+            self.flush_word()?;
+          }
+        }
+      }
+    }
+    Ok(())
   }
 
-  // initializes an advanced logger instance. Typically only called once, but if called more than once will re-init
-  // logger by re-acquiring a pointer to the advanced logger protocol.
-  fn init(&self, bs: *mut BootServices) {
-    self.inner.lock().init(bs);
+  // writes the word held back for wrapping, preceded by the spaces before it if both fit on the current line, and
+  // otherwise by a line break in place of the spaces.
+  fn flush_word(&mut self) -> fmt::Result {
+    let width = self.logger.wrap_width;
+    let wrap = &mut self.logger.wrap;
+    if width == 0 || wrap.word_len == 0 {
+      return Ok(());
+    }
+    let wrapped = wrap.column + wrap.spaces + wrap.word_columns > width;
+    let spaces = if wrapped { 0 } else { wrap.spaces };
+    wrap.spaces = 0;
+    let line_break = wrapped && wrap.column > 0;
+    wrap.column = if wrapped { 0 } else { wrap.column + spaces } + core::mem::take(&mut wrap.word_columns);
+    let len = core::mem::take(&mut wrap.word_len);
+    if line_break {
+      self.emit("\n")?;
+    }
+    for _ in 0..spaces {
+      self.emit(" ")?;
+    }
+    //the word stays in the logger state while it is written, so it is copied out in small pieces, each ending at a
+    //character boundary.
+    let mut start = 0;
+    while start < len {
+      let mut chunk = [0u8; WRAP_WORD_CHUNK];
+      let word = core::str::from_utf8(&self.logger.wrap.word[start..len]).map_err(|_| fmt::Error)?;
+      let mut end = word.len().min(WRAP_WORD_CHUNK);
+      while !word.is_char_boundary(end) {
+        end -= 1;
+      }
+      chunk[..end].copy_from_slice(&word.as_bytes()[..end]);
+      start += end;
+      self.emit(core::str::from_utf8(&chunk[..end]).map_err(|_| fmt::Error)?)?;
+    }
+    Ok(())
+  }
+
+  // writes the spaces held back for wrapping if they fit on the current line. Otherwise they are dropped, as they would
+  // be at a line break.
+  fn flush_spaces(&mut self) -> fmt::Result {
+    let width = self.logger.wrap_width;
+    let wrap = &mut self.logger.wrap;
+    let spaces = core::mem::take(&mut wrap.spaces);
+    if width == 0 || wrap.column + spaces > width {
+      return Ok(());
+    }
+    wrap.column += spaces;
+    for _ in 0..spaces {
+      self.emit(" ")?;
+    }
+    Ok(())
+  }
+
+  // writes the prefixes enabled on the logger (severity, sequence number, timestamp, name, level name) that precede the
+  // output of each log call. Called exactly once per log call, so the sequence number advances once per call.
+  fn write_prefix(&mut self) -> fmt::Result {
+    if self.logger.severity_prefix {
+      let level = self.level;
+      let severity =
+        self.logger.severity_map.iter().find(|(mask, _)| level & mask != 0).map_or(7, |(_, severity)| *severity);
+      write!(self, "<{}>", severity)?;
+    }
+    if self.logger.sequence_numbers {
+      let width = self.logger.sequence_width;
+      let sequence = self.logger.next_sequence % 10u64.pow(width as u32);
+      self.logger.next_sequence = self.logger.next_sequence.wrapping_add(1);
+      write!(self, "#{:0width$} ", sequence, width = width)?;
+    }
+    if let Some(timestamp) = self.logger.timestamp_source {
+      write!(self, "[{:}] ", timestamp())?;
+    }
+    if let Some(name) = self.logger.name {
+      write!(self, "{:}: ", name)?;
+    }
+    if self.logger.level_prefix {
+      let level = self.level;
+      match self.logger.level_name(level) {
+        Some(name) => write!(self, "[{:}] ", name)?,
+        None => write!(self, "[{:#x}] ", level)?,
+      }
+    }
+    Ok(())
   }
+}
 
-  // Log the debug output in `args` at the given log level.
-  fn log(&self, level: usize, args: fmt::Arguments) {
-    self.inner.lock().log(level, args)
+impl fmt::Write for LevelWriter<'_> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    if self.truncated {
+      return Ok(());
+    }
+    let remaining = self.logger.max_line_len.saturating_sub(self.written);
+    if s.len() <= remaining {
+      self.written += s.len();
+      return self.write_wrapped(s);
+    }
+    //the log call has exceeded the maximum line length: write what fits (without splitting a character), then the
+    //marker in place of the rest of the output.
+    let mut end = remaining;
+    while !s.is_char_boundary(end) {
+      end -= 1;
+    }
+    self.truncated = true;
+    self.written += end;
+    self.write_wrapped(&s[..end])?;
+    self.write_wrapped(TRUNCATION_MARKER)
   }
 }
 
-unsafe impl Sync for LockedAdvancedLogger {}
-unsafe impl Send for LockedAdvancedLogger {}
+// returns the name of the given level if it is one of the standard levels.
+fn standard_level_name(level: usize) -> Option<&'static str> {
+  STANDARD_LEVELS.iter().find(|(standard, _)| *standard == level).map(|(_, name)| *name)
+}
+
+// the standard levels defined by this crate and their names, in ascending order of level bit.
+const STANDARD_LEVELS: [(usize, &str); 5] = [
+  (DEBUG_INIT, "INIT"),
+  (DEBUG_WARN, "WARN"),
+  (DEBUG_INFO, "INFO"),
+  (DEBUG_VERBOSE, "VERBOSE"),
+  (DEBUG_ERROR, "ERROR"),
+];
 
-/// Initializes the logging subsystem. The `debug` and `debugln` macros may be called before calling this function, but
-/// output is discarded if the logger has not yet been initialized via this routine.
-pub fn init_debug(bs: *mut BootServices) {
-  LOGGER.init(bs);
+// number of bytes displayed on each line of a hexdump.
+const HEXDUMP_BYTES_PER_LINE: usize = 16;
+
+// Private Display implementation that formats a single line of a hexdump: the address of the first byte, up to
+// HEXDUMP_BYTES_PER_LINE bytes in hex, and an ASCII gutter with non-printable bytes shown as '.'.
+struct HexdumpLine<'a> {
+  address: usize,
+  bytes: &'a [u8],
 }
 
-#[doc(hidden)]
-pub fn _log(level: usize, args: fmt::Arguments) {
-  LOGGER.log(level, args)
+impl fmt::Display for HexdumpLine<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:08x} ", self.address)?;
+    for index in 0..HEXDUMP_BYTES_PER_LINE {
+      if index == HEXDUMP_BYTES_PER_LINE / 2 {
+        f.write_str(" ")?;
+      }
+      match self.bytes.get(index) {
+        Some(byte) => write!(f, " {:02x}", byte)?,
+        None => f.write_str("   ")?,
+      }
+    }
+    f.write_str("  |")?;
+    for byte in self.bytes {
+      f.write_char(if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })?;
+    }
+    f.write_str("|")
+  }
 }
 
-/// Prints to the AdvancedLogger log at the specified level.
-///
-/// This macro uses the same syntax as rust std [`std::println!`] macro, with the addition of a level argument that
-/// indicates what debug level the output is to be written at.
-///
-/// See [`std::fmt`] for details on format strings.
-///
-/// ```no_run
-/// use rust_advanced_logger_dxe::{init_debug, debug, DEBUG_INFO};
-/// use r_efi::efi::Status;
-/// pub extern "efiapi" fn efi_main(
-///    _image_handle: *const core::ffi::c_void,
-///    _system_table: *const r_efi::system::SystemTable,
-///  ) -> u64 {
-///
-///    //Initialize debug logging - no output without this.
-///    init_debug(unsafe { (*_system_table).boot_services});
-///
-///    debug!(DEBUG_INFO, "Hello, World. This is {:} in {:}. ", "rust", "UEFI");
-///    debug!(DEBUG_INFO, "Better add our own newline.\n");
-///
-///    Status::SUCCESS.as_usize() as u64
-/// }
-/// ```
-#[macro_export]
-macro_rules! debug {
-    ($level:expr, $($arg:tt)*) => {
-        $crate::_log($level, format_args!($($arg)*))
+// Private Display implementation that renders bytes as ASCII, with non-printable bytes shown as `\xNN` and
+// backslashes doubled so that the rendering is unambiguous. Runs of printable bytes are written with a single write.
+struct EscapedBytes<'a>(&'a [u8]);
+
+impl fmt::Display for EscapedBytes<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    // the runs between escaped bytes only contain printable ASCII, so they are valid UTF-8.
+    let run = |bytes| unsafe { core::str::from_utf8_unchecked(bytes) };
+    let mut start = 0;
+    for (index, byte) in self.0.iter().enumerate() {
+      if (byte.is_ascii_graphic() || *byte == b' ') && *byte != b'\\' {
+        continue;
+      }
+      f.write_str(run(&self.0[start..index]))?;
+      match byte {
+        b'\\' => f.write_str("\\\\")?,
+        _ => write!(f, "\\x{:02x}", byte)?,
+      }
+      start = index + 1;
     }
+    f.write_str(run(&self.0[start..]))
+  }
 }
 
-/// Prints to the AdvancedLogger log at the specified level with a newline.
+/// A logger instance with its own configuration, using the lock `R` to synchronize access to it.
 ///
-/// Equivalent to the [`debug!`] macro except that a newline is appended to the format string.
+/// The logging routines and macros of this crate use a global instance. Drivers with several subsystems may create
+/// further instances (e.g. one per subsystem), each with its own level filter, default level and prefixes, and log to
+/// them with [`debug_to!`] and [`debugln_to!`]. An instance is usually initialized with [`Self::init_shared()`] to
+/// write to the AdvancedLogger protocol located by the global instance.
 ///
+/// ## Example
 /// ```no_run
-/// use rust_advanced_logger_dxe::{init_debug, debugln, DEBUG_INFO};
-/// use r_efi::efi::Status;
-/// pub extern "efiapi" fn efi_main(
-///    _image_handle: *const core::ffi::c_void,
-///    _system_table: *const r_efi::system::SystemTable,
-///  ) -> u64 {
+/// use rust_advanced_logger_dxe::{debugln_to, init_debug, LockedAdvancedLogger, DEBUG_ERROR, DEBUG_INFO};
 ///
-///    //Initialize debug logging - no output without this.
-///    init_debug(unsafe { (*_system_table).boot_services});
+/// static NETWORK_LOG: LockedAdvancedLogger = LockedAdvancedLogger::named("net");
 ///
-///    debugln!(DEBUG_INFO, "Hello, World. This is {:} in {:}.", "rust", "UEFI");
+/// fn network_init(bs: *mut r_efi::system::BootServices) {
+///   let _ = init_debug(bs);
+///   let _ = NETWORK_LOG.init_shared();
+///   NETWORK_LOG.set_log_level_filter(DEBUG_ERROR);
 ///
-///    Status::SUCCESS.as_usize() as u64
+///   debugln_to!(NETWORK_LOG, DEBUG_INFO, "filtered out");
+///   debugln_to!(NETWORK_LOG, DEBUG_ERROR, "link down");
+///   // net: link down
 /// }
 /// ```
-#[macro_export]
-macro_rules! debugln {
-    ($level:expr) => ($crate::debug!($level, "\n"));
-    ($level:expr, $fmt:expr) => ($crate::debug!($level, concat!($fmt, "\n")));
-    ($level:expr, $fmt:expr, $($arg:tt)*) => ($crate::debug!($level, concat!($fmt, "\n"), $($arg)*));
+#[derive(Debug)]
+pub struct LockedAdvancedLogger<R: RawMutex = spin::Mutex<()>> {
+  inner: lock::Mutex<R, AdvancedLogger>,
+  // count of non-blocking log calls that were dropped because the lock was held. Kept outside the lock so that it can
+  // be updated when the lock is unavailable.
+  try_log_dropped: AtomicUsize,
+  // copy of the level filter, kept outside the lock so that filtered calls can be discarded without acquiring it. It is
+  // only updated with the lock held, alongside the filter in AdvancedLogger.
+  filter: AtomicUsize,
+  // level used by the debug_default! and debugln_default! macros.
+  default_level: AtomicUsize,
 }
 
-#[cfg(test)]
-mod tests {
-  extern crate std;
-  use crate::{
-    init_debug, AdvancedLoggerProtocol, LockedAdvancedLogger, ADVANCED_LOGGER_PROTOCOL_GUID, DEBUG_ERROR, DEBUG_INFO,
-    DEBUG_INIT, DEBUG_VERBOSE, DEBUG_WARN, LOGGER,
-  };
-  use core::{ffi::c_void, mem::MaybeUninit, slice::from_raw_parts};
-  use r_efi::{
-    efi::{Guid, Status},
-    system::BootServices,
-  };
-  use std::{println, str};
-
-  static ADVANCED_LOGGER_INSTANCE: AdvancedLoggerProtocol =
-    AdvancedLoggerProtocol { signature: 0, version: 0, write_log: mock_advanced_logger_write };
+impl<R: RawMutex> LockedAdvancedLogger<R> {
+  /// Creates a new, uninitialized logger instance. const fn to allow static initialization.
+  pub const fn new() -> Self {
+    LockedAdvancedLogger {
+      inner: lock::Mutex::new(AdvancedLogger::new()),
+      try_log_dropped: AtomicUsize::new(0),
+      filter: AtomicUsize::new(usize::MAX),
+      default_level: AtomicUsize::new(DEBUG_INFO),
+    }
+  }
 
-  extern "efiapi" fn mock_advanced_logger_write(
-    this: *const AdvancedLoggerProtocol,
-    error_level: usize,
-    buffer: *const u8,
-    buffer_size: usize,
-  ) {
-    assert_eq!(this, &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol);
-    //to avoid dealing with complicated mock state, we assume that tests will produce the same output string:
-    //"This is a <x> test.\n", where <x> depends on the debug level (e.g. "DEBUG_INFO"). In addition,
-    //the string might be built with multiple calls to write, so we just check that it is a substring
-    //of what we expect.
-    let buf: &[u8] = unsafe { from_raw_parts(buffer, buffer_size) };
-    let str = str::from_utf8(buf).unwrap();
-    println!("buffer {buffer:?}:{buffer_size:?}, str: {str:?}");
-    match error_level {
-      DEBUG_INIT => assert!("This is a DEBUG_INIT test.\n".contains(str)),
-      DEBUG_WARN => assert!("This is a DEBUG_WARN test.\n".contains(str)),
-      DEBUG_INFO => assert!("This is a DEBUG_INFO test.\n".contains(str)),
-      DEBUG_VERBOSE => assert!("This is a DEBUG_VERBOSE test.\n".contains(str)),
-      DEBUG_ERROR => assert!("This is a DEBUG_ERROR test.\n".contains(str)),
-      _ => panic!("Unrecognized error string."),
+  /// Creates a new, uninitialized logger instance whose output is prefixed with `name`, followed by `": "`.
+  ///
+  /// The name follows any severity, sequence number or timestamp prefix, and precedes the level name prefix.
+  pub const fn named(name: &'static str) -> Self {
+    let mut logger = AdvancedLogger::new();
+    logger.name = Some(name);
+    LockedAdvancedLogger {
+      inner: lock::Mutex::new(logger),
+      try_log_dropped: AtomicUsize::new(0),
+      filter: AtomicUsize::new(usize::MAX),
+      default_level: AtomicUsize::new(DEBUG_INFO),
     }
   }
 
-  extern "efiapi" fn mock_locate_protocol(
-    protocol: *mut Guid,
-    _registration: *mut c_void,
-    interface: *mut *mut c_void,
-  ) -> Status {
-    let protocol = unsafe { protocol.as_mut().unwrap() };
-    assert_eq!(protocol, &ADVANCED_LOGGER_PROTOCOL_GUID);
-    assert!(!interface.is_null());
-    unsafe {
-      interface.write(&ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol as *mut c_void);
+  /// Initializes the logger instance by locating the AdvancedLogger protocol, as for [`init_debug`].
+  ///
+  /// Returns `Status::ALREADY_STARTED` without changing anything if the instance is already initialized.
+  pub fn init(&self, bs: *mut BootServices) -> Result<(), Status> {
+    if DISABLED {
+      return Ok(());
     }
-    Status::SUCCESS
+    let mut logger = self.inner.lock();
+    if logger.protocol.is_some() {
+      return Err(Status::ALREADY_STARTED);
+    }
+    logger.init(bs)
   }
 
-  fn mock_boot_services() -> BootServices {
-    let boot_services = MaybeUninit::zeroed();
-    let mut boot_services: BootServices = unsafe { boot_services.assume_init() };
-    boot_services.locate_protocol = mock_locate_protocol;
-    boot_services
+  /// Initializes the logger instance to write to the AdvancedLogger protocol located by the global logger (see
+  /// [`init_debug`]), so that the protocol is only located once.
+  ///
+  /// Returns `Status::NOT_READY` if the global logger is not initialized, or `Status::ALREADY_STARTED` without changing
+  /// anything if the instance is already initialized. The instance keeps the protocol if the global logger is
+  /// deinitialized; drivers that may run until ExitBootServices should also call
+  /// [`Self::register_exit_boot_services_handler()`] for the instance.
+  pub fn init_shared(&self) -> Result<(), Status> {
+    if DISABLED {
+      return Ok(());
+    }
+    //the global logger's lock is released before this instance's is acquired, since they may be the same instance.
+    let protocol = LOGGER.inner.lock().protocol.ok_or(Status::NOT_READY)?;
+    let mut logger = self.inner.lock();
+    if logger.protocol.is_some() {
+      return Err(Status::ALREADY_STARTED);
+    }
+    logger.install_protocol(protocol);
+    Ok(())
   }
 
-  #[test]
-  fn init_should_initialize_logger() {
-    let mut boot_services = mock_boot_services();
-    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
-    TEST_LOGGER.init(&mut boot_services);
+  // initializes an advanced logger instance, making up to `attempts` attempts (at least one) to locate the protocol and
+  // calling `between` (without the lock held) between them. Only failures to locate the protocol are retried.
+  fn init_retry(&self, bs: *mut BootServices, attempts: u32, between: fn()) -> Result<(), Status> {
+    let mut result = self.init(bs);
+    for _ in 1..attempts {
+      match result {
+        Ok(()) | Err(Status::ALREADY_STARTED) | Err(Status::INVALID_PARAMETER) => break,
+        Err(_) => {
+          between();
+          result = self.init(bs);
+        }
+      }
+    }
+    result
+  }
 
-    assert_eq!(
-      TEST_LOGGER.inner.lock().protocol.unwrap() as *const AdvancedLoggerProtocol,
-      &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol
-    );
-    assert_eq!(TEST_LOGGER.inner.lock().level, DEBUG_INFO);
+  // re-initializes an advanced logger instance by re-acquiring a pointer to the advanced logger protocol, whether or
+  // not it is already initialized.
+  fn reinit(&self, bs: *mut BootServices) -> Result<(), Status> {
+    self.inner.lock().init(bs)
   }
 
-  #[test]
-  fn debug_macro_should_log_things() {
-    let mut boot_services = mock_boot_services();
-    init_debug(&mut boot_services);
+  // discards the protocol pointer and restores all options and counters to their defaults. The ExitBootServices event
+  // (if any) is kept, since it cannot be closed without boot services and remains harmless, as is the instance name.
+  fn deinit(&self) {
+    let mut logger = self.inner.lock();
+    let (exit_boot_services_event, name) = (logger.exit_boot_services_event, logger.name);
+    *logger = AdvancedLogger::new();
+    logger.exit_boot_services_event = exit_boot_services_event;
+    logger.name = name;
+    self.try_log_dropped.store(0, Ordering::Relaxed);
+    self.filter.store(logger.filter, Ordering::Relaxed);
+    self.default_level.store(DEBUG_INFO, Ordering::Relaxed);
+  }
 
+  // initializes an advanced logger instance with an already-located protocol instance, after checking its signature
+  // and version. Returns Status::ALREADY_STARTED if the logger is already initialized.
+  fn init_with_protocol(&self, protocol: *mut AdvancedLoggerProtocol) -> Result<(), Status> {
+    let mut logger = self.inner.lock();
+    if logger.protocol.is_some() {
+      return Err(Status::ALREADY_STARTED);
+    }
+    let instance = unsafe { protocol.as_ref() }.ok_or(Status::INVALID_PARAMETER)?;
+    if instance.signature != ADVANCED_LOGGER_PROTOCOL_SIGNATURE {
+      return Err(Status::INVALID_PARAMETER);
+    }
+    if instance.version < ADVANCED_LOGGER_PROTOCOL_VERSION {
+      return Err(Status::INCOMPATIBLE_VERSION);
+    }
+    logger.install_protocol(protocol);
+    Ok(())
+  }
+
+  // initializes an advanced logger instance using the protocol published under the given GUID. The GUID is retained
+  // and used for any subsequent re-init. Returns Status::ALREADY_STARTED if the logger is already initialized.
+  fn init_with_guid(&self, bs: *mut BootServices, guid: Guid) -> Result<(), Status> {
+    let mut logger = self.inner.lock();
+    if logger.protocol.is_some() {
+      return Err(Status::ALREADY_STARTED);
+    }
+    logger.protocol_guid = Some(guid);
+    logger.init(bs)
+  }
+
+  /// Registers an ExitBootServices event that stops the logger instance from using the AdvancedLogger protocol once
+  /// boot services have exited, as for [`register_exit_boot_services_handler`].
+  ///
+  /// Only one event is created per logger instance; subsequent calls succeed without creating another.
+  pub fn register_exit_boot_services_handler(&'static self, bs: *mut BootServices) -> Result<(), Status> {
+    if DISABLED {
+      return Ok(());
+    }
+    self.create_exit_boot_services_event(bs)
+  }
+
+  // creates the EVT_SIGNAL_EXIT_BOOT_SERVICES event that discards the protocol pointer, unless already created.
+  fn create_exit_boot_services_event(&'static self, bs: *mut BootServices) -> Result<(), Status> {
+    let mut logger = self.inner.lock();
+    if logger.exit_boot_services_event.is_some() {
+      return Ok(());
+    }
+    let boot_services = unsafe { bs.as_mut() }.ok_or(Status::INVALID_PARAMETER)?;
+    let mut event: Event = core::ptr::null_mut();
+    let status = (boot_services.create_event)(
+      EVT_SIGNAL_EXIT_BOOT_SERVICES,
+      TPL_NOTIFY,
+      Some(exit_boot_services_notify::<R>),
+      self as *const Self as *mut c_void,
+      core::ptr::addr_of_mut!(event),
+    );
+    match status {
+      Status::SUCCESS => {
+        logger.exit_boot_services_event = Some(event);
+        Ok(())
+      }
+      _ => Err(status),
+    }
+  }
+
+  // copies the accumulated log out of the AdvancedLogger protocol into `out`.
+  fn read_log_buffer(&self, out: &mut [u8]) -> Result<usize, Status> {
+    self.inner.lock().read_log_buffer(out)
+  }
+
+  // commits output buffered by the AdvancedLogger protocol.
+  fn flush(&self) {
+    self.inner.lock().flush();
+  }
+
+  /// Returns true if the logger instance has been initialized with the AdvancedLogger protocol.
+  pub fn is_initialized(&self) -> bool {
+    self.inner.lock().protocol.is_some()
+  }
+
+  // returns the revision reported by the located AdvancedLogger protocol, if initialized.
+  fn protocol_version(&self) -> Option<u32> {
+    self.inner.lock().protocol_version()
+  }
+
+  /// Logs the debug output in `args` at the specified level. Usually called through [`debug_to!`] or [`debugln_to!`].
+  pub fn log(&self, level: usize, args: fmt::Arguments) {
+    if !DISABLED && self.level_enabled(level) {
+      self.inner.lock().log(level, args)
+    }
+  }
+
+  // Log the debug output in `args` at the given log level, returning the reason if it could not be written.
+  fn log_checked(&self, level: usize, args: fmt::Arguments) -> Result<(), LogError> {
+    if !self.level_enabled(level) {
+      return Err(LogError::Filtered);
+    }
+    self.inner.lock().log_checked(level, args)
+  }
+
+  // Log the string `s` at the given log level without formatting it.
+  fn log_str(&self, level: usize, s: &str) {
+    if self.level_enabled(level) {
+      self.inner.lock().log_str(level, s)
+    }
+  }
+
+  // Log the pre-formatted bytes at the given log level.
+  fn write_bytes(&self, level: usize, bytes: &[u8]) {
+    if self.level_enabled(level) {
+      self.inner.lock().write_bytes(level, bytes)
+    }
+  }
+
+  // Log the debug output in `args` at the given log level if the lock can be acquired without waiting. Otherwise the
+  // output is dropped and counted.
+  fn try_log(&self, level: usize, args: fmt::Arguments) {
+    if !self.level_enabled(level) {
+      return;
+    }
+    match self.inner.try_lock() {
+      Some(mut logger) => logger.log(level, args),
+      None => {
+        self.try_log_dropped.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+  }
+
+  // returns the number of non-blocking log calls that were dropped because the lock was held.
+  fn try_log_dropped(&self) -> usize {
+    self.try_log_dropped.load(Ordering::Relaxed)
+  }
+
+  // returns the number of log calls that were discarded because there was no protocol to write to.
+  fn dropped_messages(&self) -> u64 {
+    self.inner.lock().dropped_messages
+  }
+
+  /// Sets the mask of debug levels that the logger instance writes to the log, as for [`set_log_level_filter`].
+  pub fn set_log_level_filter(&self, mask: usize) {
+    let mut logger = self.inner.lock();
+    logger.filter = mask;
+    self.filter.store(mask, Ordering::Relaxed);
+  }
+
+  /// Returns the mask of debug levels that the logger instance writes to the log.
+  pub fn log_level_filter(&self) -> usize {
+    self.inner.lock().filter
+  }
+
+  /// Sets the default level of the logger instance, returned by [`Self::default_level()`]. Defaults to [`DEBUG_INFO`].
+  pub fn set_default_level(&self, level: usize) {
+    self.default_level.store(level, Ordering::Relaxed);
+  }
+
+  /// Returns the default level of the logger instance, e.g. for `debugln_to!(LOG, LOG.default_level(), ...)`.
+  pub fn default_level(&self) -> usize {
+    self.default_level.load(Ordering::Relaxed)
+  }
+
+  /// Returns true if output at the specified level is written to the log by the logger instance. Does not wait for
+  /// the logger if it is in use.
+  pub fn level_enabled(&self, level: usize) -> bool {
+    level & self.filter.load(Ordering::Relaxed) != 0
+  }
+
+  // returns the number of early buffer records that were dropped because the buffer was full.
+  #[cfg(feature = "early-buffer")]
+  fn early_buffer_dropped(&self) -> usize {
+    self.inner.lock().early_buffer.dropped()
+  }
+
+  // enables or disables holding output in the early buffer while the protocol is unavailable after initialization.
+  #[cfg(feature = "early-buffer")]
+  fn set_offline_buffering(&self, enabled: bool) {
+    self.inner.lock().offline_buffering = enabled;
+  }
+
+  // logs the panic message and location at DEBUG_ERROR. The lock is only tried rather than waited on, since the panic
+  // may have occurred while the lock was held; the output is discarded in that case.
+  #[cfg(feature = "panic")]
+  fn log_panic(&self, info: &core::panic::PanicInfo) {
+    if let Some(mut logger) = self.inner.try_lock() {
+      logger.log(DEBUG_ERROR, format_args!("{:}\n", info));
+    }
+  }
+
+  // registers a sink that mirrors the output of this logger.
+  fn add_sink(&self, sink: &'static LogSink) -> Result<(), Status> {
+    self.inner.lock().add_sink(sink)
+  }
+
+  // registers a name for a custom level.
+  fn register_custom_level(&self, level: usize, name: &'static str) -> bool {
+    self.inner.lock().register_custom_level(level, name)
+  }
+
+  // returns the name of the given level if it is a standard level or a registered custom level.
+  fn level_name(&self, level: usize) -> Option<&'static str> {
+    self.inner.lock().level_name(level)
+  }
+
+  // returns the standard levels followed by a snapshot of the registered custom levels.
+  fn levels(&self) -> impl Iterator<Item = (usize, &'static str)> {
+    let custom_levels = self.inner.lock().custom_levels;
+    STANDARD_LEVELS.into_iter().chain(custom_levels.into_iter().flatten())
+  }
+
+  /// Enables or disables the level name prefix on the output of the logger instance, as for [`set_level_prefix`].
+  pub fn set_level_prefix(&self, enabled: bool) {
+    self.inner.lock().level_prefix = enabled;
+  }
+
+  // enables or disables the sequence number prefix on each log call.
+  fn set_sequence_numbers(&self, enabled: bool) {
+    self.inner.lock().sequence_numbers = enabled;
+  }
+
+  // sets the number of digits of the sequence number prefix.
+  fn set_sequence_width(&self, width: usize) {
+    self.inner.lock().sequence_width = width.clamp(1, MAX_SEQUENCE_WIDTH);
+  }
+
+  // enables or disables the syslog severity prefix on each log call.
+  fn set_severity_prefix(&self, enabled: bool) {
+    self.inner.lock().severity_prefix = enabled;
+  }
+
+  // sets the mapping from levels to syslog severities used by the severity prefix.
+  fn set_severity_map(&self, map: &'static [(usize, u8)]) {
+    self.inner.lock().severity_map = map;
+  }
+
+  // enables or disables rewriting of lone '\n' line endings to "\r\n".
+  fn set_crlf(&self, enabled: bool) {
+    let mut logger = self.inner.lock();
+    logger.crlf = enabled;
+    logger.last_write_ended_with_cr = false;
+  }
+
+  // enables or disables accumulating the output of each log call into a single write.
+  fn set_line_buffered(&self, enabled: bool) {
+    self.inner.lock().line_buffered = enabled;
+  }
+
+  // sets the maximum number of bytes written by a single log call.
+  fn set_max_line_len(&self, max_line_len: usize) {
+    self.inner.lock().max_line_len = max_line_len;
+  }
+
+  // sets the number of columns output lines are wrapped at, or 0 to disable wrapping.
+  fn set_wrap_width(&self, columns: usize) {
+    let mut logger = self.inner.lock();
+    logger.wrap_width = columns.min(MAX_WRAP_WIDTH);
+    logger.wrap.column = 0;
+  }
+
+  // sets the maximum number of bytes passed to a single write_log call.
+  fn set_max_chunk(&self, max_chunk: usize) {
+    self.inner.lock().max_chunk = max_chunk;
+  }
+
+  // sets the routine used to timestamp each log call.
+  fn set_timestamp_source(&self, timestamp_source: fn() -> u64) {
+    self.inner.lock().timestamp_source = Some(timestamp_source);
+  }
+
+  // reads the timestamp source, if set. The source is called without the lock held.
+  fn timestamp(&self) -> Option<u64> {
+    let timestamp_source = self.inner.lock().timestamp_source;
+    timestamp_source.map(|timestamp| timestamp())
+  }
+
+  /// Starts a span at the specified level on the logger instance, as for [`span!`].
+  pub fn span<'a>(&'a self, level: usize, name: &'a str) -> Span<'a, R> {
+    let active = !DISABLED && self.level_enabled(level);
+    let mut start = None;
+    if active {
+      self.log(level, format_args!("{:}: start\n", name));
+      start = self.timestamp();
+    }
+    Span { logger: self, level, name, start, active }
+  }
+
+  // logs `data` as a hexdump at the given log level, one log call per line. Addresses are displayed relative to `base`.
+  fn hexdump(&self, level: usize, base: usize, data: &[u8]) {
+    if !self.level_enabled(level) {
+      return;
+    }
+    for (index, bytes) in data.chunks(HEXDUMP_BYTES_PER_LINE).enumerate() {
+      let line = HexdumpLine { address: base.wrapping_add(index * HEXDUMP_BYTES_PER_LINE), bytes };
+      self.log(level, format_args!("{:}\n", line));
+    }
+  }
+}
+
+// ExitBootServices notification for a LockedAdvancedLogger (passed as the event context). The AdvancedLogger protocol
+// is a boot services protocol, so the pointer to it must not be used once boot services have exited.
+extern "efiapi" fn exit_boot_services_notify<R: RawMutex>(_event: Event, context: *mut c_void) {
+  if let Some(logger) = unsafe { (context as *const LockedAdvancedLogger<R>).as_ref() } {
+    logger.inner.lock().protocol = None;
+  }
+}
+
+unsafe impl<R: RawMutex> Sync for LockedAdvancedLogger<R> {}
+unsafe impl<R: RawMutex> Send for LockedAdvancedLogger<R> {}
+
+/// Initializes the logging subsystem. The `debug` and `debugln` macros may be called before calling this function, but
+/// output is discarded if the logger has not yet been initialized via this routine. If any output was discarded, a
+/// single `DEBUG_WARN` line reporting the number of discarded messages is logged once the logger is first initialized.
+///
+/// Returns the status from `locate_protocol` as an error if the AdvancedLogger protocol could not be located, or
+/// `Status::INVALID_PARAMETER` if `bs` is null; in that case the logger remains uninitialized and output continues to
+/// be discarded. Returns `Status::ALREADY_STARTED` without changing anything if the logger is already initialized; see
+/// [`reinit_debug`] to deliberately re-initialize.
+pub fn init_debug(bs: *mut BootServices) -> Result<(), Status> {
+  if DISABLED {
+    return Ok(());
+  }
+  LOGGER.init(bs)
+}
+
+/// Initializes the logging subsystem as for [`init_debug`], retrying if the AdvancedLogger protocol cannot be located
+/// yet, e.g. on platforms where it is installed shortly after the caller starts.
+///
+/// `locate_protocol` is attempted up to `attempts` times (at least once), and `between` is called between attempts,
+/// e.g. to dispatch pending events or stall. Returns the result of the last attempt. `Status::ALREADY_STARTED` and
+/// `Status::INVALID_PARAMETER` (for a null `bs`) are returned without retrying.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::init_debug_retry;
+///
+/// fn stall() {
+///   //e.g. wait for the platform to install the protocol.
+/// }
+///
+/// pub extern "efiapi" fn efi_main(
+///   _image_handle: *const core::ffi::c_void,
+///   system_table: *const r_efi::system::SystemTable,
+/// ) -> u64 {
+///   let _ = init_debug_retry(unsafe { (*system_table).boot_services }, 3, stall);
+///   r_efi::efi::Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+pub fn init_debug_retry(bs: *mut BootServices, attempts: u32, between: fn()) -> Result<(), Status> {
+  if DISABLED {
+    return Ok(());
+  }
+  LOGGER.init_retry(bs, attempts, between)
+}
+
+/// Re-initializes the logging subsystem by re-acquiring the AdvancedLogger protocol, whether or not it is already
+/// initialized.
+///
+/// Returns the status from `locate_protocol` as an error if the AdvancedLogger protocol could not be located, or
+/// `Status::INVALID_PARAMETER` if `bs` is null; in that case the logger is left uninitialized.
+pub fn reinit_debug(bs: *mut BootServices) -> Result<(), Status> {
+  if DISABLED {
+    return Ok(());
+  }
+  LOGGER.reinit(bs)
+}
+
+/// Initializes the logging subsystem with an already-located AdvancedLogger protocol interface, without using boot
+/// services.
+///
+/// This supports flows where the protocol pointer is already known, or where `locate_protocol` cannot be called.
+/// The interface is checked before it is accepted; returns:
+/// - `Status::INVALID_PARAMETER` if `ptr` is null or does not carry the AdvancedLogger protocol signature.
+/// - `Status::INCOMPATIBLE_VERSION` if the interface reports a protocol version older than this crate supports.
+/// - `Status::ALREADY_STARTED` without changing anything if the logger is already initialized.
+///
+/// A null `ptr` is rejected, but otherwise it must point to a valid AdvancedLogger protocol interface that remains
+/// valid for as long as the logger is in use.
+pub fn init_debug_with_protocol(ptr: *mut c_void) -> Result<(), Status> {
+  if DISABLED {
+    return Ok(());
+  }
+  LOGGER.init_with_protocol(ptr as *mut AdvancedLoggerProtocol)
+}
+
+/// Tears down the logging subsystem, e.g. before a driver unloads or between host tests.
+///
+/// The cached AdvancedLogger protocol pointer is discarded, so output is discarded until the logger is initialized
+/// again, and all options (level filter, prefixes, sinks, custom levels, protocol GUID, etc.) and counters are restored
+/// to their defaults so that a subsequent [`init_debug`] starts fresh. An ExitBootServices handler registered with
+/// [`register_exit_boot_services_handler`] remains registered.
+pub fn deinit_debug() {
+  LOGGER.deinit()
+}
+
+/// Initializes the logging subsystem using an AdvancedLogger protocol instance published under `guid` instead of the
+/// standard AdvancedLogger protocol GUID.
+///
+/// This supports platforms that publish the AdvancedLogger interface under a different GUID. The GUID is retained, so
+/// a later call to [`reinit_debug`] re-initializes the logger against the same GUID. Returns the status from
+/// `locate_protocol` as an error if the protocol could not be located, or `Status::ALREADY_STARTED` if the logger is
+/// already initialized.
+pub fn init_debug_with_guid(bs: *mut BootServices, guid: Guid) -> Result<(), Status> {
+  if DISABLED {
+    return Ok(());
+  }
+  LOGGER.init_with_guid(bs, guid)
+}
+
+/// Registers an ExitBootServices handler that stops the logger from using the AdvancedLogger protocol once boot
+/// services have exited.
+///
+/// The AdvancedLogger protocol pointer acquired by [`init_debug`] is only valid while boot services are available. Once
+/// the handler has run, output is discarded as if the logger had not been initialized. Returns the status from
+/// `create_event` as an error if the event could not be created, or `Status::INVALID_PARAMETER` if `bs` is null.
+/// Calling this more than once has no further effect.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::{init_debug, register_exit_boot_services_handler};
+/// use r_efi::efi::Status;
+/// pub extern "efiapi" fn efi_main(
+///    _image_handle: *const core::ffi::c_void,
+///    _system_table: *const r_efi::system::SystemTable,
+///  ) -> u64 {
+///
+///    let boot_services = unsafe { (*_system_table).boot_services };
+///    let _ = init_debug(boot_services);
+///    if let Err(status) = register_exit_boot_services_handler(boot_services) {
+///      return status.as_usize() as u64;
+///    }
+///
+///    Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+pub fn register_exit_boot_services_handler(bs: *mut BootServices) -> Result<(), Status> {
+  if DISABLED {
+    return Ok(());
+  }
+  LOGGER.register_exit_boot_services_handler(bs)
+}
+
+/// Copies the log accumulated by the AdvancedLogger into `out`, and returns the number of bytes copied.
+///
+/// Reading the log back requires a revision of the AdvancedLogger protocol that provides a read-back interface.
+/// Returns:
+/// - `Status::NOT_READY` if the logger has not been initialized via [`init_debug`].
+/// - `Status::UNSUPPORTED` if the located protocol is an older revision without the read-back interface.
+/// - `Status::BUFFER_TOO_SMALL` (or another error reported by the protocol) if the log could not be copied.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::read_log_buffer;
+///
+/// let mut buffer = [0u8; 0x1000];
+/// if let Ok(size) = read_log_buffer(&mut buffer) {
+///   let _log = &buffer[..size];
+/// }
+/// ```
+pub fn read_log_buffer(out: &mut [u8]) -> Result<usize, Status> {
+  LOGGER.read_log_buffer(out)
+}
+
+/// Commits any log output buffered internally by the AdvancedLogger implementation.
+///
+/// Call this before a planned reset or handoff to ensure everything logged so far has been committed. Flushing requires
+/// a revision of the AdvancedLogger protocol that provides a flush interface; if the logger has not been initialized
+/// via [`init_debug`], or the located protocol is an older revision without the flush interface, this is a no-op.
+pub fn flush() {
+  LOGGER.flush();
+}
+
+/// Returns true if the logging subsystem has been successfully initialized via [`init_debug`], i.e. output from the
+/// `debug` and `debugln` macros is being written to the AdvancedLogger rather than discarded.
+pub fn is_debug_initialized() -> bool {
+  LOGGER.is_initialized()
+}
+
+/// Returns the revision reported by the located AdvancedLogger protocol, or `None` if the logger has not been
+/// initialized via [`init_debug`].
+///
+/// Some interfaces are only available from a particular revision of the protocol, e.g. [`read_log_buffer`] and
+/// [`flush`]; callers can use this to adapt their behavior rather than relying on those calls failing.
+pub fn protocol_version() -> Option<u32> {
+  LOGGER.protocol_version()
+}
+
+/// Sets the mask of debug levels that are written to the log.
+///
+/// A message is logged only if its level shares at least one bit with `mask`; filtered messages are discarded without
+/// formatting their arguments or acquiring the logger lock. The default filter allows all levels.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{set_log_level_filter, DEBUG_ERROR, DEBUG_INFO, DEBUG_WARN};
+///
+/// //suppress DEBUG_VERBOSE (and any other levels not listed).
+/// set_log_level_filter(DEBUG_ERROR | DEBUG_WARN | DEBUG_INFO);
+/// ```
+pub fn set_log_level_filter(mask: usize) {
+  LOGGER.set_log_level_filter(mask);
+}
+
+/// Returns the mask of debug levels that are currently written to the log. See [`set_log_level_filter`].
+pub fn log_level_filter() -> usize {
+  LOGGER.log_level_filter()
+}
+
+/// Sets the level at which the [`debug_default!`] and [`debugln_default!`] macros log. Defaults to [`DEBUG_INFO`].
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debugln_default, set_default_level, DEBUG_VERBOSE};
+///
+/// set_default_level(DEBUG_VERBOSE);
+/// debugln_default!("logged at DEBUG_VERBOSE");
+/// ```
+pub fn set_default_level(level: usize) {
+  LOGGER.set_default_level(level);
+}
+
+/// Returns the level at which the [`debug_default!`] and [`debugln_default!`] macros log. See [`set_default_level`].
+pub fn default_level() -> usize {
+  LOGGER.default_level()
+}
+
+/// Returns true if output at `level` is currently written to the log, i.e. it is allowed by the level filter set with
+/// [`set_log_level_filter`] and by the compile-time maximum level (see the crate documentation). See also
+/// [`debug_enabled!`].
+pub fn level_enabled(level: usize) -> bool {
+  _static_level_enabled(level) && LOGGER.level_enabled(level)
+}
+
+/// Returns the number of messages (or message fragments) logged before initialization that were lost because the early
+/// log buffer was full.
+///
+/// With the `early-buffer` feature enabled, output logged before [`init_debug`] succeeds is held in a fixed buffer of
+/// [`EARLY_LOG_BUFFER_SIZE`] bytes and replayed to the AdvancedLogger once the protocol is located. When the buffer
+/// fills, the oldest output is discarded and counted here.
+#[cfg(feature = "early-buffer")]
+pub fn early_log_buffer_dropped() -> usize {
+  LOGGER.early_buffer_dropped()
+}
+
+/// Enables or disables buffering of output while the logger is offline after it has been initialized.
+///
+/// Output logged before the first successful [`init_debug`] is always held in the early buffer. Once the logger has
+/// been initialized, the AdvancedLogger protocol can become unavailable again, e.g. when a [`reinit_debug`] during a
+/// handoff fails to locate it, or at ExitBootServices. By default output is then discarded (and counted in
+/// [`dropped_messages`]); with offline buffering enabled it is instead held in the same early buffer, subject to the
+/// same capacity, and replayed when the logger is next initialized successfully. The default is disabled.
+#[cfg(feature = "early-buffer")]
+pub fn set_offline_buffering(enabled: bool) {
+  LOGGER.set_offline_buffering(enabled);
+}
+
+/// Returns the symbolic name of a debug level, e.g. `"INFO"` for [`DEBUG_INFO`]. Returns `"UNKNOWN"` for values other
+/// than the standard levels defined by this crate and the custom levels registered with [`register_custom_level`].
+pub fn level_name(level: usize) -> &'static str {
+  LOGGER.level_name(level).unwrap_or("UNKNOWN")
+}
+
+/// Returns the standard debug levels defined by this crate and their names, followed by any custom levels registered
+/// with [`register_custom_level`], e.g. for building level-selection UIs.
+///
+/// The standard levels are yielded in ascending order of level bit ([`DEBUG_INIT`], [`DEBUG_WARN`], [`DEBUG_INFO`],
+/// [`DEBUG_VERBOSE`], [`DEBUG_ERROR`]), and custom levels in the order they were first registered. Custom levels are
+/// captured when this function is called; levels registered while iterating are not yielded.
+pub fn standard_levels() -> impl Iterator<Item = (usize, &'static str)> {
+  LOGGER.levels()
+}
+
+/// Registers a name for a custom debug level, used by [`level_name`] and the level name prefix (see
+/// [`set_level_prefix`]).
+///
+/// Registering a level that is already registered replaces its name. Up to [`MAX_CUSTOM_LEVELS`] custom levels may be
+/// registered; returns false without registering anything if the table is full, or if `bit` is one of the standard
+/// levels defined by this crate. Unregistered levels are written in hex by the level name prefix.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debugln, register_custom_level};
+///
+/// const DEBUG_USB: usize = 0x00000100;
+/// register_custom_level(DEBUG_USB, "USB");
+/// debugln!(DEBUG_USB, "device attached");
+/// ```
+pub fn register_custom_level(bit: usize, name: &'static str) -> bool {
+  LOGGER.register_custom_level(bit, name)
+}
+
+/// Enables or disables a level name prefix on log output.
+///
+/// When enabled, the output of each `debug`/`debugln` invocation is prefixed with the name of its level, e.g.
+/// `[INFO] `. Values other than the standard levels and registered custom levels are written in hex, e.g. `[0x4] `. If
+/// a timestamp source is also set, the level name follows the timestamp. Disabled by default.
+pub fn set_level_prefix(enabled: bool) {
+  LOGGER.set_level_prefix(enabled);
+}
+
+/// Default mapping from UEFI levels to syslog severities used by [`set_severity_prefix`].
+///
+/// | Level           | Severity          |
+/// | --------------- | ----------------- |
+/// | `DEBUG_ERROR`   | 3 (error)         |
+/// | `DEBUG_WARN`    | 4 (warning)       |
+/// | `DEBUG_INIT`    | 5 (notice)        |
+/// | `DEBUG_INFO`    | 6 (informational) |
+/// | `DEBUG_VERBOSE` | 7 (debug)         |
+pub const DEFAULT_SEVERITY_MAP: &[(usize, u8)] =
+  &[(DEBUG_ERROR, 3), (DEBUG_WARN, 4), (DEBUG_INIT, 5), (DEBUG_INFO, 6), (DEBUG_VERBOSE, 7)];
+
+/// Enables or disables a syslog-style numeric severity prefix on log output, e.g. for log aggregation tools that
+/// parse syslog.
+///
+/// When enabled, the output of each `debug`/`debugln` invocation is prefixed with the syslog severity of its level in
+/// angle brackets, e.g. `<6>` for `DEBUG_INFO`, ahead of any timestamp or level name prefix. Severities are taken from
+/// [`DEFAULT_SEVERITY_MAP`] unless replaced with [`set_severity_map`]. Disabled by default.
+pub fn set_severity_prefix(enabled: bool) {
+  LOGGER.set_severity_prefix(enabled);
+}
+
+/// Replaces the mapping from levels to syslog severities used by [`set_severity_prefix`].
+///
+/// Each entry is a level mask and its severity (0-7). A log call uses the severity of the first entry whose mask has
+/// a bit in common with its level, so more severe entries should come first; levels that match no entry are given
+/// severity 7 (debug).
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::{set_severity_map, DEBUG_ERROR, DEBUG_INFO, DEBUG_WARN};
+///
+/// //report warnings as errors.
+/// set_severity_map(&[(DEBUG_ERROR | DEBUG_WARN, 3), (DEBUG_INFO, 6)]);
+/// ```
+pub fn set_severity_map(map: &'static [(usize, u8)]) {
+  LOGGER.set_severity_map(map);
+}
+
+/// Enables or disables a sequence number prefix on log output, to detect log lines lost downstream.
+///
+/// When enabled, the output of each `debug`/`debugln` invocation is prefixed with a counter that increases by one for
+/// each call, e.g. `#00001 `, after any severity prefix and ahead of any timestamp or level name prefix. The counter
+/// advances once per call however many writes its output takes, so a gap in the captured numbers means output was
+/// lost. Numbers are written with [`DEFAULT_SEQUENCE_WIDTH`] digits unless changed with [`set_sequence_width`], and
+/// wrap to zero when they no longer fit. Disabled by default.
+pub fn set_sequence_numbers(enabled: bool) {
+  LOGGER.set_sequence_numbers(enabled);
+}
+
+/// Sets the number of digits of the sequence number prefix (see [`set_sequence_numbers`]), between 1 and 19. Values
+/// outside that range are clamped to it.
+pub fn set_sequence_width(digits: usize) {
+  LOGGER.set_sequence_width(digits);
+}
+
+/// Registers a sink that receives a copy of all log output, e.g. a memory capture or a serial writer.
+///
+/// Output is written to the AdvancedLogger protocol first and then to each sink, subject to the same level filtering.
+/// Errors returned by a sink are ignored and do not affect the protocol output. Sinks cannot be removed once added.
+/// Returns `Status::OUT_OF_RESOURCES` if [`MAX_LOG_SINKS`] sinks are already registered.
+///
+/// ## Example
+/// ```no_run
+/// use core::fmt;
+/// use rust_advanced_logger_dxe::add_sink;
+///
+/// struct SerialWriter;
+///
+/// impl fmt::Write for SerialWriter {
+///   fn write_str(&mut self, s: &str) -> fmt::Result {
+///     //write `s` to the serial port.
+///     Ok(())
+///   }
+/// }
+///
+/// static SERIAL_SINK: spin::Mutex<SerialWriter> = spin::Mutex::new(SerialWriter);
+///
+/// let _ = add_sink(&SERIAL_SINK);
+/// ```
+pub fn add_sink(sink: &'static LogSink) -> Result<(), Status> {
+  LOGGER.add_sink(sink)
+}
+
+/// Enables or disables CRLF line-ending normalization.
+///
+/// When enabled, each `\n` in the log output that is not already preceded by `\r` is written as `\r\n`, for consoles
+/// that require CRLF line endings. Disabled by default.
+pub fn set_crlf(enabled: bool) {
+  LOGGER.set_crlf(enabled);
+}
+
+/// Enables or disables line-buffered mode.
+///
+/// `core::fmt` writes the output of a single `debug!`/`debugln!` invocation in many fragments, each of which is
+/// normally passed to the AdvancedLogger protocol separately. In line-buffered mode, the fragments are accumulated in a
+/// fixed 256-byte stack buffer and written in a single call at the end of the invocation. Longer output is written
+/// whenever the buffer fills, at a character boundary so that no multi-byte UTF-8 sequence is split across writes.
+/// Disabled by default.
+pub fn set_line_buffered(enabled: bool) {
+  LOGGER.set_line_buffered(enabled);
+}
+
+/// Sets the maximum number of bytes written by a single log call.
+///
+/// Output from a single `debug!`/`debugln!` invocation (including any timestamp or level prefix) that exceeds `n`
+/// bytes is cut off, and `...<truncated>` is written once in place of the remainder. Unlimited by default.
+pub fn set_max_line_len(n: usize) {
+  LOGGER.set_max_line_len(n);
+}
+
+/// Sets the number of columns at which log output is wrapped, e.g. 80 for a serial console. 0 (the default) disables
+/// wrapping; widths above 256 are treated as 256.
+///
+/// When a line exceeds `columns` characters (including any timestamp or level prefix), a line break is written in
+/// place of the last space that keeps it within the width. Words longer than the width are broken wherever the line
+/// fills. The column is tracked across log calls, so a line built from several `debug!` invocations is wrapped as a
+/// whole; a word split between two invocations is treated as two words. Unlike [`set_max_line_len`], no output is
+/// lost, other than the spaces replaced by line breaks. Output written with [`write_bytes`] is not wrapped, and does
+/// not advance the column.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::set_wrap_width;
+///
+/// set_wrap_width(80);
+/// ```
+pub fn set_wrap_width(columns: usize) {
+  LOGGER.set_wrap_width(columns);
+}
+
+/// Sets the maximum number of bytes passed to a single call of the AdvancedLogger protocol's `write_log`.
+///
+/// Some `write_log` implementations store the length in a 32-bit field, and truncate or misbehave when passed very
+/// large slices. Longer writes are split into consecutive `write_log` calls of at most `n` bytes each (a value of 0 is
+/// treated as 1). Chunks are split at byte boundaries, so a multi-byte UTF-8 sequence may span two calls. Defaults to
+/// [`DEFAULT_MAX_CHUNK`].
+pub fn set_max_chunk(n: usize) {
+  LOGGER.set_max_chunk(n);
+}
+
+/// Sets a timestamp source used to prefix log output.
+///
+/// Once set, `timestamp_source` is called once for each `debug`/`debugln` invocation and the returned value is written
+/// as a `[<value>] ` prefix ahead of the message. Any monotonic counter may be used, e.g. the TSC or a performance
+/// counter. No prefix is written unless a timestamp source is set.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::set_timestamp_source;
+///
+/// #[cfg(target_arch = "x86_64")]
+/// fn read_tsc() -> u64 {
+///   unsafe { core::arch::x86_64::_rdtsc() }
+/// }
+///
+/// #[cfg(target_arch = "x86_64")]
+/// set_timestamp_source(read_tsc);
+/// ```
+pub fn set_timestamp_source(timestamp_source: fn() -> u64) {
+  LOGGER.set_timestamp_source(timestamp_source);
+}
+
+/// Logs `data` as a hexdump at the specified level, 16 bytes per line. Each line shows the address of its first byte
+/// (starting at `base`), the bytes in hex, and an ASCII rendering of the bytes. See also [`hexdump!`].
+pub fn hexdump(level: usize, base: usize, data: &[u8]) {
+  if DISABLED {
+    return;
+  }
+  LOGGER.hexdump(level, base, data)
+}
+
+/// Writes pre-formatted bytes to the AdvancedLogger at the specified level, e.g. output from a no-alloc formatter.
+///
+/// The bytes are passed to the protocol as-is, without going through `core::fmt`. Output at levels excluded by the
+/// level filter is discarded, and CRLF normalization (see [`set_crlf`]) is applied if enabled, but no timestamp or
+/// level name prefix is written and [`set_max_line_len`] does not apply. Sinks only receive bytes that are valid UTF-8.
+pub fn write_bytes(level: usize, bytes: &[u8]) {
+  if DISABLED {
+    return;
+  }
+  LOGGER.write_bytes(level, bytes)
+}
+
+#[doc(hidden)]
+pub fn _log(level: usize, args: fmt::Arguments) {
+  if DISABLED {
+    return;
+  }
+  LOGGER.log(level, args)
+}
+
+#[doc(hidden)]
+pub fn _escaped(bytes: &[u8]) -> impl fmt::Display + '_ {
+  EscapedBytes(bytes)
+}
+
+#[doc(hidden)]
+pub fn _log_static(level: usize, message: &'static str) {
+  if DISABLED {
+    return;
+  }
+  LOGGER.log_str(level, message)
+}
+
+// levels the logging macros are compiled for, as selected by the `max_level_*` features (the most restrictive wins).
+#[doc(hidden)]
+pub const _STATIC_LEVEL_MASK: usize = if cfg!(feature = "max_level_off") {
+  0
+} else if cfg!(feature = "max_level_error") {
+  !(DEBUG_WARN | DEBUG_INIT | DEBUG_INFO | DEBUG_VERBOSE)
+} else if cfg!(feature = "max_level_warn") {
+  !(DEBUG_INIT | DEBUG_INFO | DEBUG_VERBOSE)
+} else if cfg!(feature = "max_level_info") {
+  !DEBUG_VERBOSE
+} else {
+  usize::MAX
+};
+
+// true if the logging macros are compiled for `level`. A function rather than an expression in the macros, so that
+// lints about the constant mask (e.g. masking with zero under max_level_off) are not reported in calling crates.
+#[doc(hidden)]
+#[allow(clippy::bad_bit_mask)]
+pub const fn _static_level_enabled(level: usize) -> bool {
+  level & _STATIC_LEVEL_MASK != 0
+}
+
+// whether debug_assert_log! checks are compiled in: in debug builds, or with the `checks` feature.
+#[doc(hidden)]
+pub const _CHECKS_ENABLED: bool = cfg!(any(debug_assertions, feature = "checks"));
+
+#[doc(hidden)]
+pub fn _try_log(level: usize, args: fmt::Arguments) {
+  if DISABLED {
+    return;
+  }
+  LOGGER.try_log(level, args)
+}
+
+/// Returns the number of [`try_debug!`] and [`try_debugln!`] calls whose output was dropped because the logger was in
+/// use at the time of the call.
+pub fn try_debug_dropped() -> usize {
+  LOGGER.try_log_dropped()
+}
+
+/// Logs the debug output in `args` at the specified level, returning the reason if it was not written.
+///
+/// The logging macros ignore failures; this allows callers to tell e.g. output discarded because the logger is not
+/// initialized from output excluded by the level filter. Unlike [`try_debug!`], this waits for the logger if it is in
+/// use. With the `early-buffer` feature, output held in the early buffer until the logger is initialized counts as
+/// written.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::{try_log, LogError, DEBUG_INFO};
+///
+/// match try_log(DEBUG_INFO, format_args!("{:} handles found\n", 3)) {
+///   Ok(()) | Err(LogError::Filtered) => (),
+///   Err(LogError::Uninitialized) => {
+///     //e.g. report through another channel.
+///   }
+///   Err(LogError::WriteFailed(_status)) => (),
+/// }
+/// ```
+pub fn try_log(level: usize, args: fmt::Arguments) -> Result<(), LogError> {
+  if DISABLED || !_static_level_enabled(level) {
+    return Err(LogError::Filtered);
+  }
+  LOGGER.log_checked(level, args)
+}
+
+/// Returns the number of log calls whose output was discarded because there was no AdvancedLogger protocol to write
+/// to, i.e. calls made before [`init_debug`] succeeded or after ExitBootServices.
+///
+/// With the `early-buffer` feature, output written before initialization (or while offline, see
+/// `set_offline_buffering`) is held in the early buffer rather than discarded, and is not counted here.
+pub fn dropped_messages() -> u64 {
+  LOGGER.dropped_messages()
+}
+
+/// Logs a panic message and location to the AdvancedLogger at [`DEBUG_ERROR`].
+///
+/// Intended to be called from the consumer's `#[panic_handler]`. Formatting is allocation-free, and the output is
+/// quietly discarded if the logger has not been initialized or if the panic occurred while the logger was in use.
+///
+/// ## Example
+/// ```ignore
+/// #[panic_handler]
+/// fn panic(info: &core::panic::PanicInfo) -> ! {
+///   rust_advanced_logger_dxe::log_panic(info);
+///   loop {}
+/// }
+/// ```
+#[cfg(feature = "panic")]
+pub fn log_panic(info: &core::panic::PanicInfo) {
+  if DISABLED {
+    return;
+  }
+  LOGGER.log_panic(info)
+}
+
+/// [`fmt::Write`] implementation that writes to the AdvancedLogger at a fixed level.
+///
+/// Obtained via [`writer`], a LogWriter allows the logger to be passed to code that renders output into a
+/// `&mut dyn fmt::Write`. Each `write_str` call is forwarded to the log as a separate log call at the writer's level.
+///
+/// ## Example
+/// ```no_run
+/// use core::fmt::Write;
+/// use rust_advanced_logger_dxe::{writer, DEBUG_INFO};
+///
+/// fn render_table(out: &mut dyn Write) -> core::fmt::Result {
+///   writeln!(out, "{:<8}{:>8}", "name", "value")?;
+///   writeln!(out, "{:<8}{:>8}", "pages", 42)
+/// }
+///
+/// let _ = render_table(&mut writer(DEBUG_INFO));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct LogWriter {
+  level: usize,
+}
+
+impl fmt::Write for LogWriter {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    _log(self.level, format_args!("{:}", s));
+    Ok(())
+  }
+}
+
+/// Returns a [`LogWriter`] that writes to the AdvancedLogger at the specified level.
+pub fn writer(level: usize) -> LogWriter {
+  LogWriter { level }
+}
+
+/// Scope guard that logs a block of related messages at a single level.
+///
+/// Obtained via [`scope`], a LevelScope saves repeating the level for each message of the block. It implements
+/// [`fmt::Write`] (so `write!` and `writeln!` can be used with it), and [`LevelScope::line`] logs a single line.
+/// The level applies for as long as the scope is alive.
+///
+/// ## Example
+/// ```no_run
+/// use rust_advanced_logger_dxe::{scope, DEBUG_VERBOSE};
+///
+/// fn dump_handles(handles: &[usize]) {
+///   let mut verbose = scope(DEBUG_VERBOSE);
+///   verbose.line(format_args!("{:} handles:", handles.len()));
+///   for handle in handles {
+///     verbose.line(format_args!("  {:#x}", handle));
+///   }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct LevelScope {
+  writer: LogWriter,
+}
+
+impl LevelScope {
+  /// Returns the level at which this scope logs.
+  pub fn level(&self) -> usize {
+    self.writer.level
+  }
+
+  /// Logs the given message followed by a newline, as a single log call at the scope's level.
+  pub fn line(&mut self, args: fmt::Arguments) {
+    _log(self.writer.level, format_args!("{:}\n", args));
+  }
+}
+
+impl fmt::Write for LevelScope {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.writer.write_str(s)
+  }
+}
+
+/// Returns a [`LevelScope`] that logs a block of messages to the AdvancedLogger at the specified level.
+pub fn scope(level: usize) -> LevelScope {
+  LevelScope { writer: writer(level) }
+}
+
+/// Guard that times a block of code, logging the end of the span when dropped.
+///
+/// Obtained via [`span!`] (or [`LockedAdvancedLogger::span`]). Nothing is logged when the span ends if its level was
+/// not written to the log when it started.
+#[derive(Debug)]
+#[must_use = "the span ends as soon as the guard is dropped"]
+pub struct Span<'a, R: RawMutex = spin::Mutex<()>> {
+  logger: &'a LockedAdvancedLogger<R>,
+  level: usize,
+  name: &'a str,
+  //timestamp at the start of the span, if a timestamp source is set.
+  start: Option<u64>,
+  //set if the start of the span was logged.
+  active: bool,
+}
+
+impl<R: RawMutex> Drop for Span<'_, R> {
+  fn drop(&mut self) {
+    if !self.active {
+      return;
+    }
+    //the timestamp source may have been set or cleared during the span, in which case no duration is logged.
+    match (self.start, self.logger.timestamp()) {
+      (Some(start), Some(end)) => {
+        self.logger.log(self.level, format_args!("{:}: end ({:})\n", self.name, end.wrapping_sub(start)))
+      }
+      _ => self.logger.log(self.level, format_args!("{:}: end\n", self.name)),
+    }
+  }
+}
+
+#[doc(hidden)]
+pub fn _span(level: usize, name: &str, compiled: bool) -> Span<'_> {
+  match compiled {
+    true => LOGGER.span(level, name),
+    false => Span { logger: &LOGGER, level, name, start: None, active: false },
+  }
+}
+
+/// Implementation of the [`log`](https://docs.rs/log) crate's [`log::Log`] trait that writes to the AdvancedLogger.
+///
+/// `log` crate levels are translated to UEFI debug levels as follows:
+///
+/// | `log::Level` | UEFI debug level  |
+/// |--------------|-------------------|
+/// | `Error`      | [`DEBUG_ERROR`]   |
+/// | `Warn`       | [`DEBUG_WARN`]    |
+/// | `Info`       | [`DEBUG_INFO`]    |
+/// | `Debug`      | [`DEBUG_VERBOSE`] |
+/// | `Trace`      | [`DEBUG_VERBOSE`] |
+///
+/// Records are filtered with the same level filter and compile-time maximum level as the [`debug!`] macro (see
+/// [`set_log_level_filter`] and the crate documentation), and a newline is appended to each record. Use [`init_log`] to
+/// install it as the `log` crate logger.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{init_debug, init_log};
+/// use r_efi::efi::Status;
+/// pub extern "efiapi" fn efi_main(
+///    _image_handle: *const core::ffi::c_void,
+///    _system_table: *const r_efi::system::SystemTable,
+///  ) -> u64 {
+///
+///    let _ = init_debug(unsafe { (*_system_table).boot_services});
+///    init_log().expect("a log crate logger was already installed.");
+///
+///    log::info!("Hello, World. This is {:} in {:}.", "rust", "UEFI");
+///
+///    Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+#[cfg(feature = "log")]
+#[derive(Debug)]
+pub struct AdvancedLoggerLog;
+
+#[cfg(feature = "log")]
+static ADVANCED_LOGGER_LOG: AdvancedLoggerLog = AdvancedLoggerLog;
+
+/// Translates a `log` crate level into the equivalent UEFI debug level.
+///
+/// | `log::Level`         | UEFI level      |
+/// | -------------------- | --------------- |
+/// | `Error`              | `DEBUG_ERROR`   |
+/// | `Warn`               | `DEBUG_WARN`    |
+/// | `Info`               | `DEBUG_INFO`    |
+/// | `Debug`, `Trace`     | `DEBUG_VERBOSE` |
+///
+/// This is the mapping used by [`AdvancedLoggerLog`]. Requires the `log` feature.
+#[cfg(feature = "log")]
+pub fn log_to_uefi_level(level: log::Level) -> usize {
+  match level {
+    log::Level::Error => DEBUG_ERROR,
+    log::Level::Warn => DEBUG_WARN,
+    log::Level::Info => DEBUG_INFO,
+    log::Level::Debug | log::Level::Trace => DEBUG_VERBOSE,
+  }
+}
+
+/// Translates a UEFI debug level into the nearest `log` crate level.
+///
+/// | UEFI level                     | `log::Level` |
+/// | ------------------------------ | ------------ |
+/// | `DEBUG_ERROR`                  | `Error`      |
+/// | `DEBUG_WARN`                   | `Warn`       |
+/// | `DEBUG_INFO`                   | `Info`       |
+/// | `DEBUG_VERBOSE`, `DEBUG_INIT`  | `Debug`      |
+///
+/// `DEBUG_INIT` (and any other level) has no `log` equivalent, and maps to `Debug`. If `level` has more than one bit
+/// set, the most severe standard level wins. The mapping round-trips through [`log_to_uefi_level`] for `DEBUG_ERROR`,
+/// `DEBUG_WARN`, `DEBUG_INFO` and `DEBUG_VERBOSE`. Requires the `log` feature.
+#[cfg(feature = "log")]
+pub fn uefi_level_to_log(level: usize) -> log::Level {
+  if level & DEBUG_ERROR != 0 {
+    log::Level::Error
+  } else if level & DEBUG_WARN != 0 {
+    log::Level::Warn
+  } else if level & DEBUG_INFO != 0 {
+    log::Level::Info
+  } else {
+    log::Level::Debug
+  }
+}
+
+#[cfg(feature = "log")]
+impl log::Log for AdvancedLoggerLog {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    level_enabled(log_to_uefi_level(metadata.level()))
+  }
+
+  fn log(&self, record: &log::Record) {
+    let level = log_to_uefi_level(record.level());
+    if _static_level_enabled(level) {
+      _log(level, format_args!("{}\n", record.args()))
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+/// Installs [`AdvancedLoggerLog`] as the `log` crate logger.
+///
+/// The `log` crate maximum level is set to the compile-time maximum level (see the crate documentation), which allows
+/// all records through unless one of the `max_level_*` features is enabled; filtering is then controlled by
+/// [`set_log_level_filter`]. Returns an error if a `log` crate logger has already been installed.
+#[cfg(feature = "log")]
+pub fn init_log() -> Result<(), log::SetLoggerError> {
+  log::set_logger(&ADVANCED_LOGGER_LOG)?;
+  log::set_max_level(static_log_level_filter());
+  Ok(())
+}
+
+// the most verbose `log` crate level whose translated level the logging macros are compiled for.
+#[cfg(feature = "log")]
+fn static_log_level_filter() -> log::LevelFilter {
+  [log::Level::Trace, log::Level::Info, log::Level::Warn, log::Level::Error]
+    .into_iter()
+    .find(|level| _static_level_enabled(log_to_uefi_level(*level)))
+    .map_or(log::LevelFilter::Off, |level| level.to_level_filter())
+}
+
+/// Prints to the AdvancedLogger log at the specified level.
+///
+/// This macro uses the same syntax as rust std [`std::println!`] macro, with the addition of a level argument that
+/// indicates what debug level the output is to be written at.
+///
+/// See [`std::fmt`] for details on format strings.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{init_debug, debug, DEBUG_INFO};
+/// use r_efi::efi::Status;
+/// pub extern "efiapi" fn efi_main(
+///    _image_handle: *const core::ffi::c_void,
+///    _system_table: *const r_efi::system::SystemTable,
+///  ) -> u64 {
+///
+///    //Initialize debug logging - no output without this.
+///    let _ = init_debug(unsafe { (*_system_table).boot_services});
+///
+///    debug!(DEBUG_INFO, "Hello, World. This is {:} in {:}. ", "rust", "UEFI");
+///    debug!(DEBUG_INFO, "Better add our own newline.\n");
+///
+///    Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+#[macro_export]
+macro_rules! debug {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::_static_level_enabled(level) {
+            $crate::_log(level, format_args!($($arg)*))
+        }
+    }}
+}
+
+/// Prints to the AdvancedLogger log at the default level.
+///
+/// Equivalent to the [`debug!`] macro at the level returned by [`default_level`] (see [`set_default_level`]), for
+/// modules that almost always log at one level.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::debug_default;
+///
+/// debug_default!("{:} handles found\n", 3);
+/// ```
+#[macro_export]
+macro_rules! debug_default {
+  ($($arg:tt)*) => {
+    $crate::debug!($crate::default_level(), $($arg)*)
+  };
+}
+
+/// Prints to the AdvancedLogger log at the default level with a newline.
+///
+/// Equivalent to the [`debug_default!`] macro except that a newline is appended to the format string.
+#[macro_export]
+macro_rules! debugln_default {
+  () => {
+    $crate::debugln!($crate::default_level())
+  };
+  ($($arg:tt)*) => {
+    $crate::debugln!($crate::default_level(), $($arg)*)
+  };
+}
+
+/// Returns true if output at the specified level is currently written to the log.
+///
+/// Use this to skip building expensive debug output that would be discarded by the level filter (see
+/// [`set_log_level_filter`]).
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debug_enabled, debugln, DEBUG_VERBOSE};
+///
+/// fn expensive() -> [u64; 4] {
+///   [1, 2, 3, 4]
+/// }
+///
+/// if debug_enabled!(DEBUG_VERBOSE) {
+///   debugln!(DEBUG_VERBOSE, "{:?}", expensive());
+/// }
+/// ```
+#[macro_export]
+macro_rules! debug_enabled {
+  ($level:expr) => {
+    $crate::level_enabled($level)
+  };
+}
+
+/// Prints a hexdump of a byte buffer to the AdvancedLogger log at the specified level.
+///
+/// The second argument is the address displayed for the first byte of the buffer, to allow the dump to be labelled
+/// with e.g. the physical address of the buffer or an offset within a larger structure.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{hexdump, DEBUG_INFO};
+///
+/// let buffer = *b"Hello, World!\n";
+/// hexdump!(DEBUG_INFO, buffer.as_ptr() as usize, &buffer);
+/// // 7ffd5c9f0a10  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0a        |Hello, World!.|
+/// ```
+#[macro_export]
+macro_rules! hexdump {
+  ($level:expr, $base:expr, $bytes:expr) => {{
+    let level = $level;
+    if $crate::_static_level_enabled(level) {
+      $crate::hexdump(level, $base, $bytes)
+    }
+  }};
+}
+
+/// Prints a byte buffer to the AdvancedLogger log at the specified level as escaped ASCII, on a single line.
+///
+/// Printable ASCII bytes are written as-is, backslashes as `\\`, and all other bytes (including control characters
+/// such as newlines, and bytes with the high bit set) as `\xNN`, followed by a newline. This suits buffers that are
+/// mostly text, where a [`hexdump!`] would be harder to read. Nothing is allocated; the bytes are formatted as they are
+/// written.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debug_escaped, DEBUG_INFO};
+///
+/// debug_escaped!(DEBUG_INFO, b"name=\"boot\"\r\n\xff");
+/// // name="boot"\x0d\x0a\xff
+/// ```
+#[macro_export]
+macro_rules! debug_escaped {
+  ($level:expr, $bytes:expr) => {
+    $crate::debug!($level, "{}\n", $crate::_escaped($bytes))
+  };
+}
+
+/// Prints to the AdvancedLogger log at the specified level with a newline.
+///
+/// Equivalent to the [`debug!`] macro except that a newline is appended to the format string.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{init_debug, debugln, DEBUG_INFO};
+/// use r_efi::efi::Status;
+/// pub extern "efiapi" fn efi_main(
+///    _image_handle: *const core::ffi::c_void,
+///    _system_table: *const r_efi::system::SystemTable,
+///  ) -> u64 {
+///
+///    //Initialize debug logging - no output without this.
+///    let _ = init_debug(unsafe { (*_system_table).boot_services});
+///
+///    debugln!(DEBUG_INFO, "Hello, World. This is {:} in {:}.", "rust", "UEFI");
+///
+///    Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+#[macro_export]
+macro_rules! debugln {
+    ($level:expr) => ($crate::debug!($level, "\n"));
+    ($level:expr, $fmt:expr) => ($crate::debug!($level, concat!($fmt, "\n")));
+    ($level:expr, $fmt:expr, $($arg:tt)*) => ($crate::debug!($level, concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Prints to the log of a [`LockedAdvancedLogger`] instance at the specified level.
+///
+/// Equivalent to the [`debug!`] macro, but writes to the given instance (with its level filter and prefixes) rather
+/// than the global logger.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debug_to, LockedAdvancedLogger, DEBUG_INFO};
+///
+/// static STORAGE_LOG: LockedAdvancedLogger = LockedAdvancedLogger::named("storage");
+///
+/// debug_to!(STORAGE_LOG, DEBUG_INFO, "{:} disks found\n", 2);
+/// ```
+#[macro_export]
+macro_rules! debug_to {
+  ($logger:expr, $level:expr, $($arg:tt)*) => {{
+    let level = $level;
+    if $crate::_static_level_enabled(level) {
+      ($logger).log(level, format_args!($($arg)*))
+    }
+  }};
+}
+
+/// Prints to the log of a [`LockedAdvancedLogger`] instance at the specified level with a newline.
+///
+/// Equivalent to the [`debug_to!`] macro except that a newline is appended to the format string.
+#[macro_export]
+macro_rules! debugln_to {
+  ($logger:expr, $level:expr) => {
+    $crate::debug_to!($logger, $level, "\n")
+  };
+  ($logger:expr, $level:expr, $fmt:expr) => {
+    $crate::debug_to!($logger, $level, concat!($fmt, "\n"))
+  };
+  ($logger:expr, $level:expr, $fmt:expr, $($arg:tt)*) => {
+    $crate::debug_to!($logger, $level, concat!($fmt, "\n"), $($arg)*)
+  };
+}
+
+/// Times a block of code, logging `"<name>: start"` at the specified level when called and `"<name>: end (<ticks>)"`
+/// when the returned [`Span`] guard is dropped.
+///
+/// The duration is the difference between readings of the timestamp source (see [`set_timestamp_source`]) taken after
+/// the start line is logged and before the end line is, so it does not include the logging itself. If no timestamp
+/// source is set, the end line omits the duration. The guard must be bound to a variable (not `_`), since the span
+/// ends when it is dropped.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{span, DEBUG_INFO};
+///
+/// fn load_drivers() {
+///   let _span = span!(DEBUG_INFO, "load drivers");
+///   //...
+/// }
+/// // load drivers: start
+/// // load drivers: end (52133)
+/// ```
+#[macro_export]
+macro_rules! span {
+  ($level:expr, $name:expr) => {{
+    let level = $level;
+    $crate::_span(level, $name, $crate::_static_level_enabled(level))
+  }};
+}
+
+/// Prints a string literal to the AdvancedLogger log at the specified level, without formatting.
+///
+/// For high-frequency trace points: the bytes of the literal are written as-is, bypassing `core::fmt` entirely, so
+/// braces are not interpreted and no arguments are accepted. The level filter, prefixes and other logger options apply
+/// as for [`debug!`]. See also [`debugln_static!`].
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debug_static, DEBUG_VERBOSE};
+///
+/// debug_static!(DEBUG_VERBOSE, "entering timer handler\n");
+/// ```
+#[macro_export]
+macro_rules! debug_static {
+  ($level:expr, $message:expr) => {{
+    let level = $level;
+    if $crate::_static_level_enabled(level) {
+      $crate::_log_static(level, $message)
+    }
+  }};
+}
+
+/// Prints a string literal to the AdvancedLogger log at the specified level with a newline, without formatting.
+///
+/// Equivalent to the [`debug_static!`] macro except that a newline is appended to the literal (at compile time).
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debugln_static, DEBUG_VERBOSE};
+///
+/// debugln_static!(DEBUG_VERBOSE, "entering timer handler");
+/// ```
+#[macro_export]
+macro_rules! debugln_static {
+  ($level:expr, $message:literal) => {
+    $crate::debug_static!($level, concat!($message, "\n"))
+  };
+}
+
+/// Prints to the AdvancedLogger log at the specified level without waiting for the logger.
+///
+/// Equivalent to the [`debug!`] macro except that if the logger is already in use (e.g. when logging from a context
+/// that interrupted another log call), the output is dropped rather than waiting for the logger to become available.
+/// The number of dropped calls is reported by [`try_debug_dropped`].
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{try_debug, DEBUG_INFO};
+///
+/// extern "efiapi" fn timer_notify(_event: r_efi::efi::Event, _context: *mut core::ffi::c_void) {
+///   try_debug!(DEBUG_INFO, "timer tick\n");
+/// }
+/// ```
+#[macro_export]
+macro_rules! try_debug {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::_static_level_enabled(level) {
+            $crate::_try_log(level, format_args!($($arg)*))
+        }
+    }}
+}
+
+/// Prints to the AdvancedLogger log at the specified level with a newline, without waiting for the logger.
+///
+/// Equivalent to the [`try_debug!`] macro except that a newline is appended to the format string.
+#[macro_export]
+macro_rules! try_debugln {
+    ($level:expr) => ($crate::try_debug!($level, "\n"));
+    ($level:expr, $fmt:expr) => ($crate::try_debug!($level, concat!($fmt, "\n")));
+    ($level:expr, $fmt:expr, $($arg:tt)*) => ($crate::try_debug!($level, concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Checks a non-fatal invariant, logging a failure at the specified level and continuing rather than panicking.
+///
+/// If the condition is false, a line with the stringified condition, the call site and the optional message is logged,
+/// e.g. `Assertion failed: count < 4 at src/driver.rs:42: count is 7`. Like [`core::debug_assert!`], the check is
+/// only compiled in debug builds, unless the `checks` feature is enabled; otherwise the condition is not evaluated.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debug_assert_log, DEBUG_WARN};
+///
+/// let count = 7;
+/// debug_assert_log!(count < 4, DEBUG_WARN, "count is {:}", count);
+/// ```
+#[macro_export]
+macro_rules! debug_assert_log {
+    ($cond:expr, $level:expr $(,)?) => {{
+        let level = $level;
+        if $crate::_CHECKS_ENABLED && $crate::_static_level_enabled(level) && !$cond {
+            $crate::_log(level, format_args!("Assertion failed: {} at {}:{}\n", stringify!($cond), file!(), line!()))
+        }
+    }};
+    ($cond:expr, $level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if $crate::_CHECKS_ENABLED && $crate::_static_level_enabled(level) && !$cond {
+            $crate::_log(
+                level,
+                format_args!(
+                    "Assertion failed: {} at {}:{}: {}\n",
+                    stringify!($cond),
+                    file!(),
+                    line!(),
+                    format_args!($($arg)+)
+                ),
+            )
+        }
+    }};
+}
+
+/// Prints a structured key-value line to the AdvancedLogger log at the specified level.
+///
+/// The line is written as the event name followed by each `key=value` pair, separated by spaces. Values are formatted
+/// with [`core::fmt::Display`], or with [`core::fmt::Debug`] if prefixed with `?` (e.g. to quote strings). No
+/// allocation is required.
+///
+/// ```no_run
+/// use rust_advanced_logger_dxe::{debug_kv, DEBUG_INFO};
+///
+/// let handle = 0x1000;
+/// debug_kv!(DEBUG_INFO, "driver_start", handle = handle, name = ?"HidDxe");
+/// // driver_start handle=4096 name="HidDxe"
+/// ```
+#[macro_export]
+macro_rules! debug_kv {
+    ($level:expr, $event:expr $(, $($kv:tt)*)?) => {
+        $crate::__debug_kv!(@munch $level, $event, [], []; $($($kv)*)?)
+    };
+}
+
+// accumulates the format string and arguments for debug_kv, one key-value pair at a time.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __debug_kv {
+    (@munch $level:expr, $event:expr, [$($fmt:tt)*], [$($arg:expr),*];) => {{
+        let level = $level;
+        if $crate::_static_level_enabled(level) {
+            $crate::_log(level, format_args!(concat!("{}" $($fmt)*, "\n"), $event $(, $arg)*))
+        }
+    }};
+    (
+        @munch $level:expr, $event:expr, [$($fmt:tt)*], [$($arg:expr),*];
+        $key:ident = ?$value:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__debug_kv!(
+            @munch $level, $event, [$($fmt)* , " ", stringify!($key), "={:?}"], [$($arg,)* $value]; $($($rest)*)?
+        )
+    };
+    (
+        @munch $level:expr, $event:expr, [$($fmt:tt)*], [$($arg:expr),*];
+        $key:ident = $value:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::__debug_kv!(
+            @munch $level, $event, [$($fmt)* , " ", stringify!($key), "={}"], [$($arg,)* $value]; $($($rest)*)?
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+  use crate::{
+    default_level, deinit_debug, init_debug, init_debug_with_protocol, is_debug_initialized, level_name,
+    log_level_filter, register_custom_level, reinit_debug, scope, set_default_level, set_level_prefix,
+    set_log_level_filter, standard_levels, testing, try_debug_dropped, writer, AdvancedLoggerFlushableProtocol,
+    AdvancedLoggerProtocol, AdvancedLoggerReadableProtocol, ColumnWriter, LevelWriter, LockedAdvancedLogger, LogError,
+    RawMutex, ADVANCED_LOGGER_PROTOCOL_FLUSH_VERSION, ADVANCED_LOGGER_PROTOCOL_GUID,
+    ADVANCED_LOGGER_PROTOCOL_READ_VERSION, ADVANCED_LOGGER_PROTOCOL_SIGNATURE, ADVANCED_LOGGER_PROTOCOL_VERSION,
+    DEBUG_ERROR, DEBUG_INFO, DEBUG_INIT, DEBUG_VERBOSE, DEBUG_WARN, DEFAULT_MAX_CHUNK, LINE_BUFFER_SIZE, LOGGER,
+    MAX_CUSTOM_LEVELS,
+  };
+  use core::{
+    ffi::c_void,
+    fmt::Write,
+    mem::MaybeUninit,
+    slice::from_raw_parts,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+  };
+  use r_efi::{
+    efi::{Event, Guid, Status, Tpl},
+    system::{BootServices, EventNotify, EVT_SIGNAL_EXIT_BOOT_SERVICES},
+  };
+  use std::{cell::Cell, println, str, string::String, sync::Mutex, thread_local, vec::Vec};
+
+  // serializes tests that configure or initialize the global LOGGER instance.
+  static GLOBAL_LOGGER_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+  static ADVANCED_LOGGER_INSTANCE: AdvancedLoggerProtocol =
+    AdvancedLoggerProtocol { signature: 0, version: 0, write_log: mock_advanced_logger_write };
+
+  extern "efiapi" fn mock_advanced_logger_write(
+    this: *const AdvancedLoggerProtocol,
+    error_level: usize,
+    buffer: *const u8,
+    buffer_size: usize,
+  ) {
+    assert_eq!(this, &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol);
+    //to avoid dealing with complicated mock state, we assume that tests will produce the same output string:
+    //"This is a <x> test.\n", where <x> depends on the debug level (e.g. "DEBUG_INFO"). In addition,
+    //the string might be built with multiple calls to write, so we just check that it is a substring
+    //of what we expect.
+    let buf: &[u8] = unsafe { from_raw_parts(buffer, buffer_size) };
+    let str = str::from_utf8(buf).unwrap();
+    println!("buffer {buffer:?}:{buffer_size:?}, str: {str:?}");
+    match error_level {
+      DEBUG_INIT => assert!("This is a DEBUG_INIT test.\n".contains(str)),
+      DEBUG_WARN => assert!("This is a DEBUG_WARN test.\n".contains(str)),
+      DEBUG_INFO => assert!("This is a DEBUG_INFO test.\n".contains(str)),
+      DEBUG_VERBOSE => assert!("This is a DEBUG_VERBOSE test.\n".contains(str)),
+      DEBUG_ERROR => assert!("This is a DEBUG_ERROR test.\n".contains(str)),
+      _ => panic!("Unrecognized error string."),
+    }
+  }
+
+  extern "efiapi" fn mock_locate_protocol(
+    protocol: *mut Guid,
+    _registration: *mut c_void,
+    interface: *mut *mut c_void,
+  ) -> Status {
+    let protocol = unsafe { protocol.as_mut().unwrap() };
+    assert_eq!(protocol, &ADVANCED_LOGGER_PROTOCOL_GUID);
+    assert!(!interface.is_null());
+    unsafe {
+      interface.write(&ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol as *mut c_void);
+    }
+    Status::SUCCESS
+  }
+
+  fn mock_boot_services() -> BootServices {
+    let boot_services = MaybeUninit::zeroed();
+    let mut boot_services: BootServices = unsafe { boot_services.assume_init() };
+    boot_services.locate_protocol = mock_locate_protocol;
+    boot_services
+  }
+
+  extern "efiapi" fn mock_locate_protocol_not_found(
+    _protocol: *mut Guid,
+    _registration: *mut c_void,
+    _interface: *mut *mut c_void,
+  ) -> Status {
+    Status::NOT_FOUND
+  }
+
+  const CUSTOM_LOGGER_PROTOCOL_GUID: Guid =
+    Guid::from_fields(0x1d0c4a3e, 0x6f5b, 0x4e2d, 0x8a, 0x71, &[0x0b, 0x3c, 0x9e, 0x52, 0xd4, 0x17]);
+
+  extern "efiapi" fn mock_locate_custom_guid_protocol(
+    protocol: *mut Guid,
+    _registration: *mut c_void,
+    interface: *mut *mut c_void,
+  ) -> Status {
+    let protocol = unsafe { protocol.as_mut().unwrap() };
+    if protocol != &CUSTOM_LOGGER_PROTOCOL_GUID {
+      return Status::NOT_FOUND;
+    }
+    unsafe {
+      interface.write(testing::mock_logger_protocol());
+    }
+    Status::SUCCESS
+  }
+
+  // boot services that locate the capturing mock protocol from the testing module, which records each write_log call
+  // (level and bytes) for the current test thread, for tests that need to inspect exactly what reached the protocol.
+  fn capturing_boot_services() -> BootServices {
+    testing::mock_boot_services()
+  }
+
+  // returns (and clears) the writes captured on the current thread.
+  fn take_captured_writes() -> Vec<(usize, String)> {
+    testing::take_captured_writes()
+      .into_iter()
+      .map(|(level, bytes)| (level, String::from_utf8(bytes).unwrap()))
+      .collect()
+  }
+
+  // returns (and clears) the writes captured on the current thread, with adjacent writes at the same level merged so
+  // that assertions don't depend on how core::fmt fragments the output.
+  fn take_captured_output() -> Vec<(usize, String)> {
+    let mut output: Vec<(usize, String)> = Vec::new();
+    for (level, str) in take_captured_writes() {
+      match output.last_mut() {
+        Some((last_level, last_str)) if *last_level == level => last_str.push_str(&str),
+        _ => output.push((level, str)),
+      }
+    }
+    output
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn init_should_initialize_logger() {
+    let mut boot_services = mock_boot_services();
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut boot_services).unwrap();
+
+    assert_eq!(
+      TEST_LOGGER.inner.lock().protocol.unwrap() as *const AdvancedLoggerProtocol,
+      &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol
+    );
+  }
+
+  // sink that always fails.
+  struct FailingSink;
+
+  impl Write for FailingSink {
+    fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+      Err(core::fmt::Error)
+    }
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn sinks_should_mirror_protocol_output() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    static FAILING_SINK: spin::Mutex<FailingSink> = spin::Mutex::new(FailingSink);
+    static CAPTURE_SINK: spin::Mutex<String> = spin::Mutex::new(String::new());
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_log_level_filter(!DEBUG_VERBOSE);
+    TEST_LOGGER.add_sink(&FAILING_SINK).unwrap();
+    TEST_LOGGER.add_sink(&CAPTURE_SINK).unwrap();
+
+    let arg = "sink";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("to the {:} {:}\n", arg, 1));
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("filtered\n"));
+    TEST_LOGGER.hexdump(DEBUG_ERROR, 0, b"sink");
+
+    let protocol_output: String = take_captured_output().into_iter().map(|(_, str)| str).collect();
+    assert!(protocol_output.starts_with("to the sink 1\n00000000 "));
+    assert_eq!(*CAPTURE_SINK.lock(), protocol_output);
+
+    //the sink table is bounded.
+    TEST_LOGGER.add_sink(&CAPTURE_SINK).unwrap();
+    TEST_LOGGER.add_sink(&CAPTURE_SINK).unwrap();
+    assert_eq!(TEST_LOGGER.add_sink(&CAPTURE_SINK), Err(Status::OUT_OF_RESOURCES));
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn debug_kv_should_render_key_value_line() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    debug_kv!(DEBUG_INFO, "boot_phase");
+    debug_kv!(DEBUG_WARN, "driver_start", handle = 0x1000, ratio = 2.5, enabled = true,);
+    let name = "Hid Dxe";
+    debug_kv!(DEBUG_ERROR, "driver_error", name = ?name, status = ?Status::NOT_FOUND, code = 14u8);
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_INFO, String::from("boot_phase\n")),
+        (DEBUG_WARN, String::from("driver_start handle=4096 ratio=2.5 enabled=true\n")),
+        (DEBUG_ERROR, std::format!("driver_error name=\"Hid Dxe\" status={:?} code=14\n", Status::NOT_FOUND)),
+      ]
+    );
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn second_init_should_be_rejected_until_reinit() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut mock_boot_services()).unwrap();
+
+    //a second init leaves the original protocol in place.
+    assert_eq!(TEST_LOGGER.init(&mut capturing_boot_services()), Err(Status::ALREADY_STARTED));
+    assert_eq!(
+      TEST_LOGGER.init_with_guid(&mut capturing_boot_services(), ADVANCED_LOGGER_PROTOCOL_GUID),
+      Err(Status::ALREADY_STARTED)
+    );
+    assert_eq!(
+      TEST_LOGGER.inner.lock().protocol.unwrap() as *const AdvancedLoggerProtocol,
+      &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol
+    );
+
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    assert_eq!(
+      TEST_LOGGER.inner.lock().protocol.unwrap() as *const AdvancedLoggerProtocol,
+      testing::mock_logger_protocol() as *const AdvancedLoggerProtocol
+    );
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn debug_macro_should_log_things() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut boot_services = mock_boot_services();
+    reinit_debug(&mut boot_services).unwrap();
+
+    assert_eq!(
+      LOGGER.inner.lock().protocol.unwrap() as *const AdvancedLoggerProtocol,
+      &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol
+    );
+
+    debugln!(DEBUG_INIT, "This is a DEBUG_INIT test.");
+    debugln!(DEBUG_WARN, "This is a {:} test.", "DEBUG_WARN");
+    debug!(DEBUG_INFO, "This {:} a {:} test.\n", "is", "DEBUG_INFO");
+    debug!(DEBUG_VERBOSE, "This {:} {:} {:} test.\n", "is", "a", "DEBUG_VERBOSE");
+    debug!(DEBUG_ERROR, "{:}", "This is a DEBUG_ERROR test.\n");
+  }
+
+  #[test]
+  fn default_filter_should_allow_all_levels() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    assert_eq!(TEST_LOGGER.log_level_filter(), usize::MAX);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn filtered_levels_should_not_reach_protocol() {
+    let mut boot_services = capturing_boot_services();
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut boot_services).unwrap();
+    TEST_LOGGER.set_log_level_filter(DEBUG_ERROR | DEBUG_INFO);
+    assert_eq!(TEST_LOGGER.log_level_filter(), DEBUG_ERROR | DEBUG_INFO);
+
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("verbose {:}\n", 1));
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("info {:}\n", 2));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("warn {:}\n", 3));
+    TEST_LOGGER.log(DEBUG_ERROR, format_args!("error {:}\n", 4));
+
+    let writes = take_captured_writes();
+    assert!(writes.iter().all(|(level, _)| *level == DEBUG_INFO || *level == DEBUG_ERROR));
+    let output: String = writes.iter().map(|(_, str)| str.as_str()).collect();
+    assert_eq!(output, "info 2\nerror 4\n");
+
+    //restoring the permissive filter should let everything through again.
+    TEST_LOGGER.set_log_level_filter(usize::MAX);
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("verbose\n"));
+    assert_eq!(take_captured_writes(), [(DEBUG_VERBOSE, String::from("verbose\n"))]);
+  }
+
+  #[test]
+  fn filtered_levels_should_not_be_formatted() {
+    struct PanicOnFormat;
+    impl core::fmt::Display for PanicOnFormat {
+      fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        panic!("filtered arguments should not be formatted.");
+      }
+    }
+
+    let mut boot_services = capturing_boot_services();
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut boot_services).unwrap();
+    TEST_LOGGER.set_log_level_filter(!DEBUG_VERBOSE);
+
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("{:}", PanicOnFormat));
+    assert!(take_captured_writes().is_empty());
+  }
+
+  #[test]
+  fn set_log_level_filter_should_update_global_logger() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let original = log_level_filter();
+    set_log_level_filter(DEBUG_ERROR | DEBUG_WARN);
+    assert_eq!(log_level_filter(), DEBUG_ERROR | DEBUG_WARN);
+    assert_eq!(LOGGER.inner.lock().filter, DEBUG_ERROR | DEBUG_WARN);
+    set_log_level_filter(original);
+    assert_eq!(log_level_filter(), original);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn init_should_report_locate_protocol_failure() {
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_protocol_not_found;
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+
+    assert_eq!(TEST_LOGGER.init(&mut boot_services), Err(Status::NOT_FOUND));
+    assert!(TEST_LOGGER.inner.lock().protocol.is_none());
+
+    //a successful init followed by a failed re-init leaves the logger uninitialized.
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert!(TEST_LOGGER.inner.lock().protocol.is_some());
+    assert_eq!(TEST_LOGGER.reinit(&mut boot_services), Err(Status::NOT_FOUND));
+    assert!(TEST_LOGGER.inner.lock().protocol.is_none());
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn deinit_debug_should_restore_defaults() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+    set_log_level_filter(DEBUG_ERROR);
+    LOGGER.set_level_prefix(true);
+    assert!(is_debug_initialized());
+
+    deinit_debug();
+    assert!(!is_debug_initialized());
+    assert_eq!(log_level_filter(), usize::MAX);
+    debugln!(DEBUG_INFO, "after {:}", "deinit");
+    assert!(take_captured_writes().is_empty());
+
+    init_debug(&mut capturing_boot_services()).unwrap();
+    take_captured_writes();
+    debugln!(DEBUG_INFO, "after {:}", "init");
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("after init\n"))]);
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn init_debug_with_protocol_should_install_protocol_directly() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    deinit_debug();
+    take_captured_writes();
+
+    let protocol = testing::mock_logger_protocol();
+    init_debug_with_protocol(protocol).unwrap();
+    assert!(is_debug_initialized());
+    debugln!(DEBUG_INFO, "no {:}", "boot services");
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("no boot services\n"))]);
+    assert_eq!(init_debug_with_protocol(protocol), Err(Status::ALREADY_STARTED));
+  }
+
+  #[test]
+  fn init_debug_with_protocol_should_validate_protocol() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    static OLD_LOGGER_INSTANCE: AdvancedLoggerProtocol = AdvancedLoggerProtocol {
+      signature: ADVANCED_LOGGER_PROTOCOL_SIGNATURE,
+      version: ADVANCED_LOGGER_PROTOCOL_VERSION - 1,
+      write_log: mock_discarding_write,
+    };
+    let as_ptr =
+      |protocol: &AdvancedLoggerProtocol| protocol as *const AdvancedLoggerProtocol as *mut AdvancedLoggerProtocol;
+
+    assert_eq!(TEST_LOGGER.init_with_protocol(core::ptr::null_mut()), Err(Status::INVALID_PARAMETER));
+    //the basic mock instance carries no signature.
+    assert_eq!(TEST_LOGGER.init_with_protocol(as_ptr(&ADVANCED_LOGGER_INSTANCE)), Err(Status::INVALID_PARAMETER));
+    assert_eq!(TEST_LOGGER.init_with_protocol(as_ptr(&OLD_LOGGER_INSTANCE)), Err(Status::INCOMPATIBLE_VERSION));
+    assert!(!TEST_LOGGER.is_initialized());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn init_should_reject_null_boot_services() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    assert_eq!(TEST_LOGGER.init(core::ptr::null_mut()), Err(Status::INVALID_PARAMETER));
+    assert!(!TEST_LOGGER.is_initialized());
+    assert_eq!(TEST_LOGGER.register_exit_boot_services_handler(core::ptr::null_mut()), Err(Status::INVALID_PARAMETER));
+    assert!(TEST_LOGGER.inner.lock().exit_boot_services_event.is_none());
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert_eq!(TEST_LOGGER.reinit(core::ptr::null_mut()), Err(Status::INVALID_PARAMETER));
+    assert!(!TEST_LOGGER.is_initialized());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn is_initialized_should_reflect_protocol_state() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    assert!(!TEST_LOGGER.is_initialized());
+
+    TEST_LOGGER.init(&mut mock_boot_services()).unwrap();
+    assert!(TEST_LOGGER.is_initialized());
+
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_protocol_not_found;
+    assert!(TEST_LOGGER.reinit(&mut boot_services).is_err());
+    assert!(!TEST_LOGGER.is_initialized());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn interleaved_levels_should_each_reach_protocol_at_their_own_level() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    {
+      let mut logger = TEST_LOGGER.inner.lock();
+      LevelWriter::new(&mut logger, DEBUG_INFO).write_str("info 1").unwrap();
+      LevelWriter::new(&mut logger, DEBUG_WARN).write_str("warn 1").unwrap();
+      LevelWriter::new(&mut logger, DEBUG_INFO).write_str("info 2").unwrap();
+      LevelWriter::new(&mut logger, DEBUG_WARN).write_str("warn 2").unwrap();
+    }
+    assert_eq!(
+      take_captured_writes(),
+      [
+        (DEBUG_INFO, String::from("info 1")),
+        (DEBUG_WARN, String::from("warn 1")),
+        (DEBUG_INFO, String::from("info 2")),
+        (DEBUG_WARN, String::from("warn 2")),
+      ]
+    );
+
+    let arg = "error";
+    TEST_LOGGER.log(DEBUG_ERROR, format_args!("{:} {:}", arg, 3));
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("{:} {:}", "verbose", 3));
+    TEST_LOGGER.log(DEBUG_ERROR, format_args!("{:} {:}", arg, 4));
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_ERROR, String::from("error 3")),
+        (DEBUG_VERBOSE, String::from("verbose 3")),
+        (DEBUG_ERROR, String::from("error 4"))
+      ]
+    );
+  }
+
+  #[cfg(feature = "log")]
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn log_crate_macros_should_log_at_translated_levels() {
+    use crate::{init_log, AdvancedLoggerLog};
+    use log::Log;
+
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+    init_log().unwrap();
+    assert!(init_log().is_err());
+
+    log::error!("error {:}", 1);
+    log::warn!("warn {:}", 2);
+    log::info!("info {:}", 3);
+    log::debug!("debug {:}", 4);
+    log::trace!("trace {:}", 5);
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_ERROR, String::from("error 1\n")),
+        (DEBUG_WARN, String::from("warn 2\n")),
+        (DEBUG_INFO, String::from("info 3\n")),
+        (DEBUG_VERBOSE, String::from("debug 4\ntrace 5\n")),
+      ]
+    );
+
+    let original = log_level_filter();
+    set_log_level_filter(DEBUG_ERROR);
+    assert!(AdvancedLoggerLog.enabled(&log::Metadata::builder().level(log::Level::Error).build()));
+    assert!(!AdvancedLoggerLog.enabled(&log::Metadata::builder().level(log::Level::Info).build()));
+    log::info!("filtered");
+    log::error!("not filtered");
+    set_log_level_filter(original);
+    assert_eq!(take_captured_output(), [(DEBUG_ERROR, String::from("not filtered\n"))]);
+  }
+
+  #[cfg(feature = "log")]
+  #[test]
+  fn log_level_conversions_should_round_trip_standard_levels() {
+    use crate::{log_to_uefi_level, uefi_level_to_log};
+
+    for (level, log_level) in [
+      (DEBUG_ERROR, log::Level::Error),
+      (DEBUG_WARN, log::Level::Warn),
+      (DEBUG_INFO, log::Level::Info),
+      (DEBUG_VERBOSE, log::Level::Debug),
+    ] {
+      assert_eq!(uefi_level_to_log(level), log_level);
+      assert_eq!(log_to_uefi_level(log_level), level);
+      assert_eq!(log_to_uefi_level(uefi_level_to_log(level)), level);
+    }
+    //lossy mappings.
+    assert_eq!(log_to_uefi_level(log::Level::Trace), DEBUG_VERBOSE);
+    assert_eq!(uefi_level_to_log(DEBUG_INIT), log::Level::Debug);
+    assert_eq!(uefi_level_to_log(DEBUG_INFO | DEBUG_ERROR), log::Level::Error);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn timestamp_should_prefix_each_log_call_once() {
+    fn mock_timestamp() -> u64 {
+      0x1234
+    }
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    let arg = "fragments";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("no timestamp with {:} {:}\n", arg, 1));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("no timestamp with fragments 1\n"))]);
+
+    TEST_LOGGER.set_timestamp_source(mock_timestamp);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("timestamp with {:} {:}\n", arg, 2));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("second line\n"));
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_INFO, String::from("[4660] timestamp with fragments 2\n")),
+        (DEBUG_WARN, String::from("[4660] second line\n"))
+      ]
+    );
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn init_with_guid_should_locate_protocol_by_custom_guid() {
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_custom_guid_protocol;
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+
+    //the standard GUID is not published by this mock.
+    assert_eq!(TEST_LOGGER.init(&mut boot_services), Err(Status::NOT_FOUND));
+
+    TEST_LOGGER.init_with_guid(&mut boot_services, CUSTOM_LOGGER_PROTOCOL_GUID).unwrap();
+    assert_eq!(TEST_LOGGER.inner.lock().protocol_guid, Some(CUSTOM_LOGGER_PROTOCOL_GUID));
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("custom guid\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("custom guid\n"))]);
+
+    //re-init should query the same custom GUID.
+    TEST_LOGGER.reinit(&mut boot_services).unwrap();
+    assert!(TEST_LOGGER.is_initialized());
+  }
+
+  thread_local! {
+    //number of calls to mock_locate_protocol_late that fail before the protocol is found, and the number of calls to
+    //count_retry.
+    static LOCATE_FAILURES: Cell<u32> = Cell::new(0);
+    static RETRIES: Cell<u32> = Cell::new(0);
+  }
+
+  // mock LocateProtocol() for a protocol that is installed after LOCATE_FAILURES calls.
+  extern "efiapi" fn mock_locate_protocol_late(
+    _protocol: *mut Guid,
+    _registration: *mut c_void,
+    interface: *mut *mut c_void,
+  ) -> Status {
+    if LOCATE_FAILURES.with(|failures| failures.replace(failures.get().saturating_sub(1))) > 0 {
+      return Status::NOT_FOUND;
+    }
+    unsafe { interface.write(testing::mock_logger_protocol()) };
+    Status::SUCCESS
+  }
+
+  fn count_retry() {
+    RETRIES.with(|retries| retries.set(retries.get() + 1));
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn init_retry_should_locate_late_protocol() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let mut boot_services = capturing_boot_services();
+    boot_services.locate_protocol = mock_locate_protocol_late;
+
+    //too few attempts fail with the last status.
+    LOCATE_FAILURES.with(|failures| failures.set(2));
+    assert_eq!(TEST_LOGGER.init_retry(&mut boot_services, 2, count_retry), Err(Status::NOT_FOUND));
+    assert_eq!(RETRIES.with(|retries| retries.take()), 1);
+    assert!(!TEST_LOGGER.is_initialized());
+
+    LOCATE_FAILURES.with(|failures| failures.set(2));
+    TEST_LOGGER.init_retry(&mut boot_services, 5, count_retry).unwrap();
+    assert_eq!(RETRIES.with(|retries| retries.take()), 2);
+    assert!(TEST_LOGGER.is_initialized());
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("located\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("located\n"))]);
+
+    //failures other than locating the protocol are not retried.
+    assert_eq!(TEST_LOGGER.init_retry(&mut boot_services, 5, count_retry), Err(Status::ALREADY_STARTED));
+    TEST_LOGGER.deinit();
+    assert_eq!(TEST_LOGGER.init_retry(core::ptr::null_mut(), 5, count_retry), Err(Status::INVALID_PARAMETER));
+    assert_eq!(RETRIES.with(|retries| retries.take()), 0);
+  }
+
+  #[cfg(feature = "early-buffer")]
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn output_before_init_should_be_replayed_after_init() {
+    use crate::EARLY_LOG_BUFFER_SIZE;
+
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let arg = "init";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("before {:} {:}\n", arg, 1));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("before {:} {:}\n", arg, 2));
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("before {:} {:}\n", arg, 3));
+    assert!(take_captured_writes().is_empty());
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_INFO, String::from("before init 1\n")),
+        (DEBUG_WARN, String::from("before init 2\n")),
+        (DEBUG_INFO, String::from("before init 3\n")),
+      ]
+    );
+    assert_eq!(TEST_LOGGER.early_buffer_dropped(), 0);
+
+    //output after init goes directly to the protocol, and a re-init does not replay anything.
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("after init\n"));
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("after init\n"))]);
+
+    //overflowing the buffer drops the oldest output. Once initialized, output is only buffered while offline if offline
+    //buffering is enabled.
+    TEST_LOGGER.set_offline_buffering(true);
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_protocol_not_found;
+    assert!(TEST_LOGGER.reinit(&mut boot_services).is_err());
+    let line = "x".repeat(EARLY_LOG_BUFFER_SIZE / 4);
+    for level in [DEBUG_INFO, DEBUG_WARN, DEBUG_INFO, DEBUG_WARN, DEBUG_ERROR] {
+      TEST_LOGGER.log(level, format_args!("{:}", line));
+    }
+    assert_eq!(TEST_LOGGER.early_buffer_dropped(), 2);
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    let levels: Vec<usize> = take_captured_output().iter().map(|(level, _)| *level).collect();
+    assert_eq!(levels, [DEBUG_INFO, DEBUG_WARN, DEBUG_ERROR]);
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn debug_enabled_should_follow_level_filter() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    assert!(TEST_LOGGER.level_enabled(DEBUG_VERBOSE));
+    TEST_LOGGER.set_log_level_filter(DEBUG_ERROR | DEBUG_WARN);
+    assert!(!TEST_LOGGER.level_enabled(DEBUG_VERBOSE));
+    assert!(!TEST_LOGGER.level_enabled(DEBUG_INFO));
+    assert!(TEST_LOGGER.level_enabled(DEBUG_WARN));
+    assert!(TEST_LOGGER.level_enabled(DEBUG_ERROR | DEBUG_INFO));
+
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let original = log_level_filter();
+    set_log_level_filter(!DEBUG_VERBOSE);
+    assert!(!debug_enabled!(DEBUG_VERBOSE));
+    assert!(debug_enabled!(DEBUG_INFO));
+    set_log_level_filter(DEBUG_VERBOSE);
+    assert!(debug_enabled!(DEBUG_VERBOSE));
+    assert!(!debug_enabled!(DEBUG_INFO));
+    set_log_level_filter(original);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn hexdump_should_format_canonical_lines() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    let mut data = [0u8; 36];
+    data[..20].copy_from_slice(b"Hello, World!\r\n\x00\x7f~ A");
+    for (index, byte) in data[20..].iter_mut().enumerate() {
+      *byte = 0x30 + index as u8;
+    }
+    TEST_LOGGER.hexdump(DEBUG_INFO, 0x1000, &data);
+
+    let writes = take_captured_output();
+    assert_eq!(writes.len(), 1);
+    assert_eq!(writes[0].0, DEBUG_INFO);
+    let lines: Vec<&str> = writes[0].1.lines().collect();
+    assert_eq!(
+      lines,
+      [
+        "00001000  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0d 0a 00  |Hello, World!...|",
+        "00001010  7f 7e 20 41 30 31 32 33  34 35 36 37 38 39 3a 3b  |.~ A0123456789:;|",
+        "00001020  3c 3d 3e 3f                                       |<=>?|",
+      ]
+    );
+    assert!(writes[0].1.ends_with('\n'));
+
+    //filtered and empty dumps produce no output.
+    TEST_LOGGER.set_log_level_filter(DEBUG_ERROR);
+    TEST_LOGGER.hexdump(DEBUG_INFO, 0, &data);
+    TEST_LOGGER.hexdump(DEBUG_ERROR, 0, &[]);
+    assert!(take_captured_writes().is_empty());
+  }
+
+  thread_local! {
+    static CREATED_EVENT: Cell<Option<(EventNotify, *mut c_void)>> = Cell::new(None);
+  }
+
+  extern "efiapi" fn mock_create_event(
+    event_type: u32,
+    _notify_tpl: Tpl,
+    notify_function: Option<EventNotify>,
+    notify_context: *mut c_void,
+    event: *mut Event,
+  ) -> Status {
+    assert_eq!(event_type, EVT_SIGNAL_EXIT_BOOT_SERVICES);
+    CREATED_EVENT.with(|created| created.set(Some((notify_function.unwrap(), notify_context))));
+    unsafe { event.write(1 as Event) };
+    Status::SUCCESS
+  }
+
+  extern "efiapi" fn mock_create_event_failure(
+    _event_type: u32,
+    _notify_tpl: Tpl,
+    _notify_function: Option<EventNotify>,
+    _notify_context: *mut c_void,
+    _event: *mut Event,
+  ) -> Status {
+    Status::OUT_OF_RESOURCES
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn exit_boot_services_should_invalidate_protocol() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let mut boot_services = capturing_boot_services();
+    boot_services.create_event = mock_create_event_failure;
+    TEST_LOGGER.init(&mut boot_services).unwrap();
+    assert_eq!(TEST_LOGGER.register_exit_boot_services_handler(&mut boot_services), Err(Status::OUT_OF_RESOURCES));
+
+    boot_services.create_event = mock_create_event;
+    TEST_LOGGER.register_exit_boot_services_handler(&mut boot_services).unwrap();
+    let (notify, context) = CREATED_EVENT.with(|created| created.take()).unwrap();
+
+    //registering again does not create a second event.
+    TEST_LOGGER.register_exit_boot_services_handler(&mut boot_services).unwrap();
+    assert!(CREATED_EVENT.with(|created| created.get()).is_none());
+
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("before exit\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("before exit\n"))]);
+
+    //simulate the ExitBootServices callback.
+    notify(1 as Event, context);
+    assert!(!TEST_LOGGER.is_initialized());
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("after exit\n"));
+    assert!(take_captured_writes().is_empty());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn log_writer_should_write_at_its_level() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    let mut info = writer(DEBUG_INFO);
+    let mut warn = writer(DEBUG_WARN);
+    write!(info, "{:<6}|{:>4}", "pages", 42).unwrap();
+    let out: &mut dyn Write = &mut warn;
+    out.write_str("dyn writer\n").unwrap();
+
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_INFO, String::from("pages |  42")), (DEBUG_WARN, String::from("dyn writer\n"))]
+    );
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn debug_assert_log_should_log_failed_conditions_and_continue() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    let count = 7;
+    debug_assert_log!(count < 10, DEBUG_WARN, "count is {:}", count);
+    assert!(take_captured_writes().is_empty());
+
+    let line = line!() + 1;
+    debug_assert_log!(count < 4, DEBUG_WARN, "count is {:}", count);
+    debug_assert_log!(count == 0, DEBUG_ERROR);
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_WARN, std::format!("Assertion failed: count < 4 at {:}:{:}: count is 7\n", file!(), line)),
+        (DEBUG_ERROR, std::format!("Assertion failed: count == 0 at {:}:{:}\n", file!(), line + 1)),
+      ]
+    );
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn level_scope_should_log_every_line_at_its_level() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    {
+      let mut warn = scope(DEBUG_WARN);
+      assert_eq!(warn.level(), DEBUG_WARN);
+      warn.line(format_args!("first {:}", 1));
+      writeln!(warn, "second {:}", 2).unwrap();
+      warn.line(format_args!("third"));
+    }
+    scope(DEBUG_INFO).line(format_args!("other"));
+
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_WARN, String::from("first 1\nsecond 2\nthird\n")), (DEBUG_INFO, String::from("other\n"))]
+    );
+  }
+
+  #[cfg(feature = "panic")]
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn log_panic_should_log_message_and_location_at_error_level() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+
+    //an uninitialized logger discards the panic output.
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(std::boxed::Box::new(|info| TEST_LOGGER.log_panic(info)));
+    let arg = "panic";
+    let result = std::panic::catch_unwind(|| panic!("synthetic {:} {:}", arg, 1));
+    assert!(result.is_err());
+    assert!(take_captured_writes().is_empty());
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    let line = line!() + 1;
+    let result = std::panic::catch_unwind(|| panic!("synthetic {:} {:}", arg, 2));
+    std::panic::set_hook(previous_hook);
+    assert!(result.is_err());
+
+    //the panic logged before init was discarded (rather than buffered), which init reports.
+    let mut output = take_captured_output();
+    if cfg!(not(feature = "early-buffer")) {
+      assert_eq!(output.remove(0), (DEBUG_WARN, String::from("1 messages were logged before init and discarded\n")));
+    }
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0].0, DEBUG_ERROR);
+    assert!(output[0].1.contains("synthetic panic 2"));
+    assert!(output[0].1.contains(&std::format!("{:}:{:}:", file!(), line)));
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn try_log_should_drop_output_when_lock_is_held() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    TEST_LOGGER.try_log(DEBUG_INFO, format_args!("unlocked\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("unlocked\n"))]);
+    assert_eq!(TEST_LOGGER.try_log_dropped(), 0);
+
+    let guard = TEST_LOGGER.inner.lock();
+    TEST_LOGGER.try_log(DEBUG_INFO, format_args!("locked\n"));
+    TEST_LOGGER.try_log(DEBUG_WARN, format_args!("locked\n"));
+    drop(guard);
+    assert!(take_captured_writes().is_empty());
+    assert_eq!(TEST_LOGGER.try_log_dropped(), 2);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn log_before_init_should_count_dropped_messages() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("before {:}\n", "init"));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("before {:}\n", "init"));
+    let expected = if cfg!(feature = "early-buffer") { 0 } else { 2 };
+    assert_eq!(TEST_LOGGER.dropped_messages(), expected);
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    take_captured_writes();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("after {:}\n", "init"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("after init\n"))]);
+    assert_eq!(TEST_LOGGER.dropped_messages(), expected);
+  }
+
+  #[cfg(not(feature = "early-buffer"))]
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn init_should_warn_once_about_messages_discarded_before_init() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("before {:}\n", "init"));
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("before {:}\n", "init"));
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_WARN, String::from("2 messages were logged before init and discarded\n"))]
+    );
+
+    //output discarded later (e.g. between ExitBootServices and a re-init) is not reported.
+    TEST_LOGGER.inner.lock().protocol = None;
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("dropped\n"));
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("after {:}\n", "init"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("after init\n"))]);
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn try_debug_macro_should_not_block() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    try_debugln!(DEBUG_INFO, "try {:}", "debugln");
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("try debugln\n"))]);
+
+    let dropped = try_debug_dropped();
+    let guard = LOGGER.inner.lock();
+    try_debug!(DEBUG_INFO, "try {:}\n", "debug");
+    drop(guard);
+    assert!(take_captured_writes().is_empty());
+    assert_eq!(try_debug_dropped(), dropped + 1);
+  }
+
+  // A mock protocol instance that reports the readable protocol revision.
+  static READABLE_LOGGER_INSTANCE: AdvancedLoggerReadableProtocol = AdvancedLoggerReadableProtocol {
+    protocol: AdvancedLoggerProtocol {
+      signature: 0,
+      version: ADVANCED_LOGGER_PROTOCOL_READ_VERSION,
+      write_log: mock_discarding_write,
+    },
+    read_log: mock_read_log,
+  };
+
+  const READABLE_LOG_CONTENTS: &[u8] = b"accumulated log\n";
+
+  extern "efiapi" fn mock_discarding_write(
+    _this: *const AdvancedLoggerProtocol,
+    _error_level: usize,
+    _buffer: *const u8,
+    _buffer_size: usize,
+  ) {
+  }
+
+  extern "efiapi" fn mock_read_log(this: *const AdvancedLoggerProtocol, buffer: *mut u8, size: *mut usize) -> Status {
+    assert_eq!(this, &READABLE_LOGGER_INSTANCE.protocol as *const AdvancedLoggerProtocol);
+    let size = unsafe { size.as_mut().unwrap() };
+    if *size < READABLE_LOG_CONTENTS.len() {
+      *size = READABLE_LOG_CONTENTS.len();
+      return Status::BUFFER_TOO_SMALL;
+    }
+    unsafe { core::ptr::copy_nonoverlapping(READABLE_LOG_CONTENTS.as_ptr(), buffer, READABLE_LOG_CONTENTS.len()) };
+    *size = READABLE_LOG_CONTENTS.len();
+    Status::SUCCESS
+  }
+
+  extern "efiapi" fn mock_locate_readable_protocol(
+    _protocol: *mut Guid,
+    _registration: *mut c_void,
+    interface: *mut *mut c_void,
+  ) -> Status {
+    unsafe {
+      interface.write(&READABLE_LOGGER_INSTANCE as *const AdvancedLoggerReadableProtocol as *mut c_void);
+    }
+    Status::SUCCESS
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn read_log_buffer_should_require_readable_protocol_version() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let mut buffer = [0u8; 64];
+    assert_eq!(TEST_LOGGER.read_log_buffer(&mut buffer), Err(Status::NOT_READY));
+
+    //the capturing mock reports an older protocol version.
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert_eq!(TEST_LOGGER.read_log_buffer(&mut buffer), Err(Status::UNSUPPORTED));
+
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_readable_protocol;
+    TEST_LOGGER.reinit(&mut boot_services).unwrap();
+    let size = TEST_LOGGER.read_log_buffer(&mut buffer).unwrap();
+    assert_eq!(&buffer[..size], READABLE_LOG_CONTENTS);
+    assert_eq!(TEST_LOGGER.read_log_buffer(&mut buffer[..4]), Err(Status::BUFFER_TOO_SMALL));
+  }
+
+  // A mock protocol instance that reports the flushable protocol revision.
+  static FLUSHABLE_LOGGER_INSTANCE: AdvancedLoggerFlushableProtocol = AdvancedLoggerFlushableProtocol {
+    readable: AdvancedLoggerReadableProtocol {
+      protocol: AdvancedLoggerProtocol {
+        signature: 0,
+        version: ADVANCED_LOGGER_PROTOCOL_FLUSH_VERSION,
+        write_log: mock_discarding_write,
+      },
+      read_log: mock_read_log,
+    },
+    flush: mock_flush,
+  };
+
+  static FLUSHES: AtomicUsize = AtomicUsize::new(0);
+
+  extern "efiapi" fn mock_flush(this: *const AdvancedLoggerProtocol) {
+    assert_eq!(this, &FLUSHABLE_LOGGER_INSTANCE.readable.protocol as *const AdvancedLoggerProtocol);
+    FLUSHES.fetch_add(1, Ordering::SeqCst);
+  }
+
+  extern "efiapi" fn mock_locate_flushable_protocol(
+    _protocol: *mut Guid,
+    _registration: *mut c_void,
+    interface: *mut *mut c_void,
+  ) -> Status {
+    unsafe {
+      interface.write(&FLUSHABLE_LOGGER_INSTANCE as *const AdvancedLoggerFlushableProtocol as *mut c_void);
+    }
+    Status::SUCCESS
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn flush_should_call_flushable_protocol() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_flushable_protocol;
+    TEST_LOGGER.init(&mut boot_services).unwrap();
+
+    let flushes = FLUSHES.load(Ordering::SeqCst);
+    TEST_LOGGER.flush();
+    assert_eq!(FLUSHES.load(Ordering::SeqCst), flushes + 1);
+  }
+
+  #[test]
+  fn flush_should_be_a_no_op_for_older_protocol_versions() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    //not initialized.
+    TEST_LOGGER.flush();
+
+    //the readable mock reports a version without the flush interface.
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_readable_protocol;
+    TEST_LOGGER.init(&mut boot_services).unwrap();
+    TEST_LOGGER.flush();
+
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.flush();
+    assert!(take_captured_writes().is_empty());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn crlf_should_rewrite_lone_line_feeds() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    let arg = "lines";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("off\n{:}\n", arg));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("off\nlines\n"))]);
+
+    TEST_LOGGER.set_crlf(true);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("\nsingle\nfragment\r\n{:}\n\n", arg));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("\r\nsingle\r\nfragment\r\nlines\r\n\r\n"))]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn crlf_should_handle_line_endings_split_across_fragments() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_crlf(true);
+
+    let mut logger = TEST_LOGGER.inner.lock();
+    let mut writer = LevelWriter::new(&mut logger, DEBUG_WARN);
+    //a "\r\n" split across fragments is not doubled up.
+    writer.write_str("split\r").unwrap();
+    writer.write_str("\nlone").unwrap();
+    //a lone '\n' at the start of a fragment is still rewritten.
+    writer.write_str("\n").unwrap();
+    writer.write_str("\n").unwrap();
+    assert_eq!(take_captured_output(), [(DEBUG_WARN, String::from("split\r\nlone\r\n\r\n"))]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn write_bytes_should_write_the_slice_unchanged() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_level_prefix(true);
+    TEST_LOGGER.set_max_line_len(4);
+
+    TEST_LOGGER.write_bytes(DEBUG_WARN, b"pre-formatted\n");
+    assert_eq!(take_captured_writes(), [(DEBUG_WARN, String::from("pre-formatted\n"))]);
+
+    TEST_LOGGER.set_log_level_filter(DEBUG_ERROR);
+    TEST_LOGGER.write_bytes(DEBUG_WARN, b"filtered\n");
+    assert!(take_captured_writes().is_empty());
+
+    TEST_LOGGER.set_crlf(true);
+    TEST_LOGGER.write_bytes(DEBUG_ERROR, b"line\n");
+    assert_eq!(take_captured_output(), [(DEBUG_ERROR, String::from("line\r\n"))]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn column_writer_should_write_padded_rows() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    let mut columns = ColumnWriter::new(DEBUG_INFO, &[6, 12, 4]);
+    for (name, value, status) in [("rax", 0x1234u64, "ok"), ("rflags", 0x123456789abc, "stale")] {
+      columns.field(name).unwrap();
+      columns.field(format_args!("{:#x}", value)).unwrap();
+      columns.field(status).unwrap();
+      columns.end_row().unwrap();
+    }
     assert_eq!(
-      LOGGER.inner.lock().protocol.unwrap() as *const AdvancedLoggerProtocol,
-      &ADVANCED_LOGGER_INSTANCE as *const AdvancedLoggerProtocol
+      take_captured_output(),
+      [(DEBUG_INFO, String::from("rax    0x1234       ok\nrflags 0x1234567... s...\n"))]
     );
-    assert_eq!(LOGGER.inner.lock().level, DEBUG_INFO);
+  }
 
-    debugln!(DEBUG_INIT, "This is a DEBUG_INIT test.");
-    debugln!(DEBUG_WARN, "This is a {:} test.", "DEBUG_WARN");
-    debug!(DEBUG_INFO, "This {:} a {:} test.\n", "is", "DEBUG_INFO");
-    debug!(DEBUG_VERBOSE, "This {:} {:} {:} test.\n", "is", "a", "DEBUG_VERBOSE");
-    debug!(DEBUG_ERROR, "{:}", "This is a DEBUG_ERROR test.\n");
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn line_buffered_mode_should_write_each_log_call_once() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_level_prefix(true);
+
+    let (first, second, third) = ("one", 2, "three");
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("{:}, {:}, {:}\n", first, second, third));
+    let unbuffered = take_captured_writes();
+    assert!(unbuffered.len() > 1);
+
+    TEST_LOGGER.set_line_buffered(true);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("{:}, {:}, {:}\n", first, second, third));
+    let buffered = take_captured_writes();
+    assert_eq!(buffered, [(DEBUG_INFO, String::from("[INFO] one, 2, three\n"))]);
+    assert_eq!(buffered[0].1, unbuffered.into_iter().map(|(_, str)| str).collect::<String>());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn line_buffered_mode_should_write_when_buffer_fills() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_line_buffered(true);
+
+    let long = "x".repeat(LINE_BUFFER_SIZE - 2);
+    let too_long = "y".repeat(LINE_BUFFER_SIZE + 1);
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("{:}{:}{:}\n", long, "abc", too_long));
+    //the buffer is written each time it fills, and the rest of the call is still buffered.
+    let writes: Vec<String> = take_captured_writes().into_iter().map(|(_, str)| str).collect();
+    assert_eq!(writes, [long + "ab", String::from("c") + &too_long[..LINE_BUFFER_SIZE - 1], String::from("yy\n")]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn line_buffered_mode_should_not_split_characters() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_line_buffered(true);
+
+    //the buffer fills one byte into the two-byte 'é', which is deferred to the next write. The capturing mock rejects
+    //writes that are not valid UTF-8.
+    let ascii = "x".repeat(LINE_BUFFER_SIZE - 1);
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("{:}{:}\n", ascii, "\u{e9}\u{e9}"));
+    let writes: Vec<String> = take_captured_writes().into_iter().map(|(_, str)| str).collect();
+    assert_eq!(writes, [ascii, String::from("\u{e9}\u{e9}\n")]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn max_line_len_should_truncate_long_log_calls() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_max_line_len(16);
+
+    let arg = "a long argument that does not fit";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("value: {:} and more {:}\n", arg, arg));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("value: a long ar...<truncated>\n"))]);
+
+    //the count is reset for each log call.
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("{:}\n", arg));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("a long argument ...<truncated>\n"))]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn writes_longer_than_max_chunk_should_be_split_in_order() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    //the default limit applies to large slices.
+    let large: Vec<u8> = (0..DEFAULT_MAX_CHUNK * 2 + 100).map(|index| b'a' + (index % 26) as u8).collect();
+    TEST_LOGGER.write_bytes(DEBUG_INFO, &large);
+    let writes = testing::take_captured_writes();
+    assert_eq!(
+      writes.iter().map(|(_, bytes)| bytes.len()).collect::<Vec<_>>(),
+      [DEFAULT_MAX_CHUNK, DEFAULT_MAX_CHUNK, 100]
+    );
+    assert!(writes.iter().all(|(level, _)| *level == DEBUG_INFO));
+    assert_eq!(writes.into_iter().flat_map(|(_, bytes)| bytes).collect::<Vec<_>>(), large);
+
+    TEST_LOGGER.set_max_chunk(4);
+    TEST_LOGGER.write_bytes(DEBUG_WARN, b"0123456789");
+    assert_eq!(
+      take_captured_writes(),
+      [(DEBUG_WARN, String::from("0123")), (DEBUG_WARN, String::from("4567")), (DEBUG_WARN, String::from("89"))]
+    );
+
+    //writes within the limit are passed through in a single call.
+    TEST_LOGGER.write_bytes(DEBUG_INFO, b"abcd");
+    assert_eq!(take_captured_writes(), [(DEBUG_INFO, String::from("abcd"))]);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn max_line_len_should_not_truncate_short_log_calls() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_max_line_len(16);
+
+    let arg = "short";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("value: {:}\n", arg));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("value: short\n"))]);
+  }
+
+  #[test]
+  fn level_name_should_map_standard_levels() {
+    assert_eq!(level_name(DEBUG_INIT), "INIT");
+    assert_eq!(level_name(DEBUG_WARN), "WARN");
+    assert_eq!(level_name(DEBUG_INFO), "INFO");
+    assert_eq!(level_name(DEBUG_VERBOSE), "VERBOSE");
+    assert_eq!(level_name(DEBUG_ERROR), "ERROR");
+    assert_eq!(level_name(0x4), "UNKNOWN");
+    assert_eq!(level_name(DEBUG_ERROR | DEBUG_INFO), "UNKNOWN");
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn custom_levels_should_be_named_once_registered() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_level_prefix(true);
+    assert_eq!(TEST_LOGGER.level_name(0x100), None);
+
+    assert!(TEST_LOGGER.register_custom_level(0x100, "USB"));
+    assert_eq!(TEST_LOGGER.level_name(0x100), Some("USB"));
+    assert!(TEST_LOGGER.register_custom_level(0x100, "XHCI"));
+    assert_eq!(TEST_LOGGER.level_name(0x100), Some("XHCI"));
+    //standard levels cannot be renamed.
+    assert!(!TEST_LOGGER.register_custom_level(DEBUG_INFO, "NOT INFO"));
+    assert_eq!(TEST_LOGGER.level_name(DEBUG_INFO), Some("INFO"));
+
+    let arg = "message";
+    TEST_LOGGER.log(0x100, format_args!("{:}\n", arg));
+    TEST_LOGGER.log(0x200, format_args!("{:}\n", arg));
+    assert_eq!(
+      take_captured_output(),
+      [(0x100, String::from("[XHCI] message\n")), (0x200, String::from("[0x200] message\n"))]
+    );
+  }
+
+  #[test]
+  fn register_custom_level_should_reject_registration_when_full() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    for index in 0..MAX_CUSTOM_LEVELS {
+      assert!(TEST_LOGGER.register_custom_level(0x100 << index, "CUSTOM"));
+    }
+    assert!(!TEST_LOGGER.register_custom_level(0x100 << MAX_CUSTOM_LEVELS, "OVERFLOW"));
+    assert_eq!(TEST_LOGGER.level_name(0x100 << MAX_CUSTOM_LEVELS), None);
+    //an existing registration can still be renamed.
+    assert!(TEST_LOGGER.register_custom_level(0x100, "RENAMED"));
+    assert_eq!(TEST_LOGGER.level_name(0x100), Some("RENAMED"));
+  }
+
+  #[test]
+  fn levels_should_yield_standard_then_custom_levels_in_order() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let standard = [
+      (DEBUG_INIT, "INIT"),
+      (DEBUG_WARN, "WARN"),
+      (DEBUG_INFO, "INFO"),
+      (DEBUG_VERBOSE, "VERBOSE"),
+      (DEBUG_ERROR, "ERROR"),
+    ];
+    assert_eq!(TEST_LOGGER.levels().collect::<Vec<_>>(), standard);
+
+    assert!(TEST_LOGGER.register_custom_level(0x200, "XHCI"));
+    assert!(TEST_LOGGER.register_custom_level(0x100, "USB"));
+    //renaming a level keeps its position.
+    assert!(TEST_LOGGER.register_custom_level(0x200, "EHCI"));
+    let levels: Vec<_> = TEST_LOGGER.levels().collect();
+    assert_eq!(levels[..standard.len()], standard);
+    assert_eq!(levels[standard.len()..], [(0x200, "EHCI"), (0x100, "USB")]);
+
+    //every yielded level round-trips through level_name.
+    for (level, name) in levels {
+      assert_eq!(TEST_LOGGER.level_name(level), Some(name));
+    }
+
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    assert_eq!(standard_levels().take(standard.len()).collect::<Vec<_>>(), standard);
+  }
+
+  #[test]
+  fn level_name_should_map_registered_custom_levels() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    assert_eq!(level_name(0x08000000), "UNKNOWN");
+    assert!(register_custom_level(0x08000000, "PLATFORM"));
+    assert_eq!(level_name(0x08000000), "PLATFORM");
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn severity_prefix_should_precede_each_log_call() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_severity_prefix(true);
+
+    for level in [DEBUG_ERROR, DEBUG_WARN, DEBUG_INIT, DEBUG_INFO, DEBUG_VERBOSE, 0x4] {
+      TEST_LOGGER.log(level, format_args!("{:}\n", "message"));
+    }
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_ERROR, String::from("<3>message\n")),
+        (DEBUG_WARN, String::from("<4>message\n")),
+        (DEBUG_INIT, String::from("<5>message\n")),
+        (DEBUG_INFO, String::from("<6>message\n")),
+        (DEBUG_VERBOSE, String::from("<7>message\n")),
+        (0x4, String::from("<7>message\n")),
+      ]
+    );
+
+    //the severity precedes the level name, and the map can be replaced.
+    TEST_LOGGER.set_level_prefix(true);
+    TEST_LOGGER.set_severity_map(&[(DEBUG_ERROR | DEBUG_WARN, 2), (DEBUG_INFO, 5)]);
+    for level in [DEBUG_WARN, DEBUG_INFO] {
+      TEST_LOGGER.log(level, format_args!("{:}\n", "message"));
+    }
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_WARN, String::from("<2>[WARN] message\n")), (DEBUG_INFO, String::from("<5>[INFO] message\n"))]
+    );
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn level_prefix_should_precede_each_log_call() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_level_prefix(true);
+
+    let arg = "message";
+    for level in [DEBUG_INIT, DEBUG_WARN, DEBUG_INFO, DEBUG_VERBOSE, DEBUG_ERROR, 0x4] {
+      TEST_LOGGER.log(level, format_args!("{:}\n", arg));
+    }
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_INIT, String::from("[INIT] message\n")),
+        (DEBUG_WARN, String::from("[WARN] message\n")),
+        (DEBUG_INFO, String::from("[INFO] message\n")),
+        (DEBUG_VERBOSE, String::from("[VERBOSE] message\n")),
+        (DEBUG_ERROR, String::from("[ERROR] message\n")),
+        (0x4, String::from("[0x4] message\n")),
+      ]
+    );
+
+    TEST_LOGGER.set_level_prefix(false);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("{:}\n", arg));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("message\n"))]);
+  }
+
+  // trivial non-spinning RawMutex; contention is a test failure.
+  struct FlagLock(AtomicBool);
+
+  unsafe impl RawMutex for FlagLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = FlagLock(AtomicBool::new(false));
+
+    fn lock(&self) {
+      assert!(self.try_lock(), "lock is already held");
+    }
+
+    fn try_lock(&self) -> bool {
+      !self.0.swap(true, Ordering::Acquire)
+    }
+
+    unsafe fn unlock(&self) {
+      self.0.store(false, Ordering::Release);
+    }
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn logger_should_use_custom_lock() {
+    static TEST_LOGGER: LockedAdvancedLogger<FlagLock> = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("custom {:}\n", "lock"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("custom lock\n"))]);
+
+    //non-blocking log calls are dropped while the custom lock is held, and succeed once it is released.
+    let guard = TEST_LOGGER.inner.lock();
+    TEST_LOGGER.try_log(DEBUG_INFO, format_args!("dropped\n"));
+    assert_eq!(TEST_LOGGER.try_log_dropped(), 1);
+    drop(guard);
+    TEST_LOGGER.try_log(DEBUG_INFO, format_args!("written\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("written\n"))]);
+  }
+
+  #[cfg(feature = "max_level_warn")]
+  #[test]
+  fn max_level_feature_should_strip_calls_above_threshold() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    //the arguments of stripped calls are not evaluated.
+    fn unreachable_arg() -> &'static str {
+      panic!("argument of a stripped log call was evaluated");
+    }
+    debugln!(DEBUG_INFO, "{:}", unreachable_arg());
+    debug!(DEBUG_VERBOSE, "{:}\n", unreachable_arg());
+    debugln!(DEBUG_INIT, "{:}", unreachable_arg());
+    try_debugln!(DEBUG_INFO, "{:}", unreachable_arg());
+    debug_kv!(DEBUG_INFO, "event", value = unreachable_arg());
+    hexdump!(DEBUG_VERBOSE, 0, unreachable_arg().as_bytes());
+    debug_assert_log!(unreachable_arg().is_empty(), DEBUG_INFO);
+    assert!(!debug_enabled!(DEBUG_INFO));
+    assert!(take_captured_output().is_empty());
+
+    debugln!(DEBUG_WARN, "{:}", "kept");
+    debugln!(DEBUG_ERROR, "{:}", "kept");
+    //custom levels are not affected by the standard level threshold.
+    debugln!(0x100, "{:}", "kept");
+    assert!(debug_enabled!(DEBUG_WARN));
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_WARN, String::from("kept\n")), (DEBUG_ERROR, String::from("kept\n")), (0x100, String::from("kept\n"))]
+    );
+
+    //log crate records are held to the same threshold.
+    #[cfg(feature = "log")]
+    {
+      use log::Log;
+      assert_eq!(crate::static_log_level_filter(), log::LevelFilter::Warn);
+      let info = log::Metadata::builder().level(log::Level::Info).build();
+      assert!(!crate::AdvancedLoggerLog.enabled(&info));
+      crate::AdvancedLoggerLog
+        .log(&log::Record::builder().level(log::Level::Info).args(format_args!("stripped")).build());
+      crate::AdvancedLoggerLog.log(&log::Record::builder().level(log::Level::Warn).args(format_args!("kept")).build());
+      assert_eq!(take_captured_output(), [(DEBUG_WARN, String::from("kept\n"))]);
+    }
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn protocol_version_should_report_located_protocol_revision() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    assert_eq!(TEST_LOGGER.protocol_version(), None);
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert_eq!(TEST_LOGGER.protocol_version(), Some(ADVANCED_LOGGER_PROTOCOL_VERSION));
+
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_readable_protocol;
+    TEST_LOGGER.reinit(&mut boot_services).unwrap();
+    assert_eq!(TEST_LOGGER.protocol_version(), Some(ADVANCED_LOGGER_PROTOCOL_READ_VERSION));
+
+    boot_services.locate_protocol = mock_locate_flushable_protocol;
+    TEST_LOGGER.reinit(&mut boot_services).unwrap();
+    assert_eq!(TEST_LOGGER.protocol_version(), Some(ADVANCED_LOGGER_PROTOCOL_FLUSH_VERSION));
+
+    TEST_LOGGER.deinit();
+    assert_eq!(TEST_LOGGER.protocol_version(), None);
+  }
+
+  #[cfg(feature = "early-buffer")]
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn offline_buffering_should_replay_output_logged_while_offline() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    let mut not_found = mock_boot_services();
+    not_found.locate_protocol = mock_locate_protocol_not_found;
+
+    //by default, output logged once the protocol is lost after init is discarded.
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    assert!(TEST_LOGGER.reinit(&mut not_found).is_err());
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("offline {:}\n", 1));
+    assert_eq!(TEST_LOGGER.dropped_messages(), 1);
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    assert!(take_captured_writes().is_empty());
+
+    //with offline buffering, it is replayed on the next successful init.
+    TEST_LOGGER.set_offline_buffering(true);
+    assert!(TEST_LOGGER.reinit(&mut not_found).is_err());
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("offline {:}\n", 2));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("offline {:}\n", 3));
+    assert!(take_captured_writes().is_empty());
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_INFO, String::from("offline 2\n")), (DEBUG_WARN, String::from("offline 3\n"))]
+    );
+    assert_eq!(TEST_LOGGER.dropped_messages(), 1);
+
+    //the same applies when the protocol is invalidated by ExitBootServices.
+    let mut boot_services = capturing_boot_services();
+    boot_services.create_event = mock_create_event;
+    TEST_LOGGER.register_exit_boot_services_handler(&mut boot_services).unwrap();
+    let (notify, context) = CREATED_EVENT.with(|created| created.take()).unwrap();
+    notify(1 as Event, context);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("offline {:}\n", 4));
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("offline 4\n"))]);
+  }
+
+  #[cfg(feature = "early-buffer")]
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn output_between_deinit_and_reinit_should_be_replayed() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.set_offline_buffering(true);
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.deinit();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("handoff {:}\n", "line"));
+    assert!(take_captured_writes().is_empty());
+
+    TEST_LOGGER.reinit(&mut capturing_boot_services()).unwrap();
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("handoff line\n"))]);
+    assert_eq!(TEST_LOGGER.dropped_messages(), 0);
+  }
+
+  #[cfg(feature = "disabled")]
+  #[test]
+  fn disabled_feature_should_discard_all_output() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    extern "efiapi" fn mock_locate_protocol_unexpected(
+      _protocol: *mut Guid,
+      _registration: *mut c_void,
+      _interface: *mut *mut c_void,
+    ) -> Status {
+      panic!("the protocol should not be located");
+    }
+    let mut boot_services = capturing_boot_services();
+    boot_services.locate_protocol = mock_locate_protocol_unexpected;
+    assert_eq!(reinit_debug(&mut boot_services), Ok(()));
+    assert_eq!(crate::init_debug_with_guid(&mut boot_services, ADVANCED_LOGGER_PROTOCOL_GUID), Ok(()));
+    assert_eq!(crate::register_exit_boot_services_handler(&mut boot_services), Ok(()));
+    assert!(!is_debug_initialized());
+
+    debugln!(DEBUG_ERROR, "discarded {:}", 1);
+    crate::_log(DEBUG_ERROR, format_args!("discarded {:}\n", 2));
+    crate::write_bytes(DEBUG_ERROR, b"discarded\n");
+    crate::hexdump(DEBUG_ERROR, 0, &[0u8; 4]);
+    writeln!(writer(DEBUG_ERROR), "discarded").unwrap();
+    assert!(take_captured_writes().is_empty());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn filtered_calls_should_not_acquire_the_lock() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_log_level_filter(!DEBUG_VERBOSE);
+
+    //with the lock held, filtered calls from another thread should still return.
+    let guard = TEST_LOGGER.inner.lock();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+      TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("filtered\n"));
+      TEST_LOGGER.try_log(DEBUG_VERBOSE, format_args!("filtered\n"));
+      TEST_LOGGER.write_bytes(DEBUG_VERBOSE, b"filtered\n");
+      TEST_LOGGER.hexdump(DEBUG_VERBOSE, 0, &[0u8; 4]);
+      sender.send(TEST_LOGGER.level_enabled(DEBUG_VERBOSE)).unwrap();
+    });
+    assert_eq!(receiver.recv_timeout(std::time::Duration::from_secs(10)), Ok(false));
+    drop(guard);
+
+    //filtered calls are not counted as dropped.
+    assert_eq!(TEST_LOGGER.try_log_dropped(), 0);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("not filtered\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("not filtered\n"))]);
+
+    //the cached filter is restored along with the rest of the state.
+    TEST_LOGGER.deinit();
+    assert!(TEST_LOGGER.level_enabled(DEBUG_VERBOSE));
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn debug_static_should_write_literal_bytes_unformatted() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    //the literal is written in a single write, and braces are not interpreted as they would be by core::fmt.
+    debug_static!(DEBUG_INFO, "static {{braces}} ");
+    debugln_static!(DEBUG_WARN, "static line");
+    assert_eq!(
+      take_captured_writes(),
+      [(DEBUG_INFO, String::from("static {{braces}} ")), (DEBUG_WARN, String::from("static line\n"))]
+    );
+
+    //the level filter and prefixes still apply.
+    let original = log_level_filter();
+    set_log_level_filter(!DEBUG_VERBOSE);
+    debugln_static!(DEBUG_VERBOSE, "filtered");
+    assert!(take_captured_writes().is_empty());
+    set_log_level_filter(original);
+    set_level_prefix(true);
+    debugln_static!(DEBUG_ERROR, "prefixed");
+    set_level_prefix(false);
+    assert_eq!(take_captured_output(), [(DEBUG_ERROR, String::from("[ERROR] prefixed\n"))]);
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn debug_escaped_should_escape_non_printable_bytes() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+
+    debug_escaped!(DEBUG_INFO, b"Printable ~ text!");
+    debug_escaped!(DEBUG_INFO, b"\x00\x07\t\r\n\x1b\x7f");
+    debug_escaped!(DEBUG_WARN, &[0x80, b'a', 0xc3, 0xa9, 0xff]);
+    debug_escaped!(DEBUG_INFO, br"C:\path\x41");
+    debug_escaped!(DEBUG_INFO, &[]);
+    assert_eq!(
+      take_captured_output(),
+      [
+        (DEBUG_INFO, String::from("Printable ~ text!\n\\x00\\x07\\x09\\x0d\\x0a\\x1b\\x7f\n")),
+        (DEBUG_WARN, String::from("\\x80a\\xc3\\xa9\\xff\n")),
+        (DEBUG_INFO, String::from("C:\\\\path\\\\x41\n\n")),
+      ]
+    );
+
+    //the level filter applies.
+    let original = log_level_filter();
+    set_log_level_filter(!DEBUG_VERBOSE);
+    debug_escaped!(DEBUG_VERBOSE, b"filtered");
+    assert!(take_captured_writes().is_empty());
+    set_log_level_filter(original);
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn default_level_macros_should_log_at_default_level() {
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+    assert_eq!(default_level(), DEBUG_INFO);
+
+    debug_default!("default {:} ", 1);
+    debugln_default!();
+    set_default_level(DEBUG_VERBOSE);
+    assert_eq!(default_level(), DEBUG_VERBOSE);
+    debugln_default!("default {:}", 2);
+    debugln_default!("literal");
+    set_default_level(DEBUG_INFO);
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_INFO, String::from("default 1 \n")), (DEBUG_VERBOSE, String::from("default 2\nliteral\n"))]
+    );
+
+    let logger: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    logger.set_default_level(DEBUG_WARN);
+    logger.deinit();
+    assert_eq!(logger.default_level(), DEBUG_INFO);
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn sequence_numbers_should_count_log_calls() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("unnumbered\n"));
+    TEST_LOGGER.set_sequence_numbers(true);
+    for index in 0..3 {
+      TEST_LOGGER.log(DEBUG_INFO, format_args!("line {:}\n", index));
+    }
+    //a call written as several fragments advances the counter once.
+    let (first, second) = ("multi", "fragment");
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("{:} {:} {:}\n", first, second, 3));
+    let writes = take_captured_writes();
+    assert!(writes.len() > 5);
+    assert_eq!(
+      take_writes_text(writes),
+      "unnumbered\n#00001 line 0\n#00002 line 1\n#00003 line 2\n#00004 multi fragment 3\n"
+    );
+
+    //numbers wrap at the configured width; filtered calls are not numbered.
+    TEST_LOGGER.set_sequence_width(1);
+    TEST_LOGGER.set_log_level_filter(!DEBUG_VERBOSE);
+    TEST_LOGGER.log(DEBUG_VERBOSE, format_args!("filtered\n"));
+    for _ in 0..6 {
+      TEST_LOGGER.log(DEBUG_INFO, format_args!("wrap\n"));
+    }
+    assert_eq!(take_writes_text(take_captured_writes()), "#5 wrap\n#6 wrap\n#7 wrap\n#8 wrap\n#9 wrap\n#0 wrap\n");
+  }
+
+  // concatenates the text of the given writes.
+  fn take_writes_text(writes: Vec<(usize, String)>) -> String {
+    writes.into_iter().map(|(_, text)| text).collect()
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn log_checked_should_report_why_output_was_not_written() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    // Display implementation that always fails.
+    struct FailingDisplay;
+    impl core::fmt::Display for FailingDisplay {
+      fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Err(core::fmt::Error)
+      }
+    }
+
+    //before init, output is discarded (unless it is held in the early buffer).
+    #[cfg(not(feature = "early-buffer"))]
+    assert_eq!(TEST_LOGGER.log_checked(DEBUG_INFO, format_args!("early\n")), Err(LogError::Uninitialized));
+
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    take_captured_writes();
+    assert_eq!(TEST_LOGGER.log_checked(DEBUG_INFO, format_args!("written {:}\n", 1)), Ok(()));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("written 1\n"))]);
+
+    TEST_LOGGER.set_log_level_filter(!DEBUG_VERBOSE);
+    assert_eq!(TEST_LOGGER.log_checked(DEBUG_VERBOSE, format_args!("filtered\n")), Err(LogError::Filtered));
+    assert_eq!(
+      TEST_LOGGER.log_checked(DEBUG_INFO, format_args!("failing {:}\n", FailingDisplay)),
+      Err(LogError::WriteFailed(Status::ABORTED))
+    );
+    take_captured_writes();
+
+    //once the protocol is gone (as after ExitBootServices), output is discarded.
+    TEST_LOGGER.inner.lock().protocol = None;
+    assert_eq!(TEST_LOGGER.log_checked(DEBUG_INFO, format_args!("late\n")), Err(LogError::Uninitialized));
+    assert!(take_captured_writes().is_empty());
+    assert_eq!(TEST_LOGGER.dropped_messages(), if cfg!(feature = "early-buffer") { 2 } else { 3 });
+  }
+
+  #[cfg_attr(
+    any(
+      feature = "max_level_off",
+      feature = "max_level_error",
+      feature = "max_level_warn",
+      feature = "max_level_info"
+    ),
+    ignore = "logs at levels stripped by the max_level_* features"
+  )]
+  #[test]
+  fn logger_instances_should_filter_independently() {
+    static STORAGE_LOG: LockedAdvancedLogger = LockedAdvancedLogger::named("storage");
+    static NETWORK_LOG: LockedAdvancedLogger = LockedAdvancedLogger::named("net");
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    deinit_debug();
+    assert_eq!(STORAGE_LOG.init_shared(), Err(Status::NOT_READY));
+    reinit_debug(&mut capturing_boot_services()).unwrap();
+    take_captured_writes();
+
+    //the instances share the global logger's protocol, but not its configuration.
+    STORAGE_LOG.init_shared().unwrap();
+    NETWORK_LOG.init_shared().unwrap();
+    assert_eq!(NETWORK_LOG.init_shared(), Err(Status::ALREADY_STARTED));
+    set_log_level_filter(DEBUG_ERROR);
+    STORAGE_LOG.set_log_level_filter(DEBUG_INFO | DEBUG_ERROR);
+    NETWORK_LOG.set_log_level_filter(DEBUG_WARN);
+    NETWORK_LOG.set_default_level(DEBUG_WARN);
+
+    debugln!(DEBUG_INFO, "global {:}", "info");
+    debugln_to!(STORAGE_LOG, DEBUG_INFO, "{:} disks", 2);
+    debugln_to!(STORAGE_LOG, DEBUG_WARN, "storage warn");
+    debugln_to!(NETWORK_LOG, DEBUG_INFO, "net info");
+    debugln_to!(NETWORK_LOG, NETWORK_LOG.default_level(), "link {:}", "down");
+    assert!(STORAGE_LOG.level_enabled(DEBUG_INFO) && !NETWORK_LOG.level_enabled(DEBUG_INFO));
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_INFO, String::from("storage: 2 disks\n")), (DEBUG_WARN, String::from("net: link down\n"))]
+    );
+
+    STORAGE_LOG.deinit();
+    NETWORK_LOG.deinit();
+    deinit_debug();
+  }
+
+  thread_local! {
+    static TICKS: Cell<u64> = Cell::new(0);
+  }
+
+  fn mock_incrementing_timestamp() -> u64 {
+    TICKS.with(|ticks| ticks.replace(ticks.get() + 1))
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn span_should_log_start_and_end_with_elapsed_ticks() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+
+    //without a timestamp source, the duration is omitted.
+    drop(TEST_LOGGER.span(DEBUG_INFO, "untimed"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("untimed: start\nuntimed: end\n"))]);
+
+    //the start line's timestamp prefix reads tick 0, the span runs from tick 1 to 4, and the end line reads tick 5.
+    TEST_LOGGER.set_timestamp_source(mock_incrementing_timestamp);
+    let span = TEST_LOGGER.span(DEBUG_WARN, "timed");
+    for _ in 0..2 {
+      mock_incrementing_timestamp();
+    }
+    drop(span);
+    assert_eq!(take_captured_output(), [(DEBUG_WARN, String::from("[0] timed: start\n[5] timed: end (3)\n"))]);
+
+    //spans at filtered levels log nothing.
+    TEST_LOGGER.set_log_level_filter(DEBUG_ERROR);
+    drop(TEST_LOGGER.span(DEBUG_INFO, "filtered"));
+    assert!(take_captured_writes().is_empty());
+  }
+
+  #[cfg_attr(feature = "disabled", ignore = "needs the AdvancedLogger protocol, which disabled builds never use")]
+  #[test]
+  fn wrap_width_should_break_long_lines_at_word_boundaries() {
+    static TEST_LOGGER: LockedAdvancedLogger = LockedAdvancedLogger::new();
+    TEST_LOGGER.init(&mut capturing_boot_services()).unwrap();
+    TEST_LOGGER.set_wrap_width(10);
+
+    //lines within the width are unchanged.
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("short {:}\n", "one"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("short one\n"))]);
+
+    //the column is tracked across fragments, and words longer than the width are broken where the line fills.
+    let arg = "wrapped";
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("this line {:} over{:} and abcdefghijklmnop\n", arg, "flows"));
+    assert_eq!(
+      take_captured_output(),
+      [(DEBUG_INFO, String::from("this line\nwrapped\noverflows\nand\nabcdefghij\nklmnop\n"))]
+    );
+
+    //the column carries over from one log call to the next.
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("12345 "));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("abc def\n"));
+    TEST_LOGGER.log(DEBUG_WARN, format_args!("next\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_WARN, String::from("12345 abc\ndef\nnext\n"))]);
+
+    //a held-back word is written whole, even when it is longer than the pieces it is copied out in.
+    TEST_LOGGER.set_wrap_width(100);
+    let word = "\u{e9}".repeat(40);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("{:} x\n", word));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, word + " x\n")]);
+
+    TEST_LOGGER.set_wrap_width(0);
+    TEST_LOGGER.log(DEBUG_INFO, format_args!("this line is not wrapped\n"));
+    assert_eq!(take_captured_output(), [(DEBUG_INFO, String::from("this line is not wrapped\n"))]);
   }
 }
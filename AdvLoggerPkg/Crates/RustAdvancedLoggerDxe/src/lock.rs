@@ -0,0 +1,128 @@
+//! Logger Lock
+//!
+//! The [`RawMutex`] trait used to synchronize access to the logger state, and the crate-internal mutex built on it.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+use core::{
+  cell::UnsafeCell,
+  fmt,
+  ops::{Deref, DerefMut},
+};
+
+/// A lock without associated data, used to serialize access to the state of a logger instance.
+///
+/// Logger instances use `spin::Mutex<()>` by default. Other implementations allow e.g. a lock that raises the TPL or
+/// disables interrupts while it is held, so that logging from an interrupt handler cannot deadlock, or a no-op lock
+/// where the caller guarantees that the logger is only used from a single processor.
+///
+/// The boot services allocator re-exports this trait as the lock of its allocator instances, so one implementation
+/// serves both crates.
+///
+/// ## Safety
+///
+/// Once `lock` has returned, or `try_lock` has returned true, the lock must not be acquired again (by any caller the
+/// implementation synchronizes between) until `unlock` is called. An implementation that relies on the caller for
+/// exclusion (e.g. one that does not synchronize between processors) must document the conditions under which it may
+/// be used.
+pub unsafe trait RawMutex {
+  /// An unlocked instance. A constant so that logger instances can be constructed in statics.
+  const INIT: Self;
+
+  /// Acquires the lock, spinning or blocking until it is available.
+  fn lock(&self);
+
+  /// Acquires the lock only if it is immediately available, returning true if it was acquired.
+  fn try_lock(&self) -> bool;
+
+  /// Releases the lock.
+  ///
+  /// ## Safety
+  ///
+  /// Must only be called by the holder of the lock.
+  unsafe fn unlock(&self);
+}
+
+unsafe impl RawMutex for spin::Mutex<()> {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const INIT: Self = spin::Mutex::new(());
+
+  fn lock(&self) {
+    core::mem::forget(spin::Mutex::lock(self));
+  }
+
+  fn try_lock(&self) -> bool {
+    spin::Mutex::try_lock(self).map(core::mem::forget).is_some()
+  }
+
+  unsafe fn unlock(&self) {
+    self.force_unlock();
+  }
+}
+
+// a value of type T, accessible only while the raw mutex R is held.
+pub(crate) struct Mutex<R, T> {
+  raw: R,
+  data: UnsafeCell<T>,
+}
+
+impl<R: RawMutex, T> Mutex<R, T> {
+  // creates an unlocked mutex. const fn to allow static initialization.
+  pub(crate) const fn new(value: T) -> Self {
+    Mutex { raw: R::INIT, data: UnsafeCell::new(value) }
+  }
+
+  // acquires the lock; it is released when the returned guard is dropped.
+  pub(crate) fn lock(&self) -> MutexGuard<'_, R, T> {
+    self.raw.lock();
+    MutexGuard { mutex: self }
+  }
+
+  // acquires the lock if it is available, without waiting.
+  pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, R, T>> {
+    //a guard releases the lock when dropped, so one is only created once the lock has been acquired.
+    match self.raw.try_lock() {
+      true => Some(MutexGuard { mutex: self }),
+      false => None,
+    }
+  }
+}
+
+impl<R: RawMutex, T: fmt::Debug> fmt::Debug for Mutex<R, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.try_lock() {
+      Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+      None => f.write_str("Mutex { <locked> }"),
+    }
+  }
+}
+
+// grants access to the value in a Mutex while its lock is held.
+pub(crate) struct MutexGuard<'a, R: RawMutex, T> {
+  mutex: &'a Mutex<R, T>,
+}
+
+impl<R: RawMutex, T> Deref for MutexGuard<'_, R, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.mutex.data.get() }
+  }
+}
+
+impl<R: RawMutex, T> DerefMut for MutexGuard<'_, R, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.mutex.data.get() }
+  }
+}
+
+impl<R: RawMutex, T> Drop for MutexGuard<'_, R, T> {
+  fn drop(&mut self) {
+    unsafe { self.mutex.raw.unlock() };
+  }
+}
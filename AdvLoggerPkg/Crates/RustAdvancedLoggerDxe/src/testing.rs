@@ -0,0 +1,112 @@
+//! Host Test Doubles
+//!
+//! Mock boot services and a mock AdvancedLogger protocol for host-based (`cargo test`) tests of crates that log with
+//! the AdvancedLogger. Available with the `testing` feature, which requires `std`.
+//!
+//! The mock protocol records the level and bytes of each write_log call made on the calling thread, so tests running
+//! in parallel do not see each other's output. Note that the AdvancedLogger itself is a global singleton shared by all
+//! tests in the binary.
+//!
+//! ## Example
+//! ```
+//! use rust_advanced_logger_dxe::{debugln, reinit_debug, testing, DEBUG_INFO};
+//!
+//! let mut boot_services = testing::mock_boot_services();
+//! reinit_debug(&mut boot_services).unwrap();
+//! # if !rust_advanced_logger_dxe::level_enabled(DEBUG_INFO) { return; }
+//! debugln!(DEBUG_INFO, "Hello, {:}.", "world");
+//!
+//! let output: Vec<u8> = testing::take_captured_writes().into_iter().flat_map(|(_, bytes)| bytes).collect();
+//! assert_eq!(output, b"Hello, world.\n");
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+extern crate std;
+
+use core::{
+  cell::{Cell, RefCell},
+  ffi::c_void,
+  mem::MaybeUninit,
+  slice::from_raw_parts,
+};
+use r_efi::{
+  efi::{Guid, Status},
+  system::BootServices,
+};
+use std::{thread_local, vec::Vec};
+
+use crate::{
+  AdvancedLoggerProtocol, ADVANCED_LOGGER_PROTOCOL_GUID, ADVANCED_LOGGER_PROTOCOL_SIGNATURE,
+  ADVANCED_LOGGER_PROTOCOL_VERSION,
+};
+
+// mock protocol instance that records each write_log call for the calling thread.
+static MOCK_LOGGER_INSTANCE: AdvancedLoggerProtocol = AdvancedLoggerProtocol {
+  signature: ADVANCED_LOGGER_PROTOCOL_SIGNATURE,
+  version: ADVANCED_LOGGER_PROTOCOL_VERSION,
+  write_log: mock_write_log,
+};
+
+thread_local! {
+  static CAPTURED_WRITES: RefCell<Vec<(usize, Vec<u8>)>> = RefCell::new(Vec::new());
+  static LOGGER_AVAILABLE: Cell<bool> = Cell::new(true);
+}
+
+extern "efiapi" fn mock_write_log(
+  this: *const AdvancedLoggerProtocol,
+  error_level: usize,
+  buffer: *const u8,
+  buffer_size: usize,
+) {
+  assert_eq!(this, &MOCK_LOGGER_INSTANCE as *const AdvancedLoggerProtocol);
+  let buf: &[u8] = unsafe { from_raw_parts(buffer, buffer_size) };
+  CAPTURED_WRITES.with(|writes| writes.borrow_mut().push((error_level, buf.to_vec())));
+}
+
+extern "efiapi" fn mock_locate_protocol(
+  protocol: *mut Guid,
+  _registration: *mut c_void,
+  interface: *mut *mut c_void,
+) -> Status {
+  let protocol = unsafe { protocol.as_ref() }.expect("null protocol guid");
+  if protocol != &ADVANCED_LOGGER_PROTOCOL_GUID || !LOGGER_AVAILABLE.with(Cell::get) {
+    return Status::NOT_FOUND;
+  }
+  unsafe {
+    interface.write(mock_logger_protocol());
+  }
+  Status::SUCCESS
+}
+
+/// Returns boot services whose LocateProtocol() finds the mock AdvancedLogger protocol, for use with
+/// [`init_debug`](crate::init_debug) or [`reinit_debug`](crate::reinit_debug). All other services are null and must not
+/// be called; callers may install their own.
+pub fn mock_boot_services() -> BootServices {
+  let boot_services = MaybeUninit::zeroed();
+  let mut boot_services: BootServices = unsafe { boot_services.assume_init() };
+  boot_services.locate_protocol = mock_locate_protocol;
+  boot_services
+}
+
+/// Returns a pointer to the mock AdvancedLogger protocol, for use with
+/// [`init_debug_with_protocol`](crate::init_debug_with_protocol).
+pub fn mock_logger_protocol() -> *mut c_void {
+  &MOCK_LOGGER_INSTANCE as *const AdvancedLoggerProtocol as *mut c_void
+}
+
+/// Sets whether LocateProtocol() in [`mock_boot_services`] finds the mock protocol on the calling thread (default
+/// `true`), to test behavior on platforms without the AdvancedLogger.
+pub fn set_mock_logger_available(available: bool) {
+  LOGGER_AVAILABLE.with(|logger_available| logger_available.set(available));
+}
+
+/// Returns (and clears) the level and bytes of each write_log call made to the mock protocol on the calling thread.
+pub fn take_captured_writes() -> Vec<(usize, Vec<u8>)> {
+  CAPTURED_WRITES.with(|writes| writes.take())
+}
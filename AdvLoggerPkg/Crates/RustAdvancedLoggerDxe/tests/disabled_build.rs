@@ -0,0 +1,57 @@
+//! Checks that a binary built with the `disabled` feature does not reference the AdvancedLogger protocol GUID, i.e.
+//! that the linker discarded the code that locates and uses the protocol.
+//!
+//! The `logging_consumer` example is built in release, with and without the feature, into a directory of its own and
+//! the linked executable is searched for the GUID bytes. The build without the feature confirms that the search finds
+//! the GUID where it is used.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+use std::{path::Path, process::Command};
+
+use r_efi::efi::Guid;
+
+// mirrors ADVANCED_LOGGER_PROTOCOL_GUID in the crate.
+const ADVANCED_LOGGER_PROTOCOL_GUID: Guid =
+  Guid::from_fields(0x434f695c, 0xef26, 0x4a12, 0x9e, 0xba, &[0xdd, 0xef, 0x00, 0x97, 0x49, 0x7c]);
+
+// builds the logging_consumer example with the given extra cargo arguments and returns the linked executable.
+fn build_consumer(name: &str, args: &[&str]) -> Vec<u8> {
+  let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+  let status = Command::new(env!("CARGO"))
+    .args(["build", "--quiet", "--release", "--example", "logging_consumer", "--manifest-path"])
+    .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+    .arg("--target-dir")
+    .arg(&target_dir)
+    .args(args)
+    .status()
+    .expect("failed to run cargo");
+  assert!(status.success(), "building the logging_consumer example failed");
+  let executable = format!("logging_consumer{:}", std::env::consts::EXE_SUFFIX);
+  std::fs::read(target_dir.join("release").join("examples").join(executable)).unwrap()
+}
+
+// true if `binary` contains the in-memory (little-endian) representation of the protocol GUID.
+fn references_guid(binary: &[u8]) -> bool {
+  let (time_low, time_mid, time_hi_and_version, clk_seq_hi_res, clk_seq_low, node) =
+    ADVANCED_LOGGER_PROTOCOL_GUID.as_fields();
+  let guid = [
+    &time_low.to_le_bytes()[..],
+    &time_mid.to_le_bytes(),
+    &time_hi_and_version.to_le_bytes(),
+    &[clk_seq_hi_res, clk_seq_low],
+    node,
+  ]
+  .concat();
+  binary.windows(guid.len()).any(|window| window == guid)
+}
+
+#[test]
+fn disabled_build_should_not_reference_protocol_guid() {
+  assert!(references_guid(&build_consumer("enabled_build", &[])));
+  assert!(!references_guid(&build_consumer("disabled_build", &["--features", "disabled"])));
+}
@@ -0,0 +1,38 @@
+//! Exercises the public host test doubles the way a consumer crate's integration tests would.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+#![cfg(feature = "testing")]
+
+use rust_advanced_logger_dxe::{debugln, init_debug, is_debug_initialized, testing, DEBUG_INFO, DEBUG_WARN};
+
+#[cfg_attr(
+  any(feature = "max_level_off", feature = "max_level_error", feature = "max_level_warn", feature = "max_level_info"),
+  ignore = "logs at levels stripped by the max_level_* features"
+)]
+#[test]
+fn mock_logger_should_capture_log_output() {
+  //without the protocol, initialization fails and nothing is logged.
+  testing::set_mock_logger_available(false);
+  let mut boot_services = testing::mock_boot_services();
+  assert!(init_debug(&mut boot_services).is_err());
+  assert!(!is_debug_initialized());
+
+  testing::set_mock_logger_available(true);
+  init_debug(&mut boot_services).unwrap();
+  debugln!(DEBUG_INFO, "Hello, {:}.", "world");
+  debugln!(DEBUG_WARN, "warning");
+
+  let mut output: Vec<(usize, Vec<u8>)> = Vec::new();
+  for (level, bytes) in testing::take_captured_writes() {
+    match output.last_mut() {
+      Some((last_level, last_bytes)) if *last_level == level => last_bytes.extend(bytes),
+      _ => output.push((level, bytes)),
+    }
+  }
+  assert_eq!(output, [(DEBUG_INFO, b"Hello, world.\n".to_vec()), (DEBUG_WARN, b"warning\n".to_vec())]);
+}
@@ -35,8 +35,8 @@ pub extern "efiapi" fn efi_main(image_handle: efi::Handle, system_table: *const
   // and because it mutates/accesses the global BOOT_SERVICES static.
   unsafe {
     BOOT_SERVICES = (*system_table).boot_services;
-    GLOBAL_ALLOCATOR.init(BOOT_SERVICES);
-    init_debug(BOOT_SERVICES);
+    let _ = GLOBAL_ALLOCATOR.init(BOOT_SERVICES);
+    let _ = init_debug(BOOT_SERVICES);
   }
 
   let status = initialize_driver_binding(image_handle);
@@ -1,7 +1,25 @@
 //! Rust Boot Services Allocator
 //!
 //! Implements a global allocator based on UEFI AllocatePool().
-//! Memory is allocated from the EFI_BOOT_SERVICES_DATA pool.
+//! Memory is allocated from the EFI_BOOT_SERVICES_DATA pool by default (see
+//! [`SpinLockedAllocator::set_memory_type()`]). Requests that need page (4 KiB) or greater alignment are allocated with
+//! UEFI AllocatePages() instead; for alignments larger than a page, the pages around the first suitably aligned range
+//! are returned to firmware.
+//!
+//! With the `guard-pages` feature, each page allocation is followed by a guard page to catch buffer overruns, see
+//! `SpinLockedAllocator::set_guard_page_callback()`.
+//!
+//! With the `validate-free` feature, frees of allocations made directly from the pool (which carry no tracking
+//! structure to verify) are checked against a filter of the pointers the allocator returned. A free of a pointer the
+//! allocator did not return is logged at `DEBUG_ERROR` and skipped, rather than passed to FreePool(). The filter may
+//! occasionally let such a free through, but never skips a legitimate one.
+//!
+//! With the `alloc-site` feature (which implies `leak-check`, so that every allocation carries a tracking structure),
+//! each allocation records the return address of the call into the allocator, which
+//! `SpinLockedAllocator::dump_outstanding()` reports so that leaks can be traced to the code that allocated them. Only
+//! the immediate caller is known: allocations made through the global allocator (e.g. by `Box` or `Vec`) reach it via
+//! the allocation shims the compiler generates, so they all report (nearly) the same site. The feature is most useful
+//! for allocations made by calling the allocator directly, e.g. with `SpinLockedAllocator::alloc_tagged()`.
 //!
 //! ## Examples and Usage
 //!
@@ -11,7 +29,7 @@
 //!   _image_handle: *const core::ffi::c_void,
 //!   system_table: *const r_efi::system::SystemTable,
 //! ) -> u64 {
-//!   rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR.init(unsafe { (*system_table).boot_services});
+//!   let _ = rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR.init(unsafe { (*system_table).boot_services});
 //!
 //!   let mut foo = vec!["asdf", "xyzpdq", "abcdefg", "theoden"];
 //!   foo.sort();
@@ -30,79 +48,962 @@
 //!
 #![no_std]
 #![feature(allocator_api)]
+#![cfg_attr(feature = "alloc-site", feature(link_llvm_intrinsics))]
+
+mod lock;
+mod single_threaded;
+mod static_arena;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use lock::RawMutex;
+pub use single_threaded::UnsyncRawMutex;
+use static_arena::ArenaState;
+pub use static_arena::StaticArena;
 
 use core::{
-  alloc::{GlobalAlloc, Layout},
+  alloc::{AllocError, Allocator, GlobalAlloc, Layout},
   ffi::c_void,
+  fmt,
+  ptr::NonNull,
+  sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering},
 };
 
+#[cfg(feature = "uefi-services")]
+use r_efi::system::SystemTable;
 use r_efi::{
-  efi::{BootServices, Status},
-  system::BOOT_SERVICES_DATA,
+  efi::{BootServices, PhysicalAddress, Status},
+  system::{MemoryType, ALLOCATE_ANY_PAGES, ALLOCATE_MAX_ADDRESS, BOOT_SERVICES_DATA},
 };
 
 /// Static GLOBAL_ALLOCATOR instance that is marked with the `#[global_allocator]` attribute.
 ///
 /// See [`core::alloc::GlobalAlloc`] for more details on rust global allocators. This allocator must be initialized
-/// before use, see [`SpinLockedAllocator::init()`].
-#[cfg_attr(not(test), global_allocator)]
+/// before use, see [`SpinLockedAllocator::init()`]. It is not registered as the global allocator in test builds or with
+/// the `testing` feature (see the `testing` module).
+#[cfg_attr(not(any(test, feature = "testing")), global_allocator)]
 pub static GLOBAL_ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
 
+/// Initializes both the [`GLOBAL_ALLOCATOR`] and the AdvancedLogger from the boot services in the given system table.
+///
+/// The allocator is initialized first, so that it is available to anything allocated on the logger paths. If the
+/// allocator fails to initialize, its error is returned and the logger is not initialized; otherwise the result of
+/// initializing the logger is returned. Returns `Status::INVALID_PARAMETER` if `system_table` is null.
+///
+/// Requires the `uefi-services` feature.
+///
+/// ## Example
+/// ```no_run
+/// use rust_boot_services_allocator_dxe::init_uefi_services;
+/// pub extern "efiapi" fn efi_main(
+///   _image_handle: *const core::ffi::c_void,
+///   system_table: *const r_efi::system::SystemTable,
+/// ) -> u64 {
+///   let _ = init_uefi_services(system_table);
+///   r_efi::efi::Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+#[cfg(feature = "uefi-services")]
+pub fn init_uefi_services(system_table: *const SystemTable) -> Result<(), Status> {
+  let boot_services = boot_services_from(system_table)?;
+  GLOBAL_ALLOCATOR.init(boot_services)?;
+  rust_advanced_logger_dxe::init_debug(boot_services)
+}
+
+/// Installs an alloc error hook on the [`GLOBAL_ALLOCATOR`] that logs the size and alignment of each failed allocation
+/// through the AdvancedLogger at `DEBUG_ERROR`.
+///
+/// The standard alloc error handler aborts without describing the allocation that failed; with this hook installed,
+/// the failure is logged just before the handler runs. The hook only logs, so allocations that handle failure (e.g.
+/// [`SpinLockedAllocator::try_alloc()`] or `Vec::try_reserve`) are logged but continue normally. Replaces any hook set
+/// with [`SpinLockedAllocator::set_alloc_error_hook()`]. Nothing is logged if the logger has not been initialized.
+///
+/// Requires the `uefi-services` feature.
+///
+/// ## Example
+/// ```no_run
+/// use rust_boot_services_allocator_dxe::{init_uefi_services, set_as_alloc_error_hook};
+/// pub extern "efiapi" fn efi_main(
+///   _image_handle: *const core::ffi::c_void,
+///   system_table: *const r_efi::system::SystemTable,
+/// ) -> u64 {
+///   let _ = init_uefi_services(system_table);
+///   set_as_alloc_error_hook();
+///   r_efi::efi::Status::SUCCESS.as_usize() as u64
+/// }
+/// ```
+#[cfg(feature = "uefi-services")]
+pub fn set_as_alloc_error_hook() {
+  GLOBAL_ALLOCATOR.set_alloc_error_hook(log_alloc_error);
+}
+
+// alloc error hook installed by set_as_alloc_error_hook(). The logger does not allocate, so it can report an
+// allocation failure.
+#[cfg(feature = "uefi-services")]
+fn log_alloc_error(layout: Layout) {
+  rust_advanced_logger_dxe::debugln!(
+    rust_advanced_logger_dxe::DEBUG_ERROR,
+    "memory allocation failed: size {:#x}, align {:#x}",
+    layout.size(),
+    layout.align()
+  );
+}
+
+// returns the boot services pointer from the given system table.
+#[cfg(feature = "uefi-services")]
+fn boot_services_from(system_table: *const SystemTable) -> Result<*mut BootServices, Status> {
+  let system_table = unsafe { system_table.as_ref() }.ok_or(Status::INVALID_PARAMETER)?;
+  Ok(system_table.boot_services)
+}
+
+/// The lock used by a [`SpinLockedAllocator`] unless another [`RawMutex`] is specified: `spin::Mutex<()>`, or
+/// [`UnsyncRawMutex`] with the `single-threaded` feature.
+#[cfg(not(feature = "single-threaded"))]
+pub type DefaultRawMutex = spin::Mutex<()>;
+/// The lock used by a [`SpinLockedAllocator`] unless another [`RawMutex`] is specified: `spin::Mutex<()>`, or
+/// [`UnsyncRawMutex`] with the `single-threaded` feature.
+#[cfg(feature = "single-threaded")]
+pub type DefaultRawMutex = UnsyncRawMutex;
+
 const ALLOC_TRACKER_SIG: u32 = 0x706F6F6C; //arbitrary sig
 
-// Used to track allocations that need larger alignment than the UEFI Pool alignment (8 bytes).
+const UEFI_PAGE_SIZE: usize = 0x1000;
+
+//alignment guaranteed by UEFI AllocatePool().
+const UEFI_POOL_ALIGNMENT: usize = 8;
+
+/// Maximum number of memory types whose outstanding bytes are counted, see
+/// [`SpinLockedAllocator::memory_type_stats()`].
+pub const MAX_STATS_MEMORY_TYPES: usize = 8;
+
+//number of allocations without a tracking structure whose memory type (other than BOOT_SERVICES_DATA) can be recorded.
+const UNTRACKED_MEMORY_TYPE_SLOTS: usize = 32;
+
+//alignment of requests (common for SIMD types) that are allocated directly from the pool when it happens to return a
+//suitably aligned pointer, even though the pool alignment does not guarantee it.
+const PROBED_ALIGNMENT: usize = 16;
+
+//number of such directly allocated (probed) allocations that can be outstanding.
+const PROBED_SLOTS: usize = 64;
+
+// largest allocation carved out of the arena block in arena mode, see SpinLockedAllocator::enable_arena().
+const ARENA_MAX_ALLOCATION: usize = 0x100;
+
+//fill patterns written over allocations when they are allocated and freed, to expose use of uninitialized memory and
+//use-after-free bugs.
+#[cfg(feature = "poison")]
+const ALLOC_POISON: u8 = 0xAA;
+#[cfg(feature = "poison")]
+const FREE_POISON: u8 = 0xDD;
+
+//number of guard pages that follow each page allocation.
+const GUARD_PAGES: usize = if cfg!(feature = "guard-pages") { 1 } else { 0 };
+//fill pattern written over guard pages, and verified when the allocation is freed.
+#[cfg(feature = "guard-pages")]
+const GUARD_CANARY: u8 = 0xCC;
+
+// Used to track allocations that need larger alignment than the UEFI Pool alignment (8 bytes), or all allocations
+// with the `leak-check` feature or once a fallback allocator is set.
 struct AllocationTracker {
   signature: u32,
   orig_ptr: *mut c_void,
+  //size in bytes of the memory allocated from firmware (starting at orig_ptr) to back the allocation.
+  size: usize,
+  //number of pages if the allocation is backed by AllocatePages(), or zero if it is backed by AllocatePool().
+  pages: usize,
+  //true if the allocation is backed by the fallback allocator rather than firmware.
+  fallback: bool,
+  //memory type the allocation was made with, for the per-memory-type statistics.
+  memory_type: MemoryType,
+  //true if the guard page following a page allocation was made inaccessible by the guard page callback.
+  #[cfg(feature = "guard-pages")]
+  guard_protected: bool,
+  //layout requested by the caller, and links to the neighbouring entries in the list of outstanding allocations.
+  #[cfg(feature = "leak-check")]
+  layout: Layout,
+  #[cfg(feature = "leak-check")]
+  prev: *mut AllocationTracker,
+  #[cfg(feature = "leak-check")]
+  next: *mut AllocationTracker,
+  //caller-supplied tag identifying the allocation, see SpinLockedAllocator::alloc_tagged().
+  #[cfg(feature = "pool-tags")]
+  tag: u32,
+  //return address of the call that made (or last resized) the allocation, or 0 if it is not known.
+  #[cfg(feature = "alloc-site")]
+  site: usize,
+}
+
+/// Totals for the outstanding allocations carrying a particular tag, see [`SpinLockedAllocator::tag_totals()`].
+#[cfg(feature = "pool-tags")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TagTotals {
+  /// Number of allocations currently outstanding with the tag.
+  pub allocations: usize,
+  /// Total bytes currently allocated with the tag, counting only the sizes requested by callers.
+  pub requested_bytes: usize,
+}
+
+// returns a pointer to the tracking structure of a tracked allocation.
+#[cfg(feature = "pool-tags")]
+fn tracker_ptr(ptr: *mut u8, layout: Layout) -> Option<*mut AllocationTracker> {
+  if layout.size() == 0 {
+    return None;
+  }
+  let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).ok()?;
+  Some(unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>() })
+}
+
+// returns the return address of the function this is inlined into, i.e. an address in the code that called the
+// allocator, as the allocation site of an allocation. Returns 0 without the `alloc-site` feature, or on architectures
+// for which no return address is available.
+#[inline(always)]
+fn caller_site() -> usize {
+  #[cfg(all(feature = "alloc-site", any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")))]
+  {
+    extern "C" {
+      #[link_name = "llvm.returnaddress"]
+      fn return_address(level: i32) -> *const u8;
+    }
+    unsafe { return_address(0) as usize }
+  }
+  #[cfg(not(all(feature = "alloc-site", any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64"))))]
+  0
+}
+
+// the tag and allocation site to record in the tracking structure of an allocation (if it has one), written while the
+// tracking structure is populated so that it is never observed without them. Empty without the `pool-tags` and
+// `alloc-site` features.
+#[derive(Debug, Clone, Copy)]
+struct AllocOrigin {
+  //tag to give the allocation, or None for the default tag.
+  #[cfg(feature = "pool-tags")]
+  tag: Option<u32>,
+  #[cfg(feature = "alloc-site")]
+  site: usize,
+}
+
+impl AllocOrigin {
+  // an allocation with the default tag made from `site` (see caller_site()).
+  #[cfg_attr(not(feature = "alloc-site"), allow(unused_variables))]
+  fn from_site(site: usize) -> Self {
+    AllocOrigin {
+      #[cfg(feature = "pool-tags")]
+      tag: None,
+      #[cfg(feature = "alloc-site")]
+      site,
+    }
+  }
+
+  // the same origin, with the given tag.
+  #[cfg(feature = "pool-tags")]
+  fn with_tag(mut self, tag: Option<u32>) -> Self {
+    self.tag = tag;
+    self
+  }
+}
+
+// formats the optional details of an outstanding allocation for dump_outstanding(): its tag and allocation site.
+#[cfg(feature = "leak-check")]
+struct TrackerDetails<'a>(&'a AllocationTracker);
+
+#[cfg(feature = "leak-check")]
+impl fmt::Display for TrackerDetails<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    #[cfg(feature = "alloc-site")]
+    if self.0.site != 0 {
+      write!(f, ", allocated from {:#x}", self.0.site)?;
+    }
+    #[cfg(feature = "pool-tags")]
+    write!(f, ", tag {:#x}", self.0.tag)?;
+    let _ = f;
+    Ok(())
+  }
+}
+
+/// Allocation statistics for a [`SpinLockedAllocator`], see [`SpinLockedAllocator::stats()`].
+///
+/// Except for `requested_bytes`, byte counts reflect the memory requested from firmware rather than the sizes requested
+/// by callers, i.e. they include any padding and tracking structure required to satisfy alignment, and whole pages for
+/// page allocations.
+///
+/// `peak_bytes` is the only resettable field (see [`SpinLockedAllocator::reset_stats()`]); the other fields describe
+/// the live allocations and are always accurate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationStats {
+  /// Total bytes currently allocated.
+  pub outstanding_bytes: usize,
+  /// Highest value of `outstanding_bytes` observed since the allocator was created or the statistics were last reset.
+  pub peak_bytes: usize,
+  /// Number of allocations currently outstanding.
+  pub outstanding_allocations: usize,
+  /// Total bytes currently allocated, counting only the sizes requested by callers.
+  pub requested_bytes: usize,
+}
+
+impl AllocationStats {
+  /// Returns the number of outstanding bytes lost to alignment padding and tracking structures, i.e. the memory
+  /// requested from firmware beyond the sizes requested by callers.
+  pub fn overhead_bytes(&self) -> usize {
+    self.outstanding_bytes - self.requested_bytes
+  }
+
+  // records an allocation of `size` bytes from firmware to satisfy a request for `requested` bytes.
+  fn record_alloc(&mut self, size: usize, requested: usize) {
+    self.outstanding_bytes += size;
+    self.peak_bytes = self.peak_bytes.max(self.outstanding_bytes);
+    self.outstanding_allocations += 1;
+    self.requested_bytes += requested;
+  }
+
+  // restarts the high-water mark from the current outstanding bytes.
+  fn reset(&mut self) {
+    self.peak_bytes = self.outstanding_bytes;
+  }
+
+  // records that an allocation of `size` bytes from firmware, made for a request of `requested` bytes, was freed.
+  fn record_dealloc(&mut self, size: usize, requested: usize) {
+    self.outstanding_bytes -= size;
+    self.outstanding_allocations -= 1;
+    self.requested_bytes -= requested;
+  }
+}
+
+// Copy of the allocation statistics that can be read without the allocator lock. The allocator publishes its
+// statistics here (with the lock held, so there is a single writer) after each operation that changes them.
+//
+// The statistics are double-buffered: each publish writes the slot that is not current, then makes it current by
+// advancing `generation`. A reader copies the current slot, and retries if a publish made the other slot current
+// meanwhile, since the next publish may then already be writing the slot it copied. A reader that interrupts a
+// publish on the same processor reads the slot the publish is not writing, so it never waits for the publish.
+struct AtomicStats {
+  generation: AtomicUsize,
+  slots: [[AtomicUsize; 4]; 2],
+}
+
+impl AtomicStats {
+  // Create a new instance with zeroed statistics. const fn to allow static initialization.
+  const fn new() -> Self {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: [AtomicUsize; 4] = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+    AtomicStats { generation: AtomicUsize::new(0), slots: [ZERO; 2] }
+  }
+
+  // publishes `stats`. Must only be called with the allocator lock held.
+  fn publish(&self, stats: &AllocationStats) {
+    let generation = self.generation.load(Ordering::Relaxed).wrapping_add(1);
+    //orders the slot writes after the publish that made the other slot current, for readers that observe them.
+    fence(Ordering::Release);
+    let slot = &self.slots[generation % 2];
+    slot[0].store(stats.outstanding_bytes, Ordering::Relaxed);
+    slot[1].store(stats.peak_bytes, Ordering::Relaxed);
+    slot[2].store(stats.outstanding_allocations, Ordering::Relaxed);
+    slot[3].store(stats.requested_bytes, Ordering::Relaxed);
+    self.generation.store(generation, Ordering::Release);
+  }
+
+  // returns the most recently published statistics.
+  fn load(&self) -> AllocationStats {
+    loop {
+      let generation = self.generation.load(Ordering::Acquire);
+      let slot = &self.slots[generation % 2];
+      let stats = AllocationStats {
+        outstanding_bytes: slot[0].load(Ordering::Relaxed),
+        peak_bytes: slot[1].load(Ordering::Relaxed),
+        outstanding_allocations: slot[2].load(Ordering::Relaxed),
+        requested_bytes: slot[3].load(Ordering::Relaxed),
+      };
+      fence(Ordering::Acquire);
+      if self.generation.load(Ordering::Relaxed) == generation {
+        return stats;
+      }
+      core::hint::spin_loop();
+    }
+  }
+}
+
+/// Outstanding bytes for a single memory type, see [`SpinLockedAllocator::memory_type_stats()`].
+///
+/// As with [`AllocationStats::outstanding_bytes`], the count reflects the memory requested from firmware (or the
+/// fallback allocator) rather than the sizes requested by callers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryTypeStats {
+  /// The memory type.
+  pub memory_type: MemoryType,
+  /// Total bytes currently allocated with the memory type.
+  pub outstanding_bytes: usize,
+}
+
+// Outstanding bytes per memory type, in the order each type was first used. Allocations without a tracking structure
+// have nowhere to record their memory type, so those made with a type other than BOOT_SERVICES_DATA are recorded in a
+// side table until they are freed.
+struct MemoryTypeTotals {
+  totals: [Option<MemoryTypeStats>; MAX_STATS_MEMORY_TYPES],
+  untracked: [Option<(*mut u8, MemoryType)>; UNTRACKED_MEMORY_TYPE_SLOTS],
+  untracked_count: usize,
+}
+
+impl MemoryTypeTotals {
+  const fn new() -> Self {
+    MemoryTypeTotals {
+      totals: [None; MAX_STATS_MEMORY_TYPES],
+      untracked: [None; UNTRACKED_MEMORY_TYPE_SLOTS],
+      untracked_count: 0,
+    }
+  }
+
+  // records an allocation of `size` bytes with the given memory type. Allocations are not counted once
+  // MAX_STATS_MEMORY_TYPES other types have been used.
+  fn record_alloc(&mut self, memory_type: MemoryType, size: usize) {
+    let slot = self
+      .totals
+      .iter()
+      .position(|entry| entry.is_some_and(|entry| entry.memory_type == memory_type))
+      .or_else(|| self.totals.iter().position(Option::is_none));
+    if let Some(index) = slot {
+      let entry = self.totals[index].get_or_insert(MemoryTypeStats { memory_type, outstanding_bytes: 0 });
+      entry.outstanding_bytes += size;
+    }
+  }
+
+  // records that an allocation of `size` bytes with the given memory type was freed.
+  fn record_dealloc(&mut self, memory_type: MemoryType, size: usize) {
+    if let Some(entry) = self.totals.iter_mut().flatten().find(|entry| entry.memory_type == memory_type) {
+      entry.outstanding_bytes -= size;
+    }
+  }
+
+  // records the memory type of an allocation without a tracking structure, and returns the type it is counted under.
+  // Allocations with the default type are not recorded, and neither are others once the side table is full; both are
+  // counted under BOOT_SERVICES_DATA.
+  fn record_untracked(&mut self, ptr: *mut u8, memory_type: MemoryType) -> MemoryType {
+    if memory_type == BOOT_SERVICES_DATA {
+      return BOOT_SERVICES_DATA;
+    }
+    match self.untracked.iter().position(Option::is_none) {
+      Some(index) => {
+        self.untracked[index] = Some((ptr, memory_type));
+        self.untracked_count += 1;
+        memory_type
+      }
+      None => BOOT_SERVICES_DATA,
+    }
+  }
+
+  // removes the side table entry (if any) for a freed allocation without a tracking structure, and returns the type it
+  // was counted under.
+  fn take_untracked(&mut self, ptr: *mut u8) -> MemoryType {
+    if self.untracked_count == 0 {
+      return BOOT_SERVICES_DATA;
+    }
+    for entry in self.untracked.iter_mut() {
+      if let Some((recorded, memory_type)) = *entry {
+        if recorded == ptr {
+          *entry = None;
+          self.untracked_count -= 1;
+          return memory_type;
+        }
+      }
+    }
+    BOOT_SERVICES_DATA
+  }
+}
+
+// pool block that small allocations are carved out of in arena mode, see SpinLockedAllocator::enable_arena().
+struct PoolArena {
+  block: *mut u8,
+  size: usize,
+  memory_type: MemoryType,
+  state: ArenaState,
+  //number of allocations carved out of the block that have not been freed.
+  allocations: usize,
+  //set once arena mode is disabled; the block is then freed along with its last allocation.
+  disabled: bool,
+}
+
+impl PoolArena {
+  // returns true if `ptr` was carved out of the arena block.
+  fn contains(&self, ptr: *mut u8) -> bool {
+    (self.block as usize..self.block as usize + self.size).contains(&(ptr as usize))
+  }
+}
+
+// number of counters in the filter of direct pool pointers, a power of two.
+#[cfg(feature = "validate-free")]
+const DIRECT_FILTER_COUNTERS: usize = 4096;
+
+// counting bloom filter of the pointers returned by direct pool allocations, so that frees of pointers the allocator
+// did not return can be refused rather than passed to FreePool(). A pointer that was inserted is always found, while
+// a pointer that was not is found only if its counters are all in use by other pointers. Saturated counters are never
+// decremented, since the number of pointers they count is then unknown.
+#[cfg(feature = "validate-free")]
+struct DirectPointerFilter {
+  counters: [u8; DIRECT_FILTER_COUNTERS],
+}
+
+#[cfg(feature = "validate-free")]
+impl DirectPointerFilter {
+  // creates an empty filter. const fn to allow static initialization.
+  const fn new() -> Self {
+    DirectPointerFilter { counters: [0; DIRECT_FILTER_COUNTERS] }
+  }
+
+  // counters for `ptr`, from the top bits of two multiplicative hashes of its pool-aligned address.
+  fn indices(ptr: *mut u8) -> [usize; 2] {
+    let address = ptr as usize as u64 / UEFI_POOL_ALIGNMENT as u64;
+    let bits = DIRECT_FILTER_COUNTERS.trailing_zeros();
+    [0x9E37_79B9_7F4A_7C15u64, 0xC2B2_AE3D_27D4_EB4Fu64]
+      .map(|multiplier| (address.wrapping_mul(multiplier) >> (u64::BITS - bits)) as usize)
+  }
+
+  // records `ptr` as returned by the allocator.
+  fn insert(&mut self, ptr: *mut u8) {
+    for index in Self::indices(ptr) {
+      self.counters[index] = self.counters[index].saturating_add(1);
+    }
+  }
+
+  // removes `ptr`, returning false (without changing the filter) if it was not inserted.
+  fn remove(&mut self, ptr: *mut u8) -> bool {
+    let indices = Self::indices(ptr);
+    if indices.iter().any(|index| self.counters[*index] == 0) {
+      return false;
+    }
+    for index in indices {
+      if self.counters[index] != u8::MAX {
+        self.counters[index] -= 1;
+      }
+    }
+    true
+  }
 }
 
 // Private unlocked allocator implementation. The public locked allocator delegates to this implementation.
 struct BootServicesAllocator {
   boot_services: Option<*mut BootServices>,
+  sealed: bool,
+  memory_type: MemoryType,
+  //largest allocation size that will be requested from firmware.
+  max_allocation: usize,
+  //alignment of the pointers returned by AllocatePool(); requests up to this alignment are allocated directly.
+  pool_alignment: usize,
+  stats: AllocationStats,
+  memory_type_totals: MemoryTypeTotals,
+  alloc_error_hook: Option<fn(Layout)>,
+  //number of times a failed firmware allocation is retried, and the callback called between attempts.
+  alloc_retries: u32,
+  alloc_retry_callback: Option<fn()>,
+  //set when freed memory must be zeroed before it is returned to firmware.
+  zero_on_free: bool,
+  corruption_handler: Option<fn(*mut u8, Layout)>,
+  //set when a free is refused because the tracking structure is corrupt, so that the corruption can be reported once
+  //the lock is released.
+  corrupted_free: Option<(*mut u8, Layout)>,
+  fallback: Option<&'static dyn GlobalAlloc>,
+  //set when every allocation must carry a tracking structure, regardless of its alignment.
+  track_all: bool,
+  //PROBED_ALIGNMENT aligned allocations that the pool happened to align, and that carry no tracking structure as a
+  //result. They are recorded so that they are freed directly, unlike other allocations with the same layout.
+  probed: [*mut u8; PROBED_SLOTS],
+  probed_count: usize,
+  //block that small untracked allocations are carved out of, while arena mode is enabled (or its block is in use).
+  arena: Option<PoolArena>,
+  //pointers returned by direct pool allocations (including probed ones), checked before they are freed.
+  #[cfg(feature = "validate-free")]
+  direct_pointers: DirectPointerFilter,
+  //makes guard pages inaccessible (or accessible again, when the second argument is false).
+  #[cfg(feature = "guard-pages")]
+  guard_page_callback: Option<fn(PhysicalAddress, bool) -> Status>,
+  //head of the list of outstanding allocations.
+  #[cfg(feature = "leak-check")]
+  outstanding: *mut AllocationTracker,
+  //tag given to allocations that are not explicitly tagged.
+  #[cfg(feature = "pool-tags")]
+  default_tag: u32,
+}
+
+// returns the non-null, suitably aligned (but dangling) pointer returned for zero-size requests, which are not passed
+// to firmware.
+fn zero_size_sentinel(layout: Layout) -> *mut u8 {
+  layout.align() as *mut u8
+}
+
+// in debug builds, panics if `ptr` does not satisfy the alignment of `layout`. This catches a firmware pool that does
+// not provide the alignment the allocator is configured to rely on (see set_pool_alignment), or an error in the
+// alignment math for tracked allocations.
+fn debug_assert_aligned(ptr: *mut u8, layout: Layout) {
+  debug_assert!(
+    ptr as usize % layout.align() == 0,
+    "allocation at {:p} does not satisfy the alignment of {:?}",
+    ptr,
+    layout
+  );
+}
+
+// size of the pool allocation for a tracked allocation with the given expanded (allocation + tracker) layout, including
+// slack to align the allocation. Since pool allocations are already UEFI_POOL_ALIGNMENT aligned, aligning one up
+// requires at most `align - UEFI_POOL_ALIGNMENT` bytes of padding, and none if `align` is UEFI_POOL_ALIGNMENT or less
+// (e.g. on targets where the tracker itself only needs 4-byte alignment).
+fn tracked_pool_size(expanded_layout: Layout) -> usize {
+  expanded_layout.size() + expanded_layout.align().saturating_sub(UEFI_POOL_ALIGNMENT)
 }
 
 impl BootServicesAllocator {
   // Create a new instance. const fn to allow static initialization.
   const fn new() -> Self {
-    BootServicesAllocator { boot_services: None }
+    BootServicesAllocator {
+      boot_services: None,
+      sealed: false,
+      memory_type: BOOT_SERVICES_DATA,
+      max_allocation: usize::MAX,
+      pool_alignment: UEFI_POOL_ALIGNMENT,
+      stats: AllocationStats { outstanding_bytes: 0, peak_bytes: 0, outstanding_allocations: 0, requested_bytes: 0 },
+      memory_type_totals: MemoryTypeTotals::new(),
+      alloc_error_hook: None,
+      alloc_retries: 0,
+      alloc_retry_callback: None,
+      zero_on_free: false,
+      corruption_handler: None,
+      corrupted_free: None,
+      fallback: None,
+      track_all: false,
+      probed: [core::ptr::null_mut(); PROBED_SLOTS],
+      probed_count: 0,
+      arena: None,
+      #[cfg(feature = "validate-free")]
+      direct_pointers: DirectPointerFilter::new(),
+      #[cfg(feature = "guard-pages")]
+      guard_page_callback: None,
+      #[cfg(feature = "leak-check")]
+      outstanding: core::ptr::null_mut(),
+      #[cfg(feature = "pool-tags")]
+      default_tag: 0,
+    }
   }
 
-  // initialize the allocator by providing a pointer to the global boot services table.
-  fn init(&mut self, boot_services: *mut BootServices) {
+  // initialize the allocator by providing a pointer to the global boot services table. Returns
+  // Status::ALREADY_STARTED without changing anything if the allocator is already initialized, or
+  // Status::INVALID_PARAMETER if the pointer is null.
+  fn init(&mut self, boot_services: *mut BootServices) -> Result<(), Status> {
+    if self.boot_services.is_some() {
+      return Err(Status::ALREADY_STARTED);
+    }
+    if boot_services.is_null() {
+      return Err(Status::INVALID_PARAMETER);
+    }
     self.boot_services = Some(boot_services);
+    Ok(())
+  }
+
+  // returns true if allocations with the given layout carry a tracking structure. With the `leak-check` feature every
+  // allocation is tracked, so that it can be linked into the list of outstanding allocations.
+  fn is_tracked(&self, layout: Layout) -> bool {
+    cfg!(feature = "leak-check") || self.track_all || layout.align() > self.pool_alignment
+  }
+
+  // returns a free slot to record a probed allocation in, if `layout` needs a tracking structure only because the pool
+  // alignment does not guarantee its PROBED_ALIGNMENT alignment.
+  fn probe_slot(&self, layout: Layout) -> Option<usize> {
+    if cfg!(feature = "leak-check")
+      || self.track_all
+      || layout.align() != PROBED_ALIGNMENT
+      || self.pool_alignment >= PROBED_ALIGNMENT
+    {
+      return None;
+    }
+    self.probed.iter().position(|probed| probed.is_null())
+  }
+
+  // returns the slot recording the allocation at `ptr`, if it is a probed allocation.
+  fn probed_index(&self, ptr: *mut u8, layout: Layout) -> Option<usize> {
+    if self.probed_count == 0 || layout.align() != PROBED_ALIGNMENT {
+      return None;
+    }
+    self.probed.iter().position(|probed| *probed == ptr)
+  }
+
+  // records an allocation without a tracking structure in the statistics (and the filter of direct pool pointers).
+  fn record_untracked_alloc(&mut self, ptr: *mut u8, layout: Layout) {
+    #[cfg(feature = "validate-free")]
+    self.direct_pointers.insert(ptr);
+    self.stats.record_alloc(layout.size(), layout.size());
+    let memory_type = self.memory_type_totals.record_untracked(ptr, self.memory_type);
+    self.memory_type_totals.record_alloc(memory_type, layout.size());
+  }
+
+  // allocates the arena block for arena mode. Returns Status::ALREADY_STARTED if an arena block is already allocated
+  // (including one that is disabled but still in use).
+  fn enable_arena(&mut self, block_size: usize) -> Result<(), Status> {
+    let Some(bs_ptr) = self.boot_services else { return Err(Status::NOT_READY) };
+    if self.sealed {
+      return Err(Status::UNSUPPORTED);
+    }
+    if self.arena.is_some() {
+      return Err(Status::ALREADY_STARTED);
+    }
+    if block_size == 0 {
+      return Err(Status::INVALID_PARAMETER);
+    }
+    let Some(bs) = (unsafe { bs_ptr.as_mut() }) else { return Err(Status::NOT_READY) };
+
+    let mut block: *mut c_void = core::ptr::null_mut();
+    match self.retry_firmware_alloc(|| (bs.allocate_pool)(self.memory_type, block_size, core::ptr::addr_of_mut!(block)))
+    {
+      Status::SUCCESS => {
+        self.arena = Some(PoolArena {
+          block: block as *mut u8,
+          size: block_size,
+          memory_type: self.memory_type,
+          state: ArenaState::new(),
+          allocations: 0,
+          disabled: false,
+        });
+        Ok(())
+      }
+      status => Err(status),
+    }
+  }
+
+  // frees the arena block if arena mode is disabled and no allocations are carved out of it.
+  fn release_arena(&mut self) {
+    let Some(arena) = self.arena.as_ref().filter(|arena| arena.disabled && arena.allocations == 0) else { return };
+    //once sealed the block is not returned to firmware, like any other allocation.
+    if let (Some(bs), false) = (self.boot_services.and_then(|bs| unsafe { bs.as_mut() }), self.sealed) {
+      let _ = (bs.free_pool)(arena.block as *mut c_void);
+    }
+    self.arena = None;
+  }
+
+  // carves an allocation out of the arena block, if arena mode is enabled and `layout` is small enough. Returns null if
+  // the allocation should be made from the pool instead.
+  fn arena_alloc(&mut self, layout: Layout) -> *mut u8 {
+    if layout.size() > ARENA_MAX_ALLOCATION || self.is_tracked(layout) {
+      return core::ptr::null_mut();
+    }
+    let Some(arena) = self.arena.as_mut().filter(|arena| !arena.disabled) else { return core::ptr::null_mut() };
+    let ptr = unsafe { arena.state.alloc(arena.block as usize, arena.size, layout) };
+    if !ptr.is_null() {
+      arena.allocations += 1;
+      let (size, memory_type) = (ArenaState::block_size(layout), arena.memory_type);
+      self.stats.record_alloc(size, layout.size());
+      self.memory_type_totals.record_alloc(memory_type, size);
+    }
+    ptr
+  }
+
+  // returns an allocation to the arena block if it was carved out of it, freeing the block if it was the last
+  // allocation in a disabled arena. Returns false if `ptr` is not in the arena block.
+  fn arena_dealloc(&mut self, ptr: *mut u8, layout: Layout) -> bool {
+    let Some(arena) = self.arena.as_mut().filter(|arena| arena.contains(ptr)) else { return false };
+    unsafe { arena.state.dealloc(arena.block as usize, ptr, layout) };
+    arena.allocations -= 1;
+    let (size, memory_type) = (ArenaState::block_size(layout), arena.memory_type);
+    self.stats.record_dealloc(size, layout.size());
+    self.memory_type_totals.record_dealloc(memory_type, size);
+    self.release_arena();
+    true
+  }
+
+  // implement allocation using EFI boot services AllocatePool() call. Page allocations are placed at or below
+  // `max_address`, if given. Returns the failing status if the allocation could not be made.
+  fn boot_services_alloc(
+    &mut self,
+    layout: Layout,
+    max_address: Option<PhysicalAddress>,
+    origin: AllocOrigin,
+  ) -> Result<*mut u8, Status> {
+    if layout.size() == 0 {
+      return Ok(zero_size_sentinel(layout));
+    }
+    let ptr = self.firmware_alloc(layout, max_address, origin)?;
+    debug_assert_aligned(ptr, layout);
+    #[cfg(feature = "poison")]
+    unsafe {
+      ptr.write_bytes(ALLOC_POISON, layout.size())
+    };
+    Ok(ptr)
+  }
+
+  // implement zeroed allocation. Only the caller's region is zeroed; any tracking structure is left intact (and the
+  // allocation is not poisoned, since it is immediately overwritten).
+  fn boot_services_alloc_zeroed(&mut self, layout: Layout, origin: AllocOrigin) -> Result<*mut u8, Status> {
+    if layout.size() == 0 {
+      return Ok(zero_size_sentinel(layout));
+    }
+    let ptr = self.firmware_alloc(layout, None, origin)?;
+    debug_assert_aligned(ptr, layout);
+    unsafe { ptr.write_bytes(0, layout.size()) };
+    Ok(ptr)
+  }
+
+  // implement dealloc (free) using EFI boot services FreePool() or FreePages() call. Only the caller's region is zeroed
+  // or poisoned; any tracking structure is left intact for firmware_dealloc to read.
+  fn boot_services_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+    //zero-size allocations are the sentinel, which was not allocated from firmware.
+    if layout.size() == 0 {
+      return;
+    }
+    if self.boot_services.is_some() && !self.sealed {
+      if self.zero_on_free {
+        unsafe { ptr.write_bytes(0, layout.size()) };
+      } else {
+        #[cfg(feature = "poison")]
+        unsafe {
+          ptr.write_bytes(FREE_POISON, layout.size())
+        };
+      }
+    }
+    self.firmware_dealloc(ptr, layout)
+  }
+
+  // makes a firmware allocation with `allocate`, retrying up to alloc_retries times (calling the alloc retry callback,
+  // if any, before each retry) until it succeeds. Returns the status of the last attempt.
+  fn retry_firmware_alloc(&self, mut allocate: impl FnMut() -> Status) -> Status {
+    let mut status = allocate();
+    for _ in 0..self.alloc_retries {
+      if status == Status::SUCCESS {
+        break;
+      }
+      if let Some(callback) = self.alloc_retry_callback {
+        callback();
+      }
+      status = allocate();
+    }
+    status
   }
 
-  // implement allocation using EFI boot services AllocatePool() call.
-  fn boot_services_alloc(&self, layout: Layout) -> *mut u8 {
-    //bail early if not initialized.
-    let Some(bs_ptr) = self.boot_services else { return core::ptr::null_mut() };
+  // allocate memory for `layout` from firmware, along with a tracking structure if one is required, which records
+  // `origin`. If `max_address` is given, page allocations are placed at or below it (and the fallback allocator is not
+  // used, since it cannot honour the constraint).
+  #[cfg_attr(not(any(feature = "pool-tags", feature = "alloc-site")), allow(unused_variables))]
+  fn firmware_alloc(
+    &mut self,
+    layout: Layout,
+    max_address: Option<PhysicalAddress>,
+    origin: AllocOrigin,
+  ) -> Result<*mut u8, Status> {
+    //bail early if not initialized, or if boot services are no longer available.
+    let Some(bs_ptr) = self.boot_services else { return Err(Status::NOT_READY) };
+    if self.sealed {
+      return Err(Status::UNSUPPORTED);
+    }
+    if layout.size() > self.max_allocation {
+      return Err(Status::OUT_OF_RESOURCES);
+    }
+
+    let Some(bs) = (unsafe { bs_ptr.as_mut() }) else { return Err(Status::NOT_READY) };
+
+    let ptr = self.arena_alloc(layout);
+    if !ptr.is_null() {
+      return Ok(ptr);
+    }
 
-    let bs = unsafe { bs_ptr.as_mut().expect("Boot Services pointer is null.") };
+    //many pools return 16-byte aligned pointers without guaranteeing it, so first try a plain pool allocation for such
+    //requests, and keep it (without a tracking structure) if the pointer returned is aligned.
+    if let Some(slot) = self.probe_slot(layout) {
+      let mut ptr: *mut c_void = core::ptr::null_mut();
+      match self
+        .retry_firmware_alloc(|| (bs.allocate_pool)(self.memory_type, layout.size(), core::ptr::addr_of_mut!(ptr)))
+      {
+        Status::SUCCESS if ptr as usize % PROBED_ALIGNMENT == 0 => {
+          self.probed[slot] = ptr as *mut u8;
+          self.probed_count += 1;
+          self.record_untracked_alloc(ptr as *mut u8, layout);
+          return Ok(ptr as *mut u8);
+        }
+        Status::SUCCESS => {
+          //not aligned; free the probe and make a tracked allocation instead.
+          let _ = (bs.free_pool)(ptr);
+        }
+        status => return Err(status),
+      }
+    }
 
-    match layout.align() {
-      0..=8 => {
-        //allocate the pointer directly since UEFI pool allocations are 8-byte aligned already.
+    match self.is_tracked(layout) {
+      false => {
+        //allocate the pointer directly since UEFI pool allocations are aligned to pool_alignment already.
         let mut ptr: *mut c_void = core::ptr::null_mut();
-        match (bs.allocate_pool)(BOOT_SERVICES_DATA, layout.size(), core::ptr::addr_of_mut!(ptr)) {
-          Status::SUCCESS => ptr as *mut u8,
-          _ => core::ptr::null_mut(),
+        match self
+          .retry_firmware_alloc(|| (bs.allocate_pool)(self.memory_type, layout.size(), core::ptr::addr_of_mut!(ptr)))
+        {
+          Status::SUCCESS => {
+            self.record_untracked_alloc(ptr as *mut u8, layout);
+            Ok(ptr as *mut u8)
+          }
+          status => Err(status),
         }
       }
-      _ => {
+      true => {
         //allocate extra space to align the allocation as requested and include a tracking structure to allow
         //recovery of the original pointer for de-allocation. Tracking structure follows the allocation.
         let (expanded_layout, tracking_offset) = match layout.extend(Layout::new::<AllocationTracker>()) {
           Ok(x) => x,
-          Err(_) => return core::ptr::null_mut(),
+          Err(_) => return Err(Status::INVALID_PARAMETER),
         };
-        let expanded_size = expanded_layout.size() + expanded_layout.align();
 
-        let mut orig_ptr: *mut c_void = core::ptr::null_mut();
-        let final_ptr = match (bs.allocate_pool)(BOOT_SERVICES_DATA, expanded_size, core::ptr::addr_of_mut!(orig_ptr)) {
-          Status::SUCCESS => orig_ptr as *mut u8,
-          _ => return core::ptr::null_mut(),
+        let backing = if expanded_layout.align() >= UEFI_PAGE_SIZE {
+          //page allocations are page-aligned already, so extra pages are only required for larger alignments. Any guard
+          //page follows the allocation, and is not counted in its size.
+          let pages = expanded_layout.size().div_ceil(UEFI_PAGE_SIZE);
+          let extra_pages = expanded_layout.align() / UEFI_PAGE_SIZE - 1;
+          let (allocate_type, max_address) = match max_address {
+            Some(max_address) => (ALLOCATE_MAX_ADDRESS, max_address),
+            None => (ALLOCATE_ANY_PAGES, 0),
+          };
+          let mut address: PhysicalAddress = 0;
+          match self.retry_firmware_alloc(|| {
+            //for ALLOCATE_MAX_ADDRESS, the address is both the constraint (on input) and the result.
+            address = max_address;
+            (bs.allocate_pages)(
+              allocate_type,
+              self.memory_type,
+              pages + extra_pages + GUARD_PAGES,
+              core::ptr::addr_of_mut!(address),
+            )
+          }) {
+            Status::SUCCESS => {
+              //return the extra pages either side of the first suitably aligned range to firmware, so that large
+              //alignments (e.g. for DMA buffers) do not waste memory. If firmware returned an aligned base, all of the
+              //extra pages follow the allocation.
+              let base = address as usize;
+              let aligned = base.next_multiple_of(expanded_layout.align());
+              let leading_pages = (aligned - base) / UEFI_PAGE_SIZE;
+              let trailing_pages = extra_pages - leading_pages;
+              if leading_pages > 0 {
+                let _ = (bs.free_pages)(base as PhysicalAddress, leading_pages);
+              }
+              if trailing_pages > 0 {
+                let _ = (bs.free_pages)(
+                  (aligned + (pages + GUARD_PAGES) * UEFI_PAGE_SIZE) as PhysicalAddress,
+                  trailing_pages,
+                );
+              }
+              Ok((aligned as *mut c_void, pages * UEFI_PAGE_SIZE, pages))
+            }
+            status => Err(status),
+          }
+        } else {
+          let expanded_size = tracked_pool_size(expanded_layout);
+          let mut orig_ptr: *mut c_void = core::ptr::null_mut();
+          match self.retry_firmware_alloc(|| {
+            (bs.allocate_pool)(self.memory_type, expanded_size, core::ptr::addr_of_mut!(orig_ptr))
+          }) {
+            Status::SUCCESS => Ok((orig_ptr, expanded_size, 0)),
+            status => Err(status),
+          }
         };
 
+        //if firmware could not satisfy the request, retry with the fallback allocator (if any). The fallback
+        //allocator honours the expanded layout directly, so no additional padding is required.
+        let (orig_ptr, size, pages, fallback) = match (backing, self.fallback) {
+          (Ok((orig_ptr, size, pages)), _) => (orig_ptr, size, pages, false),
+          (Err(status), Some(fallback)) if max_address.is_none() => match unsafe { fallback.alloc(expanded_layout) } {
+            ptr if ptr.is_null() => return Err(status),
+            ptr => (ptr as *mut c_void, expanded_layout.size(), 0, true),
+          },
+          (Err(status), _) => return Err(status),
+        };
+        let final_ptr = orig_ptr as *mut u8;
+
         //align the pointer up to the required alignment.
         let final_ptr = unsafe { final_ptr.add(final_ptr.align_offset(expanded_layout.align())) };
 
@@ -113,36 +1014,268 @@ impl BootServicesAllocator {
 
         tracker.signature = ALLOC_TRACKER_SIG;
         tracker.orig_ptr = orig_ptr;
+        tracker.size = size;
+        tracker.pages = pages;
+        tracker.fallback = fallback;
+        tracker.memory_type = self.memory_type;
+        #[cfg(feature = "guard-pages")]
+        {
+          tracker.guard_protected = pages > 0 && self.place_guard_page(orig_ptr as usize + size);
+        }
+        #[cfg(feature = "pool-tags")]
+        {
+          tracker.tag = origin.tag.unwrap_or(self.default_tag);
+        }
+        #[cfg(feature = "alloc-site")]
+        {
+          tracker.site = origin.site;
+        }
+        #[cfg(feature = "leak-check")]
+        {
+          tracker.layout = layout;
+          self.link_outstanding(tracker);
+        }
 
-        final_ptr
+        self.stats.record_alloc(size, layout.size());
+        self.memory_type_totals.record_alloc(self.memory_type, size);
+        Ok(final_ptr)
       }
     }
   }
 
-  // implement dealloc (free) using EFI boot services FreePool() call.
-  fn boot_services_dealloc(&self, ptr: *mut u8, layout: Layout) {
-    //bail early if not initialized.
+  // free memory allocated by firmware_alloc.
+  fn firmware_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+    //bail early if not initialized, or if boot services are no longer available.
     let Some(bs_ptr) = self.boot_services else { return };
+    if self.sealed {
+      return;
+    }
+
+    let Some(bs) = (unsafe { bs_ptr.as_mut() }) else { return };
 
-    let bs = unsafe { bs_ptr.as_mut().expect("Boot Services pointer is null.") };
+    if self.arena_dealloc(ptr, layout) {
+      return;
+    }
 
-    match layout.align() {
-      0..=8 => {
+    let probed = self.probed_index(ptr, layout);
+    if let Some(index) = probed {
+      self.probed[index] = core::ptr::null_mut();
+      self.probed_count -= 1;
+    }
+    match self.is_tracked(layout) && probed.is_none() {
+      false => {
+        //with validate-free, a pointer that was not allocated directly is not passed to firmware.
+        #[cfg(feature = "validate-free")]
+        if !self.direct_pointers.remove(ptr) {
+          rust_advanced_logger_dxe::debugln!(
+            rust_advanced_logger_dxe::DEBUG_ERROR,
+            "free of {:p} skipped: not allocated by this allocator (size {:#x}, align {:#x})",
+            ptr,
+            layout.size(),
+            layout.align()
+          );
+          return;
+        }
         //pointer was allocated directly, so free it directly.
         let _ = (bs.free_pool)(ptr as *mut c_void);
+        self.stats.record_dealloc(layout.size(), layout.size());
+        let memory_type = self.memory_type_totals.take_untracked(ptr);
+        self.memory_type_totals.record_dealloc(memory_type, layout.size());
       }
-      _ => {
+      true => {
         //pointer was potentially adjusted for alignment. Recover tracking structure to retrieve the original
         //pointer to free.
-        let (_, tracking_offset) = match layout.extend(Layout::new::<AllocationTracker>()) {
+        let (expanded_layout, tracking_offset) = match layout.extend(Layout::new::<AllocationTracker>()) {
           Ok(x) => x,
           Err(_) => return,
         };
-        let tracker = unsafe {
-          ptr.add(tracking_offset).cast::<AllocationTracker>().as_mut().expect("tracking pointer is invalid")
-        };
-        debug_assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
-        let _ = (bs.free_pool)(tracker.orig_ptr);
+        //read the tracker out of the allocation, since it is freed below.
+        let tracker = unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>().read() };
+        if tracker.signature != ALLOC_TRACKER_SIG {
+          //the heap is corrupt or the pointer did not come from this allocator; don't pass garbage to firmware.
+          self.corrupted_free = Some((ptr, layout));
+          return;
+        }
+        #[cfg(feature = "leak-check")]
+        self.unlink_outstanding(&tracker);
+        match tracker.pages {
+          _ if tracker.fallback => {
+            //the allocation may have been resized in place, but its backing size and alignment are unchanged.
+            if let (Some(fallback), Ok(backing_layout)) =
+              (self.fallback, Layout::from_size_align(tracker.size, expanded_layout.align()))
+            {
+              unsafe { fallback.dealloc(tracker.orig_ptr as *mut u8, backing_layout) };
+            }
+          }
+          0 => {
+            let _ = (bs.free_pool)(tracker.orig_ptr);
+          }
+          pages => {
+            #[cfg(feature = "guard-pages")]
+            if !self.remove_guard_page(tracker.orig_ptr as usize + tracker.size, tracker.guard_protected) {
+              //the allocation overran into its guard page. The tracker is intact, so the memory is still freed.
+              self.corrupted_free = Some((ptr, layout));
+            }
+            let _ = (bs.free_pages)(tracker.orig_ptr as usize as PhysicalAddress, pages + GUARD_PAGES);
+          }
+        }
+        self.stats.record_dealloc(tracker.size, layout.size());
+        self.memory_type_totals.record_dealloc(tracker.memory_type, tracker.size);
+      }
+    }
+  }
+
+  // implement realloc. Allocations with a tracking structure are resized in place if the memory already backing the
+  // allocation has room for the new size (page allocations shrunk in place return the pages they no longer need);
+  // otherwise a new allocation is made and the contents are copied to it.
+  fn boot_services_realloc(
+    &mut self,
+    ptr: *mut u8,
+    layout: Layout,
+    new_layout: Layout,
+    origin: AllocOrigin,
+  ) -> Result<*mut u8, Status> {
+    if self.sealed {
+      return Err(Status::UNSUPPORTED);
+    }
+    //zero-size allocations have no backing memory (or tracking structure) to resize.
+    if layout.size() != 0
+      && new_layout.size() != 0
+      && self.is_tracked(layout)
+      && self.probed_index(ptr, layout).is_none()
+      && self.resize_tracked_in_place(ptr, layout, new_layout, origin)
+    {
+      return Ok(ptr);
+    }
+
+    //a moved allocation keeps its tag.
+    #[cfg(feature = "pool-tags")]
+    let origin = origin.with_tag(tracker_ptr(ptr, layout).map(|tracker| unsafe { (*tracker).tag }));
+    let new_ptr = self.boot_services_alloc(new_layout, None, origin)?;
+    //copy only the caller's data; the new allocation has its own tracking structure (if required).
+    unsafe { core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_layout.size())) };
+    self.boot_services_dealloc(ptr, layout);
+    Ok(new_ptr)
+  }
+
+  // moves the tracking structure for an allocation to the position required by `new_layout`, if the memory backing the
+  // allocation is large enough and the alignment is unchanged, recording the allocation site of `origin` in it. Returns
+  // false (leaving the allocation untouched) if not.
+  #[cfg_attr(not(feature = "alloc-site"), allow(unused_variables))]
+  fn resize_tracked_in_place(&mut self, ptr: *mut u8, layout: Layout, new_layout: Layout, origin: AllocOrigin) -> bool {
+    if new_layout.align() != layout.align() {
+      return false;
+    }
+    let (Ok((_, tracking_offset)), Ok((new_expanded_layout, new_tracking_offset))) =
+      (layout.extend(Layout::new::<AllocationTracker>()), new_layout.extend(Layout::new::<AllocationTracker>()))
+    else {
+      return false;
+    };
+
+    let tracker = unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>().read() };
+    if tracker.signature != ALLOC_TRACKER_SIG
+      || ptr as usize + new_expanded_layout.size() > tracker.orig_ptr as usize + tracker.size
+    {
+      return false;
+    }
+
+    #[cfg(feature = "leak-check")]
+    self.unlink_outstanding(&tracker);
+    let new_tracker = unsafe { ptr.add(new_tracking_offset).cast::<AllocationTracker>() };
+    let page_backed = tracker.pages > 0;
+    unsafe { new_tracker.write(tracker) };
+    #[cfg(feature = "alloc-site")]
+    unsafe {
+      (*new_tracker).site = origin.site
+    };
+    self.stats.requested_bytes = self.stats.requested_bytes - layout.size() + new_layout.size();
+    if page_backed {
+      self.release_surplus_pages(unsafe { &mut *new_tracker }, new_expanded_layout.size(), (ptr, layout));
+    }
+    #[cfg(feature = "leak-check")]
+    {
+      unsafe { (*new_tracker).layout = new_layout };
+      self.link_outstanding(new_tracker);
+    }
+    true
+  }
+
+  // returns the pages backing a page allocation beyond the `used` bytes it still needs (measured from the start of the
+  // allocation) to firmware, after it has been shrunk in place. Any guard page is moved down to follow the remaining
+  // pages; `allocation` is reported as corrupt if the old guard page was overwritten.
+  //
+  // A page allocation is only shrunk in place if its alignment is unchanged, i.e. it still requires page (or greater)
+  // alignment. It is trimmed rather than moved to the pool because a pool allocation would need up to a page of slack
+  // to provide that alignment, so moving would save no memory but cost a copy; an allocation whose new layout no longer
+  // needs page alignment is moved to the pool by boot_services_realloc.
+  #[cfg_attr(not(feature = "guard-pages"), allow(unused_variables))]
+  fn release_surplus_pages(&mut self, tracker: &mut AllocationTracker, used: usize, allocation: (*mut u8, Layout)) {
+    let pages = used.div_ceil(UEFI_PAGE_SIZE);
+    let Some(bs) = self.boot_services.and_then(|bs| unsafe { bs.as_mut() }) else { return };
+    if pages >= tracker.pages {
+      return;
+    }
+    let base = tracker.orig_ptr as usize;
+    #[cfg(feature = "guard-pages")]
+    {
+      if !self.remove_guard_page(base + tracker.size, tracker.guard_protected) {
+        //the allocation overran into its guard page before it was shrunk.
+        self.corrupted_free = Some(allocation);
+      }
+      tracker.guard_protected = self.place_guard_page(base + pages * UEFI_PAGE_SIZE);
+    }
+    let surplus = tracker.pages - pages;
+    let _ = (bs.free_pages)((base + (pages + GUARD_PAGES) * UEFI_PAGE_SIZE) as PhysicalAddress, surplus);
+    self.stats.outstanding_bytes -= surplus * UEFI_PAGE_SIZE;
+    self.memory_type_totals.record_dealloc(tracker.memory_type, surplus * UEFI_PAGE_SIZE);
+    tracker.pages = pages;
+    tracker.size = pages * UEFI_PAGE_SIZE;
+  }
+
+  // fills the guard page at `address` with the canary and, if a guard page callback is set, makes it inaccessible.
+  // Returns true if the guard page was made inaccessible.
+  #[cfg(feature = "guard-pages")]
+  fn place_guard_page(&self, address: usize) -> bool {
+    unsafe { (address as *mut u8).write_bytes(GUARD_CANARY, UEFI_PAGE_SIZE) };
+    self.guard_page_callback.is_some_and(|callback| callback(address as PhysicalAddress, true) == Status::SUCCESS)
+  }
+
+  // makes the guard page at `address` accessible again if it was protected, and verifies the canary. Returns false if
+  // the canary was overwritten.
+  #[cfg(feature = "guard-pages")]
+  fn remove_guard_page(&self, address: usize, protected: bool) -> bool {
+    if let (true, Some(callback)) = (protected, self.guard_page_callback) {
+      let _ = callback(address as PhysicalAddress, false);
+    }
+    unsafe { core::slice::from_raw_parts(address as *const u8, UEFI_PAGE_SIZE) }
+      .iter()
+      .all(|byte| *byte == GUARD_CANARY)
+  }
+
+  // adds a tracking structure to the head of the list of outstanding allocations.
+  #[cfg(feature = "leak-check")]
+  fn link_outstanding(&mut self, tracker: *mut AllocationTracker) {
+    unsafe {
+      (*tracker).prev = core::ptr::null_mut();
+      (*tracker).next = self.outstanding;
+      if let Some(head) = self.outstanding.as_mut() {
+        head.prev = tracker;
+      }
+    }
+    self.outstanding = tracker;
+  }
+
+  // removes a tracking structure from the list of outstanding allocations. Only the links of `tracker` are used, so
+  // it may be a copy of the structure in the list.
+  #[cfg(feature = "leak-check")]
+  fn unlink_outstanding(&mut self, tracker: &AllocationTracker) {
+    unsafe {
+      match tracker.prev.as_mut() {
+        Some(prev) => prev.next = tracker.next,
+        None => self.outstanding = tracker.next,
+      }
+      if let Some(next) = tracker.next.as_mut() {
+        next.prev = tracker.prev;
       }
     }
   }
@@ -150,39 +1283,666 @@ impl BootServicesAllocator {
 
 /// A spin-locked allocator implementation.
 ///
-/// Provides a locked allocator instance (using [`spin::Mutex`]) that is suitable for use as a
-/// [`core::alloc::GlobalAlloc`].
-pub struct SpinLockedAllocator {
-  inner: spin::Mutex<BootServicesAllocator>,
+/// Provides a locked allocator instance (using [`spin::Mutex`] by default) that is suitable for use as a
+/// [`core::alloc::GlobalAlloc`]. Another lock can be supplied as the `L` parameter, e.g. an interrupt-disabling lock;
+/// see [`RawMutex`]. [`GLOBAL_ALLOCATOR`] uses [`DefaultRawMutex`].
+///
+/// Zero-size requests are not passed to firmware: they return a non-null, suitably aligned dangling pointer, and
+/// freeing it does nothing.
+///
+/// With the `single-threaded` feature, the default lock is [`UnsyncRawMutex`], an unsynchronized cell that avoids
+/// atomic operations on the allocation path. The allocator must then only be used from a single processor. A re-entrant
+/// allocation still fails as described below, but a re-entrant free or other operation (e.g. one made from an event
+/// notification at raised TPL that interrupts an allocation) panics, since nothing could release the lock it waits for.
+///
+/// ## Re-entrancy
+///
+/// An allocation that re-enters the allocator while another allocation is in progress (e.g. because firmware's
+/// AllocatePool() itself allocates) fails with `Status::ACCESS_DENIED` rather than waiting for the lock, which would
+/// deadlock. Boot services may only be used from the boot processor, so the allocator assumes that the lock being held
+/// during an allocation always means re-entry; the alloc error hook is not called for such failures. Frees and other
+/// operations still wait for the lock.
+pub struct SpinLockedAllocator<L = DefaultRawMutex> {
+  inner: lock::Mutex<L, BootServicesAllocator>,
+  //set while the lock is held by an allocation, so that a re-entrant allocation can fail rather than deadlock. Kept
+  //outside the lock so that it can be read when the lock is unavailable.
+  allocating: AtomicBool,
+  //copy of the statistics kept outside the lock, so that stats() does not contend with allocations.
+  stats: AtomicStats,
 }
 
-impl SpinLockedAllocator {
-  // Create a new instance. const fn to allow static initialization.
-  const fn new() -> Self {
-    SpinLockedAllocator { inner: spin::Mutex::new(BootServicesAllocator::new()) }
+impl<L: RawMutex> SpinLockedAllocator<L> {
+  /// Creates a new, uninitialized allocator. const fn to allow static initialization.
+  ///
+  /// Most drivers should use [`GLOBAL_ALLOCATOR`]; separate instances are for allocators with a custom lock, which can
+  /// be used with the `allocator_api` collections or registered as the `#[global_allocator]` of a crate that does not
+  /// link [`GLOBAL_ALLOCATOR`] as its global allocator.
+  pub const fn new() -> Self {
+    SpinLockedAllocator {
+      inner: lock::Mutex::new(BootServicesAllocator::new()),
+      allocating: AtomicBool::new(false),
+      stats: AtomicStats::new(),
+    }
+  }
+
+  // runs `f` with the lock held on behalf of an allocation. Returns Status::ACCESS_DENIED without running `f` if the
+  // lock is held by another allocation, i.e. if this allocation re-entered the allocator.
+  fn lock_for_alloc<T>(&self, f: impl FnOnce(&mut BootServicesAllocator) -> T) -> Result<T, Status> {
+    let mut inner = match self.inner.try_lock() {
+      Some(inner) => inner,
+      None if self.allocating.load(Ordering::Acquire) => return Err(Status::ACCESS_DENIED),
+      None => self.inner.lock(),
+    };
+    self.allocating.store(true, Ordering::Release);
+    let result = f(&mut inner);
+    self.stats.publish(&inner.stats);
+    self.allocating.store(false, Ordering::Release);
+    Ok(result)
   }
 
   /// Initialize the allocator.
   ///
   /// This routine initializes the allocator by providing a pointer to the global EFI Boot Services table that will
   /// supply pointers to the AllocatePool() and FreePool() primitives that implement memory allocation.
-  pub fn init(&self, boot_services: *mut BootServices) {
-    self.inner.lock().init(boot_services);
+  ///
+  /// Returns `Status::ALREADY_STARTED` without changing anything if the allocator is already initialized, since
+  /// replacing the table could strand allocations made with the previous one. Use [`Self::reinit()`] to replace it
+  /// deliberately. Returns `Status::INVALID_PARAMETER` if `boot_services` is null, leaving the allocator uninitialized.
+  pub fn init(&self, boot_services: *mut BootServices) -> Result<(), Status> {
+    self.inner.lock().init(boot_services)
   }
-}
 
-unsafe impl GlobalAlloc for SpinLockedAllocator {
-  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-    self.inner.lock().boot_services_alloc(layout)
+  /// Re-initializes the allocator with a new EFI Boot Services table, whether or not it is already initialized.
+  ///
+  /// Outstanding allocations are freed through the new table, so it must be able to free memory allocated through
+  /// the previous one.
+  pub fn reinit(&self, boot_services: *mut BootServices) {
+    self.inner.lock().boot_services = Some(boot_services);
   }
 
-  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-    self.inner.lock().boot_services_dealloc(ptr, layout)
+  /// Seals the allocator so that boot services are no longer used.
+  ///
+  /// Boot services (and the boot services table) are no longer available after ExitBootServices, so this should be
+  /// called from an ExitBootServices event notification. Once sealed, allocations fail cleanly by returning null, and
+  /// frees are ignored.
+  pub fn seal(&self) {
+    self.inner.lock().sealed = true;
   }
-}
 
-unsafe impl Sync for SpinLockedAllocator {}
-unsafe impl Send for SpinLockedAllocator {}
+  /// Sets a fallback allocator that is used when firmware cannot satisfy an allocation, e.g. a static arena on
+  /// platforms where the boot services pool is constrained.
+  ///
+  /// Allocations made from the fallback are freed back to it. To tell them apart, every allocation carries a tracking
+  /// structure once a fallback is set, so this must be called before any allocations are made.
+  ///
+  /// ## Panics
+  ///
+  /// Panics if any allocations are outstanding.
+  pub fn set_fallback(&self, f: &'static dyn GlobalAlloc) {
+    let mut inner = self.inner.lock();
+    assert_eq!(inner.stats.outstanding_allocations, 0, "fallback allocator set with allocations outstanding");
+    inner.fallback = Some(f);
+    inner.track_all = true;
+  }
+
+  /// Sets a callback used to make guard pages inaccessible, e.g. using the platform's memory attribute protocol.
+  ///
+  /// Requires the `guard-pages` feature, with which each page allocation (see the crate documentation) is followed by
+  /// an extra guard page, so that overrunning the last page of the allocation faults immediately. The callback is
+  /// called with the address of the guard page and `true` to make it inaccessible once the allocation is made, and with
+  /// `false` to make it accessible again before it is freed; it must restore access whenever it reported success
+  /// removing it. The allocator lock is held while it is called, so it must not allocate.
+  ///
+  /// Guard pages are also filled with a canary, which is verified when the allocation is freed. If no callback is set
+  /// (or it fails), the guard page remains accessible and an overrun is only detected then: the memory is still freed,
+  /// and the corruption handler (see [`Self::set_corruption_handler()`]) is called. Guard pages are not counted in the
+  /// allocation statistics.
+  #[cfg(feature = "guard-pages")]
+  pub fn set_guard_page_callback(&self, callback: fn(PhysicalAddress, bool) -> Status) {
+    self.inner.lock().guard_page_callback = Some(callback);
+  }
+
+  /// Sets the memory type used for subsequent allocations.
+  ///
+  /// Defaults to `BOOT_SERVICES_DATA`. Drivers whose allocations must survive into runtime may use e.g.
+  /// `RUNTIME_SERVICES_DATA`. Changing the memory type does not affect existing allocations.
+  pub fn set_memory_type(&self, mem_type: MemoryType) {
+    self.inner.lock().memory_type = mem_type;
+  }
+
+  /// Sets the largest allocation size (in bytes) that will be requested from firmware.
+  ///
+  /// Requests larger than `size` fail immediately (calling the alloc error hook, if set) without calling firmware, to
+  /// catch bugs that produce absurd allocation sizes. Defaults to unlimited.
+  pub fn set_max_allocation(&self, size: usize) {
+    self.inner.lock().max_allocation = size;
+  }
+
+  /// Sets the alignment (in bytes) of the pointers returned by AllocatePool() on this platform.
+  ///
+  /// UEFI only guarantees 8-byte alignment for pool allocations, but many implementations return 16-byte aligned
+  /// pointers. Requests with an alignment up to `bytes` are allocated directly from the pool, rather than with the
+  /// extra space and tracking structure needed to align them. Defaults to 8; values below 8 are ignored.
+  ///
+  /// While the pool alignment is below 16, 16-byte aligned requests (common for SIMD types) are first tried as a plain
+  /// pool allocation, which is kept if the pointer returned happens to be aligned, and otherwise freed and replaced by
+  /// an aligned allocation with a tracking structure. Up to 64 such untracked allocations are recorded, so that they
+  /// are freed directly; while all are in use, 16-byte aligned requests are always tracked.
+  ///
+  /// Allocations made before and after the change would be freed differently, so this must be called before any
+  /// allocations are made.
+  ///
+  /// ## Panics
+  ///
+  /// Panics if `bytes` is not a power of two, or if any allocations are outstanding.
+  pub fn set_pool_alignment(&self, bytes: usize) {
+    assert!(bytes.is_power_of_two(), "pool alignment must be a power of two");
+    let mut inner = self.inner.lock();
+    assert_eq!(inner.stats.outstanding_allocations, 0, "pool alignment set with allocations outstanding");
+    inner.pool_alignment = bytes.max(UEFI_POOL_ALIGNMENT);
+  }
+
+  /// Enables arena mode, in which small allocations are carved out of a single pool block of `block_size` bytes
+  /// allocated up front, rather than each being requested from firmware. This avoids the cost of an AllocatePool() call
+  /// for the many short-lived allocations typical of boot, e.g. while parsing configuration.
+  ///
+  /// Requests of up to 256 bytes that would otherwise be allocated directly from the pool (i.e. that carry no tracking
+  /// structure, see [`Self::set_pool_alignment()`]) are served from the block, with a free list for reuse. Larger
+  /// requests, and small ones while the block is exhausted, are allocated from the pool as usual. With the `leak-check`
+  /// feature, or once a fallback allocator is set, every allocation carries a tracking structure, so none are served
+  /// from the block. Allocations carved out of the block are counted in the statistics with their size in the block,
+  /// but the block itself is not.
+  ///
+  /// Returns `Status::ALREADY_STARTED` if an arena block is already allocated, `Status::INVALID_PARAMETER` if
+  /// `block_size` is zero, or the failing status if the block could not be allocated.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// GLOBAL_ALLOCATOR.enable_arena(0x10000).unwrap();
+  /// //make short-lived allocations.
+  /// GLOBAL_ALLOCATOR.disable_arena();
+  /// ```
+  pub fn enable_arena(&self, block_size: usize) -> Result<(), Status> {
+    self.lock_for_alloc(|inner| inner.enable_arena(block_size))?
+  }
+
+  /// Disables arena mode (see [`Self::enable_arena()`]), so that all subsequent allocations are made from the pool.
+  ///
+  /// The arena block is freed once every allocation carved out of it has been freed: immediately if none are
+  /// outstanding, and otherwise along with the last of them.
+  pub fn disable_arena(&self) {
+    let mut inner = self.inner.lock();
+    if let Some(arena) = inner.arena.as_mut() {
+      arena.disabled = true;
+    }
+    inner.release_arena();
+  }
+
+  /// Sets whether freed memory is zeroed before it is returned to firmware, e.g. for drivers that handle key material.
+  /// Defaults to off.
+  ///
+  /// Unlike the `poison` feature (which is intended for debugging, and is overridden while this is on), this is a
+  /// runtime setting. Memory is not zeroed once the allocator is sealed, since it is then not returned to firmware.
+  pub fn set_zero_on_free(&self, enabled: bool) {
+    self.inner.lock().zero_on_free = enabled;
+  }
+
+  /// Sets a hook that is called with the requested layout when an allocation fails.
+  ///
+  /// The hook is called before the failed allocation returns null, e.g. to log diagnostics before the alloc error
+  /// handler aborts. It is called without the allocator lock held, so it may itself use the allocator.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use core::alloc::Layout;
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// fn alloc_error(_layout: Layout) {
+  ///   //report the failed allocation.
+  /// }
+  ///
+  /// GLOBAL_ALLOCATOR.set_alloc_error_hook(alloc_error);
+  /// ```
+  pub fn set_alloc_error_hook(&self, hook: fn(Layout)) {
+    self.inner.lock().alloc_error_hook = Some(hook);
+  }
+
+  /// Sets the number of times an allocation that firmware fails is retried before giving up, for firmware where
+  /// AllocatePool() can fail transiently under memory pressure. Defaults to 0 (no retries).
+  ///
+  /// The alloc error hook is only called if the final attempt fails. Requests that fail before reaching firmware (e.g.
+  /// because they exceed [`Self::set_max_allocation()`]) are not retried.
+  pub fn set_alloc_retry(&self, count: u32) {
+    self.inner.lock().alloc_retries = count;
+  }
+
+  /// Sets a callback that is called before each retry of a failed allocation (see [`Self::set_alloc_retry()`]), e.g. to
+  /// dispatch pending events that may release memory.
+  ///
+  /// The callback is called with the allocator lock held; allocations made from it (or from anything it dispatches)
+  /// fail with `Status::ACCESS_DENIED`, as described under [Re-entrancy](SpinLockedAllocator#re-entrancy).
+  pub fn set_alloc_retry_callback(&self, callback: fn()) {
+    self.inner.lock().alloc_retry_callback = Some(callback);
+  }
+
+  // completes an allocation, invoking the alloc error hook if the allocation failed. Must be called without the lock
+  // held.
+  fn complete_alloc(
+    result: Result<*mut u8, Status>,
+    hook: Option<fn(Layout)>,
+    layout: Layout,
+  ) -> Result<*mut u8, Status> {
+    if let (Err(_), Some(hook)) = (result, hook) {
+      hook(layout);
+    }
+    result
+  }
+
+  /// Allocates memory as described by `layout`, returning the status reported by firmware if the allocation fails.
+  ///
+  /// Behaves like [`GlobalAlloc::alloc`] (including calling the alloc error hook on failure), but preserves the reason
+  /// for the failure, e.g. `Status::OUT_OF_RESOURCES` or `Status::INVALID_PARAMETER` from firmware, or
+  /// `Status::NOT_READY` if the allocator has not been initialized.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use core::alloc::Layout;
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// match GLOBAL_ALLOCATOR.try_alloc(Layout::from_size_align(0x100, 0x1000).unwrap()) {
+  ///   Ok(ptr) => {
+  ///     //use the allocation.
+  ///   }
+  ///   Err(status) => {
+  ///     //report the status.
+  ///   }
+  /// }
+  /// ```
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  pub fn try_alloc(&self, layout: Layout) -> Result<*mut u8, Status> {
+    self.alloc_from(layout, AllocOrigin::from_site(caller_site()))
+  }
+
+  // try_alloc() on behalf of `origin`.
+  fn alloc_from(&self, layout: Layout, origin: AllocOrigin) -> Result<*mut u8, Status> {
+    let (result, hook) =
+      self.lock_for_alloc(|inner| (inner.boot_services_alloc(layout, None, origin), inner.alloc_error_hook))?;
+    Self::complete_alloc(result, hook, layout)
+  }
+
+  /// Allocates memory as described by `layout` from physical addresses at or below `max_address`, e.g. for a DMA buffer
+  /// that must be below 4 GiB. Returns null if the allocation cannot be satisfied.
+  ///
+  /// The allocation is made from pages allocated with `AllocateMaxAddress`, so its alignment is raised to the UEFI page
+  /// size (if it is not larger already), and it must be freed with that alignment, i.e. with
+  /// `layout.align_to(0x1000)`. The fallback allocator (see [`Self::set_fallback()`]) is not used, since it cannot
+  /// satisfy the constraint. The constraint only applies to this allocation: reallocating it may move it elsewhere.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use core::alloc::{GlobalAlloc, Layout};
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// let layout = Layout::from_size_align(0x200, 0x1000).unwrap();
+  /// let buffer = GLOBAL_ALLOCATOR.alloc_in_range(layout, 0xFFFF_FFFF);
+  /// if !buffer.is_null() {
+  ///   //program the device with the buffer address.
+  ///   unsafe { GLOBAL_ALLOCATOR.dealloc(buffer, layout) };
+  /// }
+  /// ```
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  pub fn alloc_in_range(&self, layout: Layout, max_address: u64) -> *mut u8 {
+    let origin = AllocOrigin::from_site(caller_site());
+    let Ok(layout) = layout.align_to(UEFI_PAGE_SIZE) else { return core::ptr::null_mut() };
+    let Ok((result, hook)) = self.lock_for_alloc(|inner| {
+      (inner.boot_services_alloc(layout, Some(max_address as PhysicalAddress), origin), inner.alloc_error_hook)
+    }) else {
+      return core::ptr::null_mut();
+    };
+    Self::complete_alloc(result, hook, layout).unwrap_or(core::ptr::null_mut())
+  }
+
+  /// Sets a handler that is called when heap corruption is detected while freeing an allocation.
+  ///
+  /// Allocations that need more than pool alignment carry a tracking structure that is verified when they are freed.
+  /// If it is corrupt (e.g. due to a buffer overrun, or because the pointer was not allocated by this allocator), the
+  /// memory is not freed and the handler is called with the pointer and layout passed to `dealloc`. If no handler is
+  /// set, the allocator panics. The handler is called without the allocator lock held.
+  pub fn set_corruption_handler(&self, handler: fn(*mut u8, Layout)) {
+    self.inner.lock().corruption_handler = Some(handler);
+  }
+
+  // reports a free that was refused due to heap corruption. Must be called without the lock held.
+  fn report_corruption(corrupted_free: Option<(*mut u8, Layout)>, handler: Option<fn(*mut u8, Layout)>) {
+    match (corrupted_free, handler) {
+      (None, _) => (),
+      (Some((ptr, layout)), Some(handler)) => handler(ptr, layout),
+      (Some((ptr, layout)), None) => panic!("heap corruption detected freeing {:?} with {:?}", ptr, layout),
+    }
+  }
+
+  // resizes the allocation at `ptr` from `layout` to `new_layout` (which may have a different alignment), preserving
+  // its contents up to the smaller of the two sizes. The allocation site of `origin` is recorded for the resized
+  // allocation.
+  fn reallocate(
+    &self,
+    ptr: *mut u8,
+    layout: Layout,
+    new_layout: Layout,
+    origin: AllocOrigin,
+  ) -> Result<*mut u8, Status> {
+    let (result, hook, corrupted_free, handler) = self.lock_for_alloc(|inner| {
+      let result = inner.boot_services_realloc(ptr, layout, new_layout, origin);
+      (result, inner.alloc_error_hook, inner.corrupted_free.take(), inner.corruption_handler)
+    })?;
+    Self::report_corruption(corrupted_free, handler);
+    Self::complete_alloc(result, hook, new_layout)
+  }
+
+  /// Returns a snapshot of the allocation statistics for this allocator.
+  ///
+  /// The statistics are read from a copy the allocator updates after each allocation and free, without acquiring the
+  /// allocator lock, so monitoring does not delay allocations (and may be done while the lock is held, e.g. from an
+  /// event notification that interrupted an allocation). The fields of a snapshot are always consistent with each
+  /// other, describing the state after the same operation.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// let stats = GLOBAL_ALLOCATOR.stats();
+  /// assert!(stats.peak_bytes >= stats.outstanding_bytes);
+  /// ```
+  pub fn stats(&self) -> AllocationStats {
+    self.stats.load()
+  }
+
+  /// Returns the outstanding bytes for each memory type that has been allocated from (see [`Self::set_memory_type()`]),
+  /// in the order the types were first used.
+  ///
+  /// Up to [`MAX_STATS_MEMORY_TYPES`] memory types are counted; allocations with further types are not, so the totals
+  /// then no longer add up to the `outstanding_bytes` of [`Self::stats()`]. Allocations with pool alignment (which
+  /// carry no tracking structure) made with a type other than `BOOT_SERVICES_DATA` are recorded in a side table of 32
+  /// entries until they are freed; when it is full, further such allocations are counted under `BOOT_SERVICES_DATA`.
+  /// Memory types are never removed, so a type whose allocations have all been freed is reported with zero bytes.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// for entry in GLOBAL_ALLOCATOR.memory_type_stats().iter().flatten() {
+  ///   let _ = (entry.memory_type, entry.outstanding_bytes);
+  /// }
+  /// ```
+  pub fn memory_type_stats(&self) -> [Option<MemoryTypeStats>; MAX_STATS_MEMORY_TYPES] {
+    self.inner.lock().memory_type_totals.totals
+  }
+
+  /// Resets the resettable allocation statistics, e.g. between the phases of a self-test.
+  ///
+  /// The high-water mark (`peak_bytes`) restarts from the bytes currently outstanding, so that it reflects only
+  /// activity after the reset. The counts of outstanding bytes and allocations describe the live allocations, and are
+  /// not changed.
+  pub fn reset_stats(&self) {
+    let mut inner = self.inner.lock();
+    inner.stats.reset();
+    self.stats.publish(&inner.stats);
+  }
+
+  /// Writes a human-readable description of the allocator's configuration and statistics to `w`, one `name: value`
+  /// field per line.
+  ///
+  /// The state is copied while the lock is held and written after it is released, and nothing is allocated, so `w` may
+  /// be e.g. the AdvancedLogger's `LogWriter`.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// fn report(out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+  ///   GLOBAL_ALLOCATOR.describe(out)
+  /// }
+  /// ```
+  pub fn describe(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+    let (initialized, sealed, memory_type, max_allocation, pool_alignment, alloc_retries, fallback, stats) = {
+      let inner = self.inner.lock();
+      (
+        inner.boot_services.is_some(),
+        inner.sealed,
+        inner.memory_type,
+        inner.max_allocation,
+        inner.pool_alignment,
+        inner.alloc_retries,
+        inner.fallback.is_some(),
+        inner.stats,
+      )
+    };
+
+    writeln!(w, "initialized: {:}", initialized)?;
+    writeln!(w, "sealed: {:}", sealed)?;
+    writeln!(w, "memory type: {:}", memory_type)?;
+    match max_allocation {
+      usize::MAX => writeln!(w, "max allocation: unlimited")?,
+      size => writeln!(w, "max allocation: {:#x}", size)?,
+    }
+    writeln!(w, "pool alignment: {:#x}", pool_alignment)?;
+    writeln!(w, "alloc retries: {:}", alloc_retries)?;
+    writeln!(w, "fallback: {:}", if fallback { "set" } else { "none" })?;
+    writeln!(w, "outstanding allocations: {:}", stats.outstanding_allocations)?;
+    writeln!(w, "outstanding bytes: {:#x}", stats.outstanding_bytes)?;
+    writeln!(w, "requested bytes: {:#x}", stats.requested_bytes)?;
+    writeln!(w, "peak bytes: {:#x}", stats.peak_bytes)
+  }
+
+  /// Returns the number of allocations that have not been freed.
+  ///
+  /// Requires the `leak-check` feature.
+  #[cfg(feature = "leak-check")]
+  pub fn outstanding_count(&self) -> usize {
+    let mut count = 0;
+    self.for_each_outstanding(|_, _| count += 1);
+    count
+  }
+
+  /// Calls `f` with the pointer and layout of each allocation that has not been freed, most recent first.
+  ///
+  /// The allocator is locked while `f` is called, so `f` must not allocate or free memory with this allocator.
+  /// Requires the `leak-check` feature.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// GLOBAL_ALLOCATOR.for_each_outstanding(|ptr, layout| {
+  ///   //report the leaked allocation.
+  /// });
+  /// ```
+  #[cfg(feature = "leak-check")]
+  pub fn for_each_outstanding(&self, mut f: impl FnMut(*mut u8, Layout)) {
+    self.for_each_tracker(|ptr, tracker| f(ptr, tracker.layout));
+  }
+
+  // calls `f` with the pointer and tracking structure of each outstanding allocation, with the allocator locked.
+  #[cfg(feature = "leak-check")]
+  fn for_each_tracker(&self, mut f: impl FnMut(*mut u8, &AllocationTracker)) {
+    let inner = self.inner.lock();
+    let mut tracker = inner.outstanding;
+    while let Some(entry) = unsafe { tracker.as_ref() } {
+      //the tracker follows the allocation, at the offset given by the layout.
+      if let Ok((_, tracking_offset)) = entry.layout.extend(Layout::new::<AllocationTracker>()) {
+        f(unsafe { (tracker as *mut u8).sub(tracking_offset) }, entry);
+      }
+      tracker = entry.next;
+    }
+  }
+
+  /// Logs the address, size, and alignment (and, with the `pool-tags` feature, tag) of each allocation that has not
+  /// been freed at the given debug level, e.g. as a heap report at driver shutdown. With the `alloc-site` feature, each
+  /// line also reports the allocation site, as `allocated from <return address>`, where it is known. The site is the
+  /// return address of the call into the allocator, so for allocations made through the global allocator it is the
+  /// address of the compiler-generated allocation shim rather than of the code that allocated (see the crate
+  /// documentation).
+  ///
+  /// Nothing is logged if the advanced logger has not been initialized. Requires the `leak-check` feature.
+  ///
+  /// ## Example
+  /// ```no_run
+  /// use rust_advanced_logger_dxe::DEBUG_WARN;
+  /// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+  ///
+  /// GLOBAL_ALLOCATOR.dump_outstanding(DEBUG_WARN);
+  /// ```
+  #[cfg(feature = "leak-check")]
+  pub fn dump_outstanding(&self, level: usize) {
+    if !rust_advanced_logger_dxe::is_debug_initialized() {
+      return;
+    }
+    //the logger does not allocate, so it is safe to use while the allocator is locked.
+    self.for_each_tracker(|ptr, tracker| {
+      rust_advanced_logger_dxe::debugln!(
+        level,
+        "outstanding allocation {:p}: size {:#x}, align {:#x}{:}",
+        ptr,
+        tracker.layout.size(),
+        tracker.layout.align(),
+        TrackerDetails(tracker)
+      );
+    });
+  }
+
+  /// Sets the tag given to allocations that are not made with [`Self::alloc_tagged()`]. Defaults to zero.
+  ///
+  /// Requires the `pool-tags` feature.
+  #[cfg(feature = "pool-tags")]
+  pub fn set_default_tag(&self, tag: u32) {
+    self.inner.lock().default_tag = tag;
+  }
+
+  /// Allocates memory as described by `layout`, tagging the allocation with `tag`.
+  ///
+  /// Firmware pool allocations cannot carry a tag, so the tag is stored in the allocation's tracking structure, where
+  /// it is reported by [`Self::dump_outstanding()`] and [`Self::tag_totals()`]. Returns null if the allocation fails.
+  /// Requires the `pool-tags` feature.
+  #[cfg(feature = "pool-tags")]
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  pub fn alloc_tagged(&self, layout: Layout, tag: u32) -> *mut u8 {
+    let origin = AllocOrigin::from_site(caller_site()).with_tag(Some(tag));
+    self.alloc_from(layout, origin).unwrap_or(core::ptr::null_mut())
+  }
+
+  /// Returns the totals for the outstanding allocations tagged with `tag`.
+  ///
+  /// Requires the `pool-tags` feature.
+  #[cfg(feature = "pool-tags")]
+  pub fn tag_totals(&self, tag: u32) -> TagTotals {
+    let mut totals = TagTotals::default();
+    self.for_each_tracker(|_, tracker| {
+      if tracker.tag == tag {
+        totals.allocations += 1;
+        totals.requested_bytes += tracker.layout.size();
+      }
+    });
+    totals
+  }
+}
+
+unsafe impl<L: RawMutex> GlobalAlloc for SpinLockedAllocator<L> {
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    self.alloc_from(layout, AllocOrigin::from_site(caller_site())).unwrap_or(core::ptr::null_mut())
+  }
+
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+    let origin = AllocOrigin::from_site(caller_site());
+    let Ok((result, hook)) =
+      self.lock_for_alloc(|inner| (inner.boot_services_alloc_zeroed(layout, origin), inner.alloc_error_hook))
+    else {
+      return core::ptr::null_mut();
+    };
+    Self::complete_alloc(result, hook, layout).unwrap_or(core::ptr::null_mut())
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    let (corrupted_free, handler) = {
+      let mut inner = self.inner.lock();
+      inner.boot_services_dealloc(ptr, layout);
+      self.stats.publish(&inner.stats);
+      (inner.corrupted_free.take(), inner.corruption_handler)
+    };
+    Self::report_corruption(corrupted_free, handler);
+  }
+
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let origin = AllocOrigin::from_site(caller_site());
+    let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else { return core::ptr::null_mut() };
+    self.reallocate(ptr, layout, new_layout, origin).unwrap_or(core::ptr::null_mut())
+  }
+}
+
+/// Allows a [`SpinLockedAllocator`] to be used for individual collections via the [`core::alloc::Allocator`] trait,
+/// e.g. with `Box::new_in` or `Vec::new_in`, independently of the global allocator.
+///
+/// [`AllocError`] cannot carry the reason for a failure; use [`SpinLockedAllocator::try_alloc()`] where the failing
+/// status is needed.
+///
+/// ## Example
+/// ```no_run
+/// #![feature(allocator_api)]
+/// use rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR;
+///
+/// let mut values = Vec::new_in(&GLOBAL_ALLOCATOR);
+/// values.push(42u32);
+/// ```
+unsafe impl<L: RawMutex> Allocator for SpinLockedAllocator<L> {
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+    let ptr = self.alloc_from(layout, AllocOrigin::from_site(caller_site())).map_err(|_| AllocError)?;
+    NonNull::new(ptr).map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size())).ok_or(AllocError)
+  }
+
+  unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+    GlobalAlloc::dealloc(self, ptr.as_ptr(), layout)
+  }
+
+  //the default grow/shrink implementations allocate, copy and free; these resize tracked allocations in place where
+  //the backing memory allows, and otherwise move them while keeping their tracking structures intact.
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+    let origin = AllocOrigin::from_site(caller_site());
+    let ptr = self.reallocate(ptr.as_ptr(), old_layout, new_layout, origin).map_err(|_| AllocError)?;
+    NonNull::new(ptr).map(|ptr| NonNull::slice_from_raw_parts(ptr, new_layout.size())).ok_or(AllocError)
+  }
+
+  unsafe fn grow_zeroed(
+    &self,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+  ) -> Result<NonNull<[u8]>, AllocError> {
+    let new_ptr = self.grow(ptr, old_layout, new_layout)?;
+    new_ptr.cast::<u8>().as_ptr().add(old_layout.size()).write_bytes(0, new_layout.size() - old_layout.size());
+    Ok(new_ptr)
+  }
+
+  #[cfg_attr(feature = "alloc-site", inline(never))]
+  unsafe fn shrink(
+    &self,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+  ) -> Result<NonNull<[u8]>, AllocError> {
+    let origin = AllocOrigin::from_site(caller_site());
+    let ptr = self.reallocate(ptr.as_ptr(), old_layout, new_layout, origin).map_err(|_| AllocError)?;
+    NonNull::new(ptr).map(|ptr| NonNull::slice_from_raw_parts(ptr, new_layout.size())).ok_or(AllocError)
+  }
+}
+
+unsafe impl<L: RawMutex> Sync for SpinLockedAllocator<L> {}
+unsafe impl<L: RawMutex> Send for SpinLockedAllocator<L> {}
 
 #[cfg(test)]
 mod tests {
@@ -191,59 +1951,55 @@ mod tests {
   use core::{
     alloc::{GlobalAlloc, Layout},
     ffi::c_void,
-    mem::MaybeUninit,
   };
   use std::alloc::System;
 
   use r_efi::{
-    efi::Status,
-    system::{BootServices, BOOT_SERVICES_DATA},
+    efi::{PhysicalAddress, Status},
+    system::{
+      AllocateType, BootServices, MemoryType, ALLOCATE_ANY_PAGES, ALLOCATE_MAX_ADDRESS, BOOT_SERVICES_DATA,
+      RUNTIME_SERVICES_DATA,
+    },
+  };
+  use std::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread_local,
   };
-  use std::collections::BTreeMap;
-
-  use crate::{AllocationTracker, SpinLockedAllocator, ALLOC_TRACKER_SIG};
 
-  static ALLOCATION_TRACKER: spin::Mutex<BTreeMap<usize, Layout>> = spin::Mutex::new(BTreeMap::new());
+  use crate::{
+    testing::{self, mock_free_pool, ALLOCATION_TRACKER, PAGE_ALLOCATION_TRACKER, PAGE_BACKING_TRACKER},
+    tracked_pool_size, AllocationStats, AllocationTracker, RawMutex, SpinLockedAllocator, UnsyncRawMutex,
+    ALLOC_TRACKER_SIG, GUARD_PAGES, MAX_STATS_MEMORY_TYPES, UEFI_PAGE_SIZE,
+  };
 
-  extern "efiapi" fn mock_allocate_pool(
-    pool_type: r_efi::system::MemoryType,
-    size: usize,
-    buffer: *mut *mut c_void,
-  ) -> Status {
+  // the testing module's mocks, additionally checking that the allocator uses the default memory type.
+  extern "efiapi" fn mock_allocate_pool(pool_type: MemoryType, size: usize, buffer: *mut *mut c_void) -> Status {
     assert_eq!(pool_type, BOOT_SERVICES_DATA);
-
-    unsafe {
-      let layout = Layout::from_size_align(size, 8).unwrap();
-      let ptr = System.alloc(layout) as *mut c_void;
-      buffer.write(ptr);
-      let existing_key = ALLOCATION_TRACKER.lock().insert(ptr as usize, layout);
-      assert!(existing_key.is_none());
-    }
-
-    Status::SUCCESS
+    testing::mock_allocate_pool(pool_type, size, buffer)
   }
 
-  extern "efiapi" fn mock_free_pool(buffer: *mut c_void) -> Status {
-    let layout = ALLOCATION_TRACKER.lock().remove(&(buffer as usize)).expect("freeing an un-allocated pointer");
-    unsafe {
-      System.dealloc(buffer as *mut u8, layout);
-    }
-
-    Status::SUCCESS
+  extern "efiapi" fn mock_allocate_pages(
+    allocation_type: AllocateType,
+    memory_type: MemoryType,
+    pages: usize,
+    memory: *mut PhysicalAddress,
+  ) -> Status {
+    assert_eq!(memory_type, BOOT_SERVICES_DATA);
+    testing::mock_allocate_pages(allocation_type, memory_type, pages, memory)
   }
 
   fn mock_boot_services() -> BootServices {
-    let boot_services = MaybeUninit::zeroed();
-    let mut boot_services: BootServices = unsafe { boot_services.assume_init() };
+    let mut boot_services = testing::mock_boot_services();
     boot_services.allocate_pool = mock_allocate_pool;
-    boot_services.free_pool = mock_free_pool;
+    boot_services.allocate_pages = mock_allocate_pages;
     boot_services
   }
 
   #[test]
   fn basic_alloc_and_dealloc() {
     static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
-    ALLOCATOR.init(&mut mock_boot_services());
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
 
     let layout = Layout::from_size_align(0x40, 0x8).unwrap();
     let ptr = unsafe { ALLOCATOR.alloc_zeroed(layout) };
@@ -257,12 +2013,12 @@ mod tests {
   #[test]
   fn big_alignment_should_allocate_tracking_structure() {
     static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
-    ALLOCATOR.init(&mut mock_boot_services());
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
 
-    let layout = Layout::from_size_align(0x40, 0x1000).unwrap();
+    let layout = Layout::from_size_align(0x40, 0x100).unwrap();
     let ptr = unsafe { ALLOCATOR.alloc_zeroed(layout) };
     assert!(!ptr.is_null());
-    assert_eq!(ptr.align_offset(0x1000), 0);
+    assert_eq!(ptr.align_offset(0x100), 0);
 
     // reconstruct a reference to the tracker structure at the end of the allocation.
     let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
@@ -278,4 +2034,1845 @@ mod tests {
 
     assert!(!ALLOCATION_TRACKER.lock().contains_key(&(orig_ptr_addr)));
   }
+
+  #[test]
+  fn page_alignment_should_allocate_pages() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    for (size, align, expected_pages) in
+      [(0x40, 0x1000, 1), (0x1000, 0x1000, 2), (0x2800, 0x1000, 3), (0x40, 0x4000, 1), (0x4000, 0x4000, 5)]
+    {
+      let layout = Layout::from_size_align(size, align).unwrap();
+      let ptr = unsafe { ALLOCATOR.alloc_zeroed(layout) };
+      assert!(!ptr.is_null());
+      assert_eq!(ptr.align_offset(align), 0);
+
+      let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+      let tracker =
+        unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>().as_mut().expect("tracking pointer is invalid") };
+      assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+      assert_eq!(tracker.pages, expected_pages);
+
+      let base = tracker.orig_ptr as usize;
+      assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&base), Some(&(expected_pages + GUARD_PAGES)));
+      assert!(!ALLOCATION_TRACKER.lock().contains_key(&base));
+
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      assert!(!PAGE_ALLOCATION_TRACKER.lock().contains_key(&base));
+    }
+  }
+
+  #[test]
+  fn large_alignment_should_return_unused_pages() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    //firmware base is offset from 64 KiB alignment by 0 (no waste), 1, and 15 pages.
+    for offset in [0, 1, 15] {
+      testing::set_page_base_offset(offset);
+      let layout = Layout::from_size_align(0x2000, 0x10000).unwrap();
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      testing::set_page_base_offset(0);
+      assert!(!ptr.is_null());
+      assert_eq!(ptr.align_offset(0x10000), 0);
+
+      let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+      let tracker =
+        unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>().as_mut().expect("tracking pointer is invalid") };
+      assert_eq!(tracker.pages, 3);
+      assert_eq!(tracker.orig_ptr as usize, ptr as usize);
+
+      //only the pages in use should remain allocated.
+      let base = ptr as usize;
+      assert_eq!(PAGE_ALLOCATION_TRACKER.lock().range(base - 0x10000..base + 0x20000).count(), 1);
+      assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&base), Some(&(3 + GUARD_PAGES)));
+
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      assert!(!PAGE_ALLOCATION_TRACKER.lock().contains_key(&base));
+      assert!(!PAGE_BACKING_TRACKER.lock().range(base - 0x10000..base + 0x20000).any(|_| true));
+    }
+  }
+
+  #[test]
+  fn stats_should_track_outstanding_and_peak_bytes() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    assert_eq!(ALLOCATOR.stats(), AllocationStats::default());
+
+    let tracker_size = core::mem::size_of::<AllocationTracker>();
+    let layouts = [
+      (
+        Layout::from_size_align(0x40, 0x8).unwrap(),
+        if cfg!(feature = "leak-check") { 0x40 + tracker_size } else { 0x40 },
+      ),
+      (Layout::from_size_align(0x40, 0x100).unwrap(), 0x40 + tracker_size + 0x100 - 8),
+      (Layout::from_size_align(0x40, 0x1000).unwrap(), UEFI_PAGE_SIZE),
+    ];
+
+    let mut ptrs = std::vec::Vec::new();
+    let mut expected_bytes = 0;
+    for (layout, expected_size) in layouts {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      ptrs.push((ptr, layout, expected_size));
+      expected_bytes += expected_size;
+      let stats = ALLOCATOR.stats();
+      assert_eq!(stats.outstanding_bytes, expected_bytes);
+      assert_eq!(stats.peak_bytes, expected_bytes);
+      assert_eq!(stats.outstanding_allocations, ptrs.len());
+    }
+    let peak = expected_bytes;
+
+    for (ptr, layout, expected_size) in ptrs.drain(..) {
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      expected_bytes -= expected_size;
+      assert_eq!(ALLOCATOR.stats().outstanding_bytes, expected_bytes);
+    }
+    assert_eq!(
+      ALLOCATOR.stats(),
+      AllocationStats { outstanding_bytes: 0, peak_bytes: peak, outstanding_allocations: 0, requested_bytes: 0 }
+    );
+  }
+
+  thread_local! {
+    static OBSERVED_MEMORY_TYPE: Cell<Option<MemoryType>> = Cell::new(None);
+  }
+
+  extern "efiapi" fn mock_allocate_pool_any_type(
+    pool_type: r_efi::system::MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+  ) -> Status {
+    OBSERVED_MEMORY_TYPE.with(|observed| observed.set(Some(pool_type)));
+    mock_allocate_pool(BOOT_SERVICES_DATA, size, buffer)
+  }
+
+  #[test]
+  fn set_memory_type_should_change_pool_type() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_any_type;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert_eq!(OBSERVED_MEMORY_TYPE.with(|observed| observed.take()), Some(BOOT_SERVICES_DATA));
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+
+    ALLOCATOR.set_memory_type(RUNTIME_SERVICES_DATA);
+    for layout in [layout, Layout::from_size_align(0x40, 0x100).unwrap()] {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      assert_eq!(OBSERVED_MEMORY_TYPE.with(|observed| observed.take()), Some(RUNTIME_SERVICES_DATA));
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+  }
+
+  #[test]
+  fn memory_type_stats_should_break_down_outstanding_bytes_by_type() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    //the testing module's mocks accept any memory type.
+    ALLOCATOR.init(&mut testing::mock_boot_services()).unwrap();
+    assert_eq!(ALLOCATOR.memory_type_stats(), [None; MAX_STATS_MEMORY_TYPES]);
+
+    let small = Layout::from_size_align(0x40, 0x8).unwrap();
+    let aligned = Layout::from_size_align(0x40, 0x100).unwrap();
+    let boot_services_data = [unsafe { ALLOCATOR.alloc(small) }, unsafe { ALLOCATOR.alloc(aligned) }];
+    ALLOCATOR.set_memory_type(RUNTIME_SERVICES_DATA);
+    let runtime_services_data = [unsafe { ALLOCATOR.alloc(small) }, unsafe { ALLOCATOR.alloc(aligned) }];
+    //allocations keep the type they were made with once the memory type changes back.
+    ALLOCATOR.set_memory_type(BOOT_SERVICES_DATA);
+
+    let outstanding = |memory_type| {
+      ALLOCATOR
+        .memory_type_stats()
+        .iter()
+        .flatten()
+        .find(|entry| entry.memory_type == memory_type)
+        .unwrap()
+        .outstanding_bytes
+    };
+    let total = ALLOCATOR.stats().outstanding_bytes;
+    assert_eq!(outstanding(BOOT_SERVICES_DATA), total / 2);
+    assert_eq!(outstanding(RUNTIME_SERVICES_DATA), total / 2);
+    let stats = ALLOCATOR.memory_type_stats();
+    assert_eq!(stats.iter().flatten().count(), 2);
+    assert_eq!(stats[0].unwrap().memory_type, BOOT_SERVICES_DATA);
+
+    unsafe {
+      ALLOCATOR.dealloc(runtime_services_data[0], small);
+      ALLOCATOR.dealloc(runtime_services_data[1], aligned);
+    }
+    assert_eq!(outstanding(RUNTIME_SERVICES_DATA), 0);
+    assert_eq!(outstanding(BOOT_SERVICES_DATA), ALLOCATOR.stats().outstanding_bytes);
+    unsafe {
+      ALLOCATOR.dealloc(boot_services_data[0], small);
+      ALLOCATOR.dealloc(boot_services_data[1], aligned);
+    }
+    assert_eq!(outstanding(BOOT_SERVICES_DATA), 0);
+  }
+
+  // returns the tracking structure for the given allocation.
+  fn tracker_for(ptr: *mut u8, layout: Layout) -> &'static AllocationTracker {
+    let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+    unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>().as_ref().expect("tracking pointer is invalid") }
+  }
+
+  #[test]
+  fn realloc_should_preserve_contents_and_tracker() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    for align in [0x8, 0x100, 0x1000] {
+      let layout = Layout::from_size_align(0x40, align).unwrap();
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      for index in 0..layout.size() {
+        unsafe { ptr.add(index).write(index as u8) };
+      }
+
+      //grow beyond the memory backing the allocation.
+      let grown_layout = Layout::from_size_align(0x2000, align).unwrap();
+      let grown_ptr = unsafe { ALLOCATOR.realloc(ptr, layout, grown_layout.size()) };
+      assert!(!grown_ptr.is_null());
+      assert_eq!(grown_ptr.align_offset(align), 0);
+      for index in 0..layout.size() {
+        assert_eq!(unsafe { grown_ptr.add(index).read() }, index as u8);
+      }
+      for index in layout.size()..grown_layout.size() {
+        unsafe { grown_ptr.add(index).write(index as u8) };
+      }
+      if align > 8 {
+        assert_eq!(tracker_for(grown_ptr, grown_layout).signature, ALLOC_TRACKER_SIG);
+      }
+
+      //shrink, which should be done in place for allocations with a tracker.
+      let shrunk_layout = Layout::from_size_align(0x20, align).unwrap();
+      let shrunk_ptr = unsafe { ALLOCATOR.realloc(grown_ptr, grown_layout, shrunk_layout.size()) };
+      assert!(!shrunk_ptr.is_null());
+      for index in 0..shrunk_layout.size() {
+        assert_eq!(unsafe { shrunk_ptr.add(index).read() }, index as u8);
+      }
+      if align > 8 {
+        assert_eq!(shrunk_ptr, grown_ptr);
+        let tracker = tracker_for(shrunk_ptr, shrunk_layout);
+        assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+        assert!(
+          ALLOCATION_TRACKER.lock().contains_key(&(tracker.orig_ptr as usize))
+            || PAGE_ALLOCATION_TRACKER.lock().contains_key(&(tracker.orig_ptr as usize))
+        );
+      }
+
+      unsafe { ALLOCATOR.dealloc(shrunk_ptr, shrunk_layout) };
+      assert_eq!(ALLOCATOR.stats().outstanding_bytes, 0);
+      assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+    }
+  }
+
+  extern "efiapi" fn mock_allocate_pool_failure(
+    _pool_type: r_efi::system::MemoryType,
+    _size: usize,
+    _buffer: *mut *mut c_void,
+  ) -> Status {
+    Status::OUT_OF_RESOURCES
+  }
+
+  thread_local! {
+    static FAILED_LAYOUT: Cell<Option<Layout>> = Cell::new(None);
+  }
+
+  #[test]
+  fn alloc_error_hook_should_be_called_on_failure() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    fn alloc_error_hook(layout: Layout) {
+      //the hook must be called without the allocator locked.
+      assert!(!ALLOCATOR.inner.is_locked());
+      FAILED_LAYOUT.with(|failed| failed.set(Some(layout)));
+    }
+
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_failure;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    //no hook set - allocation fails quietly.
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+
+    ALLOCATOR.set_alloc_error_hook(alloc_error_hook);
+    for layout in [layout, Layout::from_size_align(0x80, 0x100).unwrap()] {
+      assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+      assert_eq!(FAILED_LAYOUT.with(|failed| failed.take()), Some(layout));
+    }
+    assert_eq!(ALLOCATOR.stats(), AllocationStats::default());
+  }
+
+  thread_local! {
+    static FREED_CONTENTS: core::cell::RefCell<std::vec::Vec<u8>> = core::cell::RefCell::new(std::vec::Vec::new());
+  }
+
+  extern "efiapi" fn mock_free_pool_recording_contents(buffer: *mut c_void) -> Status {
+    let layout = *ALLOCATION_TRACKER.lock().get(&(buffer as usize)).expect("freeing an un-allocated pointer");
+    let contents = unsafe { core::slice::from_raw_parts(buffer as *const u8, layout.size()) };
+    FREED_CONTENTS.with(|freed| *freed.borrow_mut() = contents.to_vec());
+    mock_free_pool(buffer)
+  }
+
+  #[cfg(feature = "poison")]
+  #[test]
+  fn poison_should_fill_allocated_and_freed_memory() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.free_pool = mock_free_pool_recording_contents;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    for layout in [Layout::from_size_align(0x40, 0x8).unwrap(), Layout::from_size_align(0x40, 0x100).unwrap()] {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+      assert!(contents.iter().all(|byte| *byte == 0xAA));
+      //offset of the caller's region within the pool allocation.
+      let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+      let tracker_size = core::mem::size_of::<AllocationTracker>();
+      let (offset, tracker_bytes) = match layout.align() {
+        0..=8 => (0, std::vec::Vec::new()),
+        _ => {
+          let tracker = tracker_for(ptr, layout);
+          assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+          let tracker_bytes = unsafe { core::slice::from_raw_parts(ptr.add(tracking_offset), tracker_size) };
+          (ptr as usize - tracker.orig_ptr as usize, tracker_bytes.to_vec())
+        }
+      };
+
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      let freed = FREED_CONTENTS.with(|freed| freed.take());
+      assert!(freed[offset..offset + layout.size()].iter().all(|byte| *byte == 0xDD));
+      if layout.align() > 8 {
+        //the tracking structure is not poisoned.
+        assert_eq!(freed[offset + tracking_offset..offset + tracking_offset + tracker_size], tracker_bytes);
+      }
+    }
+  }
+
+  thread_local! {
+    static CORRUPTED_FREE: Cell<Option<(*mut u8, Layout)>> = Cell::new(None);
+  }
+
+  #[test]
+  fn corrupt_tracker_should_not_be_freed() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x100).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    let orig_ptr = tracker_for(ptr, layout).orig_ptr as usize;
+    let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+    let tracker = unsafe { ptr.add(tracking_offset).cast::<AllocationTracker>() };
+
+    //no handler set - the allocator panics (after releasing its lock).
+    unsafe { (*tracker).signature = !ALLOC_TRACKER_SIG };
+    let result = std::panic::catch_unwind(|| unsafe { ALLOCATOR.dealloc(ptr, layout) });
+    assert!(result.is_err());
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&orig_ptr));
+
+    fn corruption_handler(ptr: *mut u8, layout: Layout) {
+      CORRUPTED_FREE.with(|corrupted| corrupted.set(Some((ptr, layout))));
+    }
+    ALLOCATOR.set_corruption_handler(corruption_handler);
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert_eq!(CORRUPTED_FREE.with(|corrupted| corrupted.take()), Some((ptr, layout)));
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&orig_ptr));
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 1);
+
+    //repair the tracker so the allocation can be freed normally.
+    unsafe { (*tracker).signature = ALLOC_TRACKER_SIG };
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert!(CORRUPTED_FREE.with(|corrupted| corrupted.take()).is_none());
+    assert!(!ALLOCATION_TRACKER.lock().contains_key(&orig_ptr));
+  }
+
+  #[cfg(feature = "guard-pages")]
+  #[test]
+  fn guard_page_canary_should_detect_overrun() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    fn corruption_handler(ptr: *mut u8, layout: Layout) {
+      CORRUPTED_FREE.with(|corrupted| corrupted.set(Some((ptr, layout))));
+    }
+    ALLOCATOR.set_corruption_handler(corruption_handler);
+
+    let layout = Layout::from_size_align(0x1000, 0x1000).unwrap();
+    for overrun in [false, true] {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      let tracker = tracker_for(ptr, layout);
+      let guard_page = tracker.orig_ptr as usize + tracker.size;
+      assert!(!tracker.guard_protected);
+      assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&(ptr as usize)), Some(&(tracker.pages + 1)));
+
+      unsafe { ptr.write_bytes(0x11, layout.size()) };
+      if overrun {
+        unsafe { (guard_page as *mut u8).add(0x10).write(0x11) };
+      }
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      assert_eq!(CORRUPTED_FREE.with(|corrupted| corrupted.take()), overrun.then_some((ptr, layout)));
+      //the memory is freed either way, including the guard page.
+      assert!(!PAGE_ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+      assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+    }
+  }
+
+  #[cfg(feature = "guard-pages")]
+  thread_local! {
+    static GUARD_PAGE_CALLS: std::cell::RefCell<std::vec::Vec<(PhysicalAddress, bool)>> = Default::default();
+  }
+
+  #[cfg(feature = "guard-pages")]
+  #[test]
+  fn guard_page_callback_should_protect_and_restore_guard_page() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    fn guard_page_callback(address: PhysicalAddress, no_access: bool) -> Status {
+      GUARD_PAGE_CALLS.with(|calls| calls.borrow_mut().push((address, no_access)));
+      Status::SUCCESS
+    }
+    ALLOCATOR.set_guard_page_callback(guard_page_callback);
+
+    let layout = Layout::from_size_align(0x40, 0x1000).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    let tracker = tracker_for(ptr, layout);
+    let guard_page = (tracker.orig_ptr as usize + tracker.size) as PhysicalAddress;
+    assert!(tracker.guard_protected);
+    assert_eq!(GUARD_PAGE_CALLS.with(|calls| calls.take()), [(guard_page, true)]);
+
+    //pool allocations have no guard page.
+    let small = Layout::from_size_align(0x40, 0x8).unwrap();
+    let small_ptr = unsafe { ALLOCATOR.alloc(small) };
+    unsafe { ALLOCATOR.dealloc(small_ptr, small) };
+    assert!(GUARD_PAGE_CALLS.with(|calls| calls.take()).is_empty());
+
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert_eq!(GUARD_PAGE_CALLS.with(|calls| calls.take()), [(guard_page, false)]);
+  }
+
+  thread_local! {
+    static REQUESTED_POOL_SIZE: Cell<Option<usize>> = Cell::new(None);
+  }
+
+  extern "efiapi" fn mock_allocate_pool_recording_size(
+    pool_type: r_efi::system::MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+  ) -> Status {
+    REQUESTED_POOL_SIZE.with(|requested| requested.set(Some(size)));
+    mock_allocate_pool_offset(pool_type, size, buffer)
+  }
+
+  thread_local! {
+    //offset from 16-byte alignment of the buffers returned by mock_allocate_pool_offset, and the buffers it has
+    //returned that have not been freed.
+    static POOL_OFFSET: Cell<usize> = Cell::new(8);
+    static OFFSET_POOL: core::cell::RefCell<std::collections::BTreeSet<usize>> =
+      core::cell::RefCell::new(std::collections::BTreeSet::new());
+  }
+
+  // mock AllocatePool() returning buffers at POOL_OFFSET from 16-byte alignment, e.g. 8-byte aligned buffers that are
+  // never 16-byte aligned, as a pool providing only the alignment UEFI guarantees might. The buffers are leaked when
+  // they are freed.
+  extern "efiapi" fn mock_allocate_pool_offset(
+    _pool_type: r_efi::system::MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+  ) -> Status {
+    let backing = std::boxed::Box::leak(std::vec![0u128; size / 16 + 2].into_boxed_slice());
+    let ptr = unsafe { backing.as_mut_ptr().cast::<u8>().add(POOL_OFFSET.with(Cell::get)) };
+    OFFSET_POOL.with(|pool| pool.borrow_mut().insert(ptr as usize));
+    unsafe { buffer.write(ptr as *mut c_void) };
+    Status::SUCCESS
+  }
+
+  extern "efiapi" fn mock_free_pool_offset(buffer: *mut c_void) -> Status {
+    assert!(OFFSET_POOL.with(|pool| pool.borrow_mut().remove(&(buffer as usize))), "freed unknown pool buffer");
+    Status::SUCCESS
+  }
+
+  fn offset_pool_boot_services() -> BootServices {
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_offset;
+    boot_services.free_pool = mock_free_pool_offset;
+    boot_services
+  }
+
+  #[test]
+  fn aligned_allocation_should_request_minimal_slack() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = offset_pool_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_recording_size;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let tracker_size = core::mem::size_of::<AllocationTracker>();
+    for align in [0x10, 0x40, 0x800] {
+      let layout = Layout::from_size_align(0x40, align).unwrap();
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      assert_eq!(ptr.align_offset(align), 0);
+      assert_eq!(REQUESTED_POOL_SIZE.with(|requested| requested.take()), Some(0x40 + tracker_size + align - 8));
+
+      //the tracker must fit within the pool allocation however the pool pointer is aligned.
+      let tracker = tracker_for(ptr, layout);
+      assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+      let (expanded_layout, _) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+      assert!(ptr as usize + expanded_layout.size() <= tracker.orig_ptr as usize + tracker.size);
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+  }
+
+  #[test]
+  fn tracked_pool_size_should_not_shrink_below_expanded_size_for_small_alignments() {
+    //an expanded layout only 4-byte aligned, as on targets where the tracker needs no more.
+    for align in [1, 2, 4, 8] {
+      let expanded_layout = Layout::from_size_align(0x44, align).unwrap();
+      assert_eq!(tracked_pool_size(expanded_layout), 0x44);
+    }
+    assert_eq!(tracked_pool_size(Layout::from_size_align(0x80, 0x40).unwrap()), 0x80 + 0x40 - 8);
+  }
+
+  #[test]
+  fn allocator_trait_should_allocate_through_boot_services() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    #[repr(align(0x100))]
+    struct Aligned([u8; 0x40]);
+
+    let boxed = std::boxed::Box::new_in(0x1234u64, &ALLOCATOR);
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(&*boxed as *const u64 as usize)));
+    let aligned = std::boxed::Box::new_in(Aligned([0x5a; 0x40]), &ALLOCATOR);
+    assert_eq!((&*aligned as *const Aligned).align_offset(0x100), 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 2);
+
+    let mut values = std::vec::Vec::new_in(&ALLOCATOR);
+    for value in 0..0x100u32 {
+      values.push(value);
+    }
+    assert!((0..0x100u32).eq(values.iter().copied()));
+    assert_eq!(*boxed, 0x1234);
+    assert!(aligned.0.iter().all(|byte| *byte == 0x5a));
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 3);
+
+    drop(values);
+    drop(boxed);
+    drop(aligned);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 0);
+  }
+
+  extern "efiapi" fn mock_allocate_pool_unreachable(
+    _pool_type: r_efi::system::MemoryType,
+    _size: usize,
+    _buffer: *mut *mut c_void,
+  ) -> Status {
+    panic!("allocate_pool called on a sealed allocator");
+  }
+
+  extern "efiapi" fn mock_free_pool_unreachable(_buffer: *mut c_void) -> Status {
+    panic!("free_pool called on a sealed allocator");
+  }
+
+  #[test]
+  fn sealed_allocator_should_not_use_boot_services() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+
+    ALLOCATOR.seal();
+    //boot services must not be touched once sealed.
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_unreachable;
+    boot_services.free_pool = mock_free_pool_unreachable;
+    ALLOCATOR.inner.lock().boot_services = Some(&mut boot_services);
+
+    for layout in
+      [layout, Layout::from_size_align(0x40, 0x100).unwrap(), Layout::from_size_align(0x40, 0x1000).unwrap()]
+    {
+      assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+    }
+    assert!(unsafe { ALLOCATOR.realloc(ptr, layout, 0x80) }.is_null());
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+
+    //clean up the allocation made before sealing.
+    let _ = mock_free_pool(ptr as *mut c_void);
+  }
+
+  #[cfg(feature = "leak-check")]
+  #[test]
+  fn outstanding_allocations_should_be_reported() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    assert_eq!(ALLOCATOR.outstanding_count(), 0);
+
+    let mut leaked = std::vec::Vec::new();
+    for (size, align) in [(0x40, 0x1), (0x40, 0x8), (0x40, 0x100), (0x40, 0x1000)] {
+      let layout = Layout::from_size_align(size, align).unwrap();
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      leaked.push((ptr, layout));
+    }
+    assert_eq!(ALLOCATOR.outstanding_count(), leaked.len());
+
+    //in-place and moving reallocations keep the list consistent.
+    let (ptr, layout) = leaked[2];
+    let shrunk_layout = Layout::from_size_align(0x20, layout.align()).unwrap();
+    assert_eq!(unsafe { ALLOCATOR.realloc(ptr, layout, shrunk_layout.size()) }, ptr);
+    leaked[2] = (ptr, shrunk_layout);
+    let (ptr, layout) = leaked[1];
+    let grown_layout = Layout::from_size_align(0x2000, layout.align()).unwrap();
+    let grown_ptr = unsafe { ALLOCATOR.realloc(ptr, layout, grown_layout.size()) };
+    assert!(!grown_ptr.is_null());
+    leaked[1] = (grown_ptr, grown_layout);
+
+    let mut reported = std::vec::Vec::new();
+    ALLOCATOR.for_each_outstanding(|ptr, layout| reported.push((ptr, layout)));
+    reported.sort_by_key(|(ptr, _)| *ptr as usize);
+    leaked.sort_by_key(|(ptr, _)| *ptr as usize);
+    assert_eq!(reported, leaked);
+
+    //free from the middle of the list first.
+    for index in [1, 0, 3, 2] {
+      let (ptr, layout) = leaked[index];
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+    assert_eq!(ALLOCATOR.outstanding_count(), 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn second_init_should_be_rejected_until_reinit() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    assert_eq!(ALLOCATOR.init(&mut boot_services), Ok(()));
+
+    //a second init leaves the original table in place.
+    let mut failing_boot_services = mock_boot_services();
+    failing_boot_services.allocate_pool = mock_allocate_pool_failure;
+    assert_eq!(ALLOCATOR.init(&mut failing_boot_services), Err(Status::ALREADY_STARTED));
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+
+    ALLOCATOR.reinit(&mut failing_boot_services);
+    assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+  }
+
+  #[test]
+  fn over_cap_allocation_should_not_reach_firmware() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    fn alloc_error_hook(layout: Layout) {
+      FAILED_LAYOUT.with(|failed| failed.set(Some(layout)));
+    }
+    let mut boot_services = offset_pool_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_recording_size;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    ALLOCATOR.set_alloc_error_hook(alloc_error_hook);
+    ALLOCATOR.set_max_allocation(0x100);
+
+    for layout in [Layout::from_size_align(0x101, 0x8).unwrap(), Layout::from_size_align(0x101, 0x100).unwrap()] {
+      assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+      assert_eq!(FAILED_LAYOUT.with(|failed| failed.take()), Some(layout));
+      assert!(REQUESTED_POOL_SIZE.with(|requested| requested.take()).is_none());
+    }
+
+    let layout = Layout::from_size_align(0x100, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert!(FAILED_LAYOUT.with(|failed| failed.take()).is_none());
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+  }
+
+  #[test]
+  fn alloc_zeroed_should_not_clobber_tracker() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    for align in [0x8, 0x100, 0x1000] {
+      let layout = Layout::from_size_align(0x40, align).unwrap();
+      let ptr = unsafe { ALLOCATOR.alloc_zeroed(layout) };
+      assert!(!ptr.is_null());
+      let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+      assert!(contents.iter().all(|byte| *byte == 0));
+      if align > 8 {
+        let tracker = tracker_for(ptr, layout);
+        assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+        assert!(!tracker.orig_ptr.is_null());
+      }
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn overhead_should_account_for_alignment_padding() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x1000).unwrap();
+    let mut ptrs = std::vec::Vec::new();
+    for count in 1..=4 {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      ptrs.push(ptr);
+      //each small object occupies a whole page.
+      let stats = ALLOCATOR.stats();
+      assert_eq!(stats.requested_bytes, count * 0x40);
+      assert_eq!(stats.overhead_bytes(), count * (UEFI_PAGE_SIZE - 0x40));
+    }
+
+    //shrinking in place adds to the overhead.
+    let shrunk_ptr = unsafe { ALLOCATOR.realloc(ptrs[0], layout, 0x20) };
+    assert_eq!(shrunk_ptr, ptrs[0]);
+    assert_eq!(ALLOCATOR.stats().overhead_bytes(), 4 * (UEFI_PAGE_SIZE - 0x40) + 0x20);
+    unsafe { ALLOCATOR.dealloc(shrunk_ptr, Layout::from_size_align(0x20, 0x1000).unwrap()) };
+
+    for ptr in ptrs.drain(1..) {
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+    assert_eq!(ALLOCATOR.stats().overhead_bytes(), 0);
+    assert_eq!(ALLOCATOR.stats().requested_bytes, 0);
+  }
+
+  #[cfg(any(feature = "leak-check", feature = "uefi-services", feature = "validate-free"))]
+  #[repr(C)]
+  struct MockLoggerProtocol {
+    signature: u32,
+    version: u32,
+    write_log: extern "efiapi" fn(*const MockLoggerProtocol, usize, *const u8, usize),
+  }
+
+  #[cfg(any(feature = "leak-check", feature = "uefi-services", feature = "validate-free"))]
+  static MOCK_LOGGER: MockLoggerProtocol = MockLoggerProtocol { signature: 0, version: 0, write_log: mock_write_log };
+
+  #[cfg(any(feature = "leak-check", feature = "uefi-services", feature = "validate-free"))]
+  thread_local! {
+    static LOGGED: core::cell::RefCell<std::vec::Vec<(usize, std::string::String)>> =
+      core::cell::RefCell::new(std::vec::Vec::new());
+  }
+
+  #[cfg(any(feature = "leak-check", feature = "uefi-services", feature = "validate-free"))]
+  extern "efiapi" fn mock_write_log(_this: *const MockLoggerProtocol, level: usize, buffer: *const u8, count: usize) {
+    let text = std::string::String::from_utf8_lossy(unsafe { core::slice::from_raw_parts(buffer, count) });
+    LOGGED.with(|logged| logged.borrow_mut().push((level, text.into_owned())));
+  }
+
+  #[cfg(any(feature = "leak-check", feature = "uefi-services", feature = "validate-free"))]
+  extern "efiapi" fn mock_locate_logger(
+    _guid: *mut r_efi::efi::Guid,
+    _reg: *mut c_void,
+    ptr: *mut *mut c_void,
+  ) -> Status {
+    unsafe { ptr.write(&MOCK_LOGGER as *const MockLoggerProtocol as *mut c_void) };
+    Status::SUCCESS
+  }
+
+  #[cfg(any(feature = "leak-check", feature = "uefi-services"))]
+  extern "efiapi" fn mock_locate_logger_not_found(
+    _guid: *mut r_efi::efi::Guid,
+    _reg: *mut c_void,
+    _ptr: *mut *mut c_void,
+  ) -> Status {
+    Status::NOT_FOUND
+  }
+
+  // serializes tests that initialize the global logger.
+  #[cfg(any(feature = "leak-check", feature = "uefi-services", feature = "validate-free"))]
+  static GLOBAL_LOGGER_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+  #[cfg(feature = "leak-check")]
+  #[test]
+  fn dump_outstanding_should_log_each_live_allocation() {
+    use rust_advanced_logger_dxe::DEBUG_WARN;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_logger;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let layouts = [Layout::from_size_align(0x40, 0x8).unwrap(), Layout::from_size_align(0x80, 0x100).unwrap()];
+    let ptrs: std::vec::Vec<_> = layouts.iter().map(|layout| unsafe { ALLOCATOR.alloc(*layout) }).collect();
+
+    //nothing is logged while the logger is not initialized.
+    let mut no_logger_boot_services = mock_boot_services();
+    no_logger_boot_services.locate_protocol = mock_locate_logger_not_found;
+    assert!(rust_advanced_logger_dxe::reinit_debug(&mut no_logger_boot_services).is_err());
+    ALLOCATOR.dump_outstanding(DEBUG_WARN);
+    assert!(LOGGED.with(|logged| logged.take()).is_empty());
+
+    rust_advanced_logger_dxe::reinit_debug(&mut boot_services).unwrap();
+    ALLOCATOR.dump_outstanding(DEBUG_WARN);
+    let output: std::string::String = LOGGED
+      .with(|logged| logged.take())
+      .into_iter()
+      .map(|(level, text)| {
+        assert_eq!(level, DEBUG_WARN);
+        text
+      })
+      .collect();
+    let lines: std::vec::Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), layouts.len());
+    for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+      let expected =
+        std::format!("outstanding allocation {:p}: size {:#x}, align {:#x}", ptr, layout.size(), layout.align());
+      assert!(lines.iter().any(|line| line.starts_with(&expected)));
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+
+    ALLOCATOR.dump_outstanding(DEBUG_WARN);
+    assert!(LOGGED.with(|logged| logged.take()).is_empty());
+  }
+
+  static FALLBACK_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+  static FALLBACK_DEALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+  // fallback allocator backed by the host allocator, counting calls.
+  struct CountingFallback;
+
+  unsafe impl GlobalAlloc for CountingFallback {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+      FALLBACK_ALLOCS.fetch_add(1, Ordering::SeqCst);
+      System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+      FALLBACK_DEALLOCS.fetch_add(1, Ordering::SeqCst);
+      System.dealloc(ptr, layout)
+    }
+  }
+
+  #[test]
+  fn failed_allocation_should_use_fallback() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    static FALLBACK: CountingFallback = CountingFallback;
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_failure;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    assert!(unsafe { ALLOCATOR.alloc(layout) }.is_null());
+    ALLOCATOR.set_fallback(&FALLBACK);
+
+    let layouts = [layout, Layout::from_size_align(0x40, 0x100).unwrap()];
+    let mut ptrs = std::vec::Vec::new();
+    for layout in layouts {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      assert_eq!(ptr.align_offset(layout.align()), 0);
+      let tracker = tracker_for(ptr, layout);
+      assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+      assert!(tracker.fallback);
+      ptrs.push(ptr);
+    }
+    assert_eq!(FALLBACK_ALLOCS.load(Ordering::SeqCst), 2);
+
+    //shrinking in place must still free the original backing layout.
+    let shrunk_ptr = unsafe { ALLOCATOR.realloc(ptrs[1], layouts[1], 0x20) };
+    assert_eq!(shrunk_ptr, ptrs[1]);
+    unsafe { ALLOCATOR.dealloc(ptrs[0], layouts[0]) };
+    unsafe { ALLOCATOR.dealloc(shrunk_ptr, Layout::from_size_align(0x20, 0x100).unwrap()) };
+    assert_eq!(FALLBACK_DEALLOCS.load(Ordering::SeqCst), 2);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn static_arena_should_serve_as_fallback() {
+    use crate::StaticArena;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    static ARENA: StaticArena<0x400> = StaticArena::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_failure;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    ALLOCATOR.set_fallback(&ARENA);
+
+    let arena = &ARENA as *const StaticArena<0x400> as usize..&ARENA as *const StaticArena<0x400> as usize + 0x400;
+    let layout = Layout::from_size_align(0x100, 0x40).unwrap();
+    let first = unsafe { ALLOCATOR.alloc(layout) };
+    let second = unsafe { ALLOCATOR.alloc(layout) };
+    for ptr in [first, second] {
+      assert!(!ptr.is_null());
+      assert_eq!(ptr.align_offset(layout.align()), 0);
+      assert!(arena.contains(&(ptr as usize)));
+    }
+
+    //the arena is exhausted until memory is freed back to it.
+    let large = Layout::from_size_align(0x200, 0x8).unwrap();
+    assert!(unsafe { ALLOCATOR.alloc(large) }.is_null());
+    unsafe {
+      ALLOCATOR.dealloc(first, layout);
+      ALLOCATOR.dealloc(second, layout);
+    }
+    let ptr = unsafe { ALLOCATOR.alloc(large) };
+    assert!(arena.contains(&(ptr as usize)));
+    unsafe { ALLOCATOR.dealloc(ptr, large) };
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  thread_local! {
+    static FAILURE_STATUS: Cell<Status> = Cell::new(Status::SUCCESS);
+  }
+
+  extern "efiapi" fn mock_allocate_pool_status(
+    _pool_type: r_efi::system::MemoryType,
+    _size: usize,
+    _buffer: *mut *mut c_void,
+  ) -> Status {
+    FAILURE_STATUS.with(|status| status.get())
+  }
+
+  extern "efiapi" fn mock_allocate_pages_status(
+    _allocation_type: AllocateType,
+    _memory_type: r_efi::system::MemoryType,
+    _pages: usize,
+    _memory: *mut PhysicalAddress,
+  ) -> Status {
+    FAILURE_STATUS.with(|status| status.get())
+  }
+
+  #[test]
+  fn try_alloc_should_propagate_failure_status() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    assert_eq!(ALLOCATOR.try_alloc(layout), Err(Status::NOT_READY));
+
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_status;
+    boot_services.allocate_pages = mock_allocate_pages_status;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    for status in [Status::OUT_OF_RESOURCES, Status::INVALID_PARAMETER, Status::NOT_FOUND] {
+      FAILURE_STATUS.with(|failure| failure.set(status));
+      for align in [0x8, 0x100, 0x1000] {
+        assert_eq!(ALLOCATOR.try_alloc(Layout::from_size_align(0x40, align).unwrap()), Err(status));
+      }
+    }
+    assert_eq!(ALLOCATOR.stats(), AllocationStats::default());
+  }
+
+  thread_local! {
+    static TRANSIENT_FAILURES: Cell<usize> = Cell::new(0);
+    static RETRY_CALLBACKS: Cell<usize> = Cell::new(0);
+  }
+
+  // fails with OUT_OF_RESOURCES while TRANSIENT_FAILURES is non-zero, counting it down.
+  extern "efiapi" fn mock_allocate_pool_transient(
+    pool_type: r_efi::system::MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+  ) -> Status {
+    match TRANSIENT_FAILURES.with(|failures| failures.replace(failures.get().saturating_sub(1))) {
+      0 => mock_allocate_pool(pool_type, size, buffer),
+      _ => Status::OUT_OF_RESOURCES,
+    }
+  }
+
+  fn count_retry_callback() {
+    RETRY_CALLBACKS.with(|callbacks| callbacks.set(callbacks.get() + 1));
+  }
+
+  #[test]
+  fn alloc_should_retry_transient_failures() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_transient;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+
+    //no retries by default.
+    TRANSIENT_FAILURES.with(|failures| failures.set(1));
+    assert_eq!(ALLOCATOR.try_alloc(layout), Err(Status::OUT_OF_RESOURCES));
+
+    //the allocation succeeds on the final attempt, with the callback called before each retry.
+    ALLOCATOR.set_alloc_retry(3);
+    ALLOCATOR.set_alloc_retry_callback(count_retry_callback);
+    TRANSIENT_FAILURES.with(|failures| failures.set(3));
+    let ptr = ALLOCATOR.try_alloc(layout).unwrap();
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    assert_eq!(RETRY_CALLBACKS.with(Cell::get), 3);
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+
+    //more failures than retries still fail.
+    RETRY_CALLBACKS.with(|callbacks| callbacks.set(0));
+    TRANSIENT_FAILURES.with(|failures| failures.set(4));
+    assert_eq!(ALLOCATOR.try_alloc(layout), Err(Status::OUT_OF_RESOURCES));
+    assert_eq!(RETRY_CALLBACKS.with(Cell::get), 3);
+    TRANSIENT_FAILURES.with(|failures| failures.set(0));
+  }
+
+  #[cfg(feature = "pool-tags")]
+  #[test]
+  fn tagged_allocations_should_be_reported_per_tag() {
+    use crate::TagTotals;
+    use rust_advanced_logger_dxe::DEBUG_INFO;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_logger;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    rust_advanced_logger_dxe::reinit_debug(&mut boot_services).unwrap();
+    ALLOCATOR.set_default_tag(0x1234);
+
+    let small = Layout::from_size_align(0x40, 0x8).unwrap();
+    let aligned = Layout::from_size_align(0x80, 0x100).unwrap();
+    let untagged = unsafe { ALLOCATOR.alloc(small) };
+    let tagged = [ALLOCATOR.alloc_tagged(small, 0x5678), ALLOCATOR.alloc_tagged(aligned, 0x5678)];
+    assert!(!untagged.is_null() && tagged.iter().all(|ptr| !ptr.is_null()));
+
+    assert_eq!(ALLOCATOR.tag_totals(0x1234), TagTotals { allocations: 1, requested_bytes: 0x40 });
+    assert_eq!(ALLOCATOR.tag_totals(0x5678), TagTotals { allocations: 2, requested_bytes: 0xC0 });
+    assert_eq!(ALLOCATOR.tag_totals(0), TagTotals::default());
+
+    //a moving realloc keeps the tag.
+    let grown = unsafe { ALLOCATOR.realloc(tagged[0], small, 0x2000) };
+    assert!(!grown.is_null());
+    assert_eq!(ALLOCATOR.tag_totals(0x5678), TagTotals { allocations: 2, requested_bytes: 0x2080 });
+
+    LOGGED.with(|logged| logged.take());
+    ALLOCATOR.dump_outstanding(DEBUG_INFO);
+    let output: std::string::String = LOGGED.with(|logged| logged.take()).into_iter().map(|(_, text)| text).collect();
+    assert_eq!(output.lines().filter(|line| line.ends_with("tag 0x5678")).count(), 2);
+    assert_eq!(output.lines().filter(|line| line.ends_with("tag 0x1234")).count(), 1);
+
+    unsafe {
+      ALLOCATOR.dealloc(untagged, small);
+      ALLOCATOR.dealloc(grown, Layout::from_size_align(0x2000, 0x8).unwrap());
+      ALLOCATOR.dealloc(tagged[1], aligned);
+    }
+    assert_eq!(ALLOCATOR.tag_totals(0x5678), TagTotals::default());
+  }
+
+  #[test]
+  fn allocator_grow_and_shrink_should_preserve_contents_and_tracker() {
+    use core::{alloc::Allocator, ptr::NonNull};
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    let check = |ptr: NonNull<[u8]>, layout: Layout, len: usize| {
+      let ptr = ptr.cast::<u8>().as_ptr();
+      assert_eq!(ptr.align_offset(layout.align()), 0);
+      for index in 0..len {
+        assert_eq!(unsafe { ptr.add(index).read() }, index as u8);
+      }
+      if layout.align() > 8 {
+        let tracker = tracker_for(ptr, layout);
+        assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+        assert!(ptr as usize + layout.size() <= tracker.orig_ptr as usize + tracker.size);
+      }
+    };
+
+    //alignment may change across grow/shrink, moving allocations between the tracked and untracked paths.
+    for (align, new_align) in [(0x100, 0x100), (0x1000, 0x1000), (0x8, 0x100), (0x100, 0x8)] {
+      let layout = Layout::from_size_align(0x40, align).unwrap();
+      let ptr = ALLOCATOR.allocate(layout).unwrap();
+      for index in 0..layout.size() {
+        unsafe { ptr.cast::<u8>().as_ptr().add(index).write(index as u8) };
+      }
+
+      let grown_layout = Layout::from_size_align(0x3000, new_align).unwrap();
+      let grown = unsafe { ALLOCATOR.grow_zeroed(ptr.cast(), layout, grown_layout) }.unwrap();
+      assert_eq!(grown.len(), grown_layout.size());
+      check(grown, grown_layout, layout.size());
+      let contents = unsafe { core::slice::from_raw_parts(grown.cast::<u8>().as_ptr(), grown_layout.size()) };
+      assert!(contents[layout.size()..].iter().all(|byte| *byte == 0));
+      for index in 0..grown_layout.size() {
+        unsafe { grown.cast::<u8>().as_ptr().add(index).write(index as u8) };
+      }
+
+      let shrunk_layout = Layout::from_size_align(0x20, align).unwrap();
+      let shrunk = unsafe { ALLOCATOR.shrink(grown.cast(), grown_layout, shrunk_layout) }.unwrap();
+      check(shrunk, shrunk_layout, shrunk_layout.size());
+      if align == new_align {
+        assert_eq!(shrunk.cast::<u8>(), grown.cast::<u8>());
+      }
+
+      let regrown_layout = Layout::from_size_align(0x30, align).unwrap();
+      let regrown = unsafe { ALLOCATOR.grow(shrunk.cast(), shrunk_layout, regrown_layout) }.unwrap();
+      check(regrown, regrown_layout, shrunk_layout.size());
+      unsafe { ALLOCATOR.deallocate(regrown.cast(), regrown_layout) };
+    }
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 0);
+  }
+
+  #[cfg(feature = "uefi-services")]
+  #[test]
+  fn init_uefi_services_should_initialize_allocator_and_logger() {
+    use crate::{init_uefi_services, GLOBAL_ALLOCATOR};
+    use r_efi::system::SystemTable;
+    use rust_advanced_logger_dxe::{debugln, is_debug_initialized, DEBUG_INFO};
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    assert_eq!(init_uefi_services(core::ptr::null()), Err(Status::INVALID_PARAMETER));
+
+    //other tests may have initialized the global logger.
+    let mut no_logger_boot_services = mock_boot_services();
+    no_logger_boot_services.locate_protocol = mock_locate_logger_not_found;
+    assert!(rust_advanced_logger_dxe::reinit_debug(&mut no_logger_boot_services).is_err());
+
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_logger;
+    let mut system_table: SystemTable = unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
+    system_table.boot_services = &mut boot_services;
+    init_uefi_services(&system_table).unwrap();
+    assert!(is_debug_initialized());
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { GLOBAL_ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { GLOBAL_ALLOCATOR.dealloc(ptr, layout) };
+
+    LOGGED.with(|logged| logged.take());
+    debugln!(DEBUG_INFO, "services {:}", "initialized");
+    let output: std::string::String = LOGGED.with(|logged| logged.take()).into_iter().map(|(_, text)| text).collect();
+    assert_eq!(output, "services initialized\n");
+
+    //the allocator is already initialized, so the logger is left alone.
+    assert_eq!(init_uefi_services(&system_table), Err(Status::ALREADY_STARTED));
+  }
+
+  #[cfg(feature = "uefi-services")]
+  #[test]
+  fn alloc_error_hook_should_log_failed_layout() {
+    use crate::{set_as_alloc_error_hook, GLOBAL_ALLOCATOR};
+    use rust_advanced_logger_dxe::DEBUG_ERROR;
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_logger;
+    rust_advanced_logger_dxe::reinit_debug(&mut boot_services).unwrap();
+
+    set_as_alloc_error_hook();
+    let hook = GLOBAL_ALLOCATOR.inner.lock().alloc_error_hook.expect("alloc error hook not installed");
+    LOGGED.with(|logged| logged.take());
+    hook(Layout::from_size_align(0x12000, 0x1000).unwrap());
+    let logged = LOGGED.with(|logged| logged.take());
+    assert!(logged.iter().all(|(level, _)| *level == DEBUG_ERROR));
+    let output: std::string::String = logged.into_iter().map(|(_, text)| text).collect();
+    assert_eq!(output, "memory allocation failed: size 0x12000, align 0x1000\n");
+  }
+
+  #[cfg(not(feature = "leak-check"))]
+  #[test]
+  fn pool_alignment_should_allocate_aligned_requests_directly() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut offset_boot_services = offset_pool_boot_services();
+    ALLOCATOR.init(&mut offset_boot_services).unwrap();
+    let layout = Layout::from_size_align(0x40, 0x10).unwrap();
+
+    //by default, 16-byte alignment requires a tracking structure unless the pool happens to provide it.
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert_eq!(ptr as usize % 0x10, 0);
+    assert!(ALLOCATOR.stats().overhead_bytes() > 0);
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+
+    let mut boot_services = mock_boot_services();
+    ALLOCATOR.reinit(&mut boot_services);
+    ALLOCATOR.set_pool_alignment(0x10);
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert_eq!(ptr as usize % 0x10, 0);
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, layout.size());
+    assert_eq!(ALLOCATOR.stats().overhead_bytes(), 0);
+
+    //larger alignments are still tracked.
+    let large_layout = Layout::from_size_align(0x40, 0x20).unwrap();
+    let large_ptr = unsafe { ALLOCATOR.alloc(large_layout) };
+    assert_eq!(large_ptr as usize % 0x20, 0);
+    assert!(ALLOCATOR.stats().overhead_bytes() > 0);
+
+    unsafe {
+      ALLOCATOR.dealloc(ptr, layout);
+      ALLOCATOR.dealloc(large_ptr, large_layout);
+    }
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  static REENTRANT_ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+
+  thread_local! {
+    static REENTRANT_RESULT: Cell<Option<Result<*mut u8, Status>>> = Cell::new(None);
+  }
+
+  extern "efiapi" fn mock_allocate_pool_reentrant(
+    pool_type: r_efi::system::MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+  ) -> Status {
+    let result = REENTRANT_ALLOCATOR.try_alloc(Layout::from_size_align(0x10, 0x8).unwrap());
+    REENTRANT_RESULT.with(|reentrant_result| reentrant_result.set(Some(result)));
+    mock_allocate_pool(pool_type, size, buffer)
+  }
+
+  #[test]
+  fn reentrant_allocation_should_fail_instead_of_deadlocking() {
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_reentrant;
+    REENTRANT_ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { REENTRANT_ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(REENTRANT_RESULT.with(|result| result.take()), Some(Err(Status::ACCESS_DENIED)));
+
+    let ptr = unsafe { REENTRANT_ALLOCATOR.realloc(ptr, layout, 0x80) };
+    assert!(!ptr.is_null());
+    assert_eq!(REENTRANT_RESULT.with(|result| result.take()), Some(Err(Status::ACCESS_DENIED)));
+
+    //the allocator is usable once the outer allocation completes.
+    unsafe { REENTRANT_ALLOCATOR.dealloc(ptr, Layout::from_size_align(0x80, 0x8).unwrap()) };
+    assert_eq!(REENTRANT_ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  static UNSYNC_REENTRANT_ALLOCATOR: SpinLockedAllocator<UnsyncRawMutex> = SpinLockedAllocator::new();
+
+  extern "efiapi" fn mock_allocate_pool_reentrant_unsync(
+    pool_type: r_efi::system::MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+  ) -> Status {
+    let result = UNSYNC_REENTRANT_ALLOCATOR.try_alloc(Layout::from_size_align(0x10, 0x8).unwrap());
+    REENTRANT_RESULT.with(|reentrant_result| reentrant_result.set(Some(result)));
+    mock_allocate_pool(pool_type, size, buffer)
+  }
+
+  #[test]
+  fn single_threaded_reentrant_allocation_should_fail_instead_of_panicking() {
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_reentrant_unsync;
+    UNSYNC_REENTRANT_ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { UNSYNC_REENTRANT_ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(REENTRANT_RESULT.with(|result| result.take()), Some(Err(Status::ACCESS_DENIED)));
+
+    unsafe { UNSYNC_REENTRANT_ALLOCATOR.dealloc(ptr, layout) };
+    assert_eq!(UNSYNC_REENTRANT_ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn init_should_reject_null_boot_services() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    assert_eq!(ALLOCATOR.init(core::ptr::null_mut()), Err(Status::INVALID_PARAMETER));
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    assert_eq!(ALLOCATOR.try_alloc(layout), Err(Status::NOT_READY));
+
+    //a null table passed to reinit leaves allocations failing cleanly.
+    ALLOCATOR.reinit(core::ptr::null_mut());
+    assert_eq!(ALLOCATOR.try_alloc(layout), Err(Status::NOT_READY));
+
+    let mut boot_services = mock_boot_services();
+    ALLOCATOR.reinit(&mut boot_services);
+    let ptr = ALLOCATOR.try_alloc(layout).unwrap();
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+  }
+
+  #[test]
+  fn reset_stats_should_restart_peak_from_outstanding_bytes() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    let small = Layout::from_size_align(0x40, 0x8).unwrap();
+    let large = Layout::from_size_align(0x400, 0x8).unwrap();
+
+    let live = unsafe { ALLOCATOR.alloc(small) };
+    let freed = unsafe { ALLOCATOR.alloc(large) };
+    unsafe { ALLOCATOR.dealloc(freed, large) };
+    let before = ALLOCATOR.stats();
+    assert!(before.peak_bytes >= small.size() + large.size());
+
+    ALLOCATOR.reset_stats();
+    let after = ALLOCATOR.stats();
+    assert_eq!(after.peak_bytes, before.outstanding_bytes);
+    assert_eq!(after.outstanding_bytes, before.outstanding_bytes);
+    assert_eq!(after.outstanding_allocations, 1);
+    assert_eq!(after.requested_bytes, small.size());
+
+    //the peak reflects only activity after the reset.
+    let second = unsafe { ALLOCATOR.alloc(small) };
+    let peak = ALLOCATOR.stats().outstanding_bytes;
+    assert!(peak < before.peak_bytes);
+    unsafe {
+      ALLOCATOR.dealloc(second, small);
+      ALLOCATOR.dealloc(live, small);
+    }
+    assert_eq!(ALLOCATOR.stats().peak_bytes, peak);
+  }
+
+  #[test]
+  fn describe_should_render_configuration_and_stats() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut description = std::string::String::new();
+    ALLOCATOR.describe(&mut description).unwrap();
+    assert!(description.starts_with("initialized: false\nsealed: false\n"));
+    assert!(description.contains("max allocation: unlimited\n"));
+
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    ALLOCATOR.set_max_allocation(0x10000);
+    ALLOCATOR.set_alloc_retry(2);
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+
+    let mut description = std::string::String::new();
+    ALLOCATOR.describe(&mut description).unwrap();
+    let stats = ALLOCATOR.stats();
+    for field in [
+      "initialized: true\n".into(),
+      std::format!("memory type: {:}\n", BOOT_SERVICES_DATA),
+      "max allocation: 0x10000\n".into(),
+      "pool alignment: 0x8\n".into(),
+      "alloc retries: 2\n".into(),
+      "fallback: none\n".into(),
+      "outstanding allocations: 1\n".into(),
+      std::format!("outstanding bytes: {:#x}\n", stats.outstanding_bytes),
+      std::format!("requested bytes: {:#x}\n", stats.requested_bytes),
+      std::format!("peak bytes: {:#x}\n", stats.peak_bytes),
+    ] {
+      assert!(description.contains(&field), "{:?} missing from {:?}", field, description);
+    }
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+  }
+
+  #[test]
+  fn zero_on_free_should_scrub_freed_memory() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.free_pool = mock_free_pool_recording_contents;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    ALLOCATOR.set_zero_on_free(true);
+
+    for layout in [Layout::from_size_align(0x40, 0x8).unwrap(), Layout::from_size_align(0x40, 0x100).unwrap()] {
+      let ptr = unsafe { ALLOCATOR.alloc(layout) };
+      assert!(!ptr.is_null());
+      unsafe { ptr.write_bytes(0x5A, layout.size()) };
+      //offset of the caller's region within the pool allocation, and the tracking structure following it.
+      let (_, tracking_offset) = layout.extend(Layout::new::<AllocationTracker>()).unwrap();
+      let tracker_size = core::mem::size_of::<AllocationTracker>();
+      let (offset, tracker_bytes) = match layout.align() {
+        0..=8 => (0, std::vec::Vec::new()),
+        _ => {
+          let tracker = tracker_for(ptr, layout);
+          let tracker_bytes = unsafe { core::slice::from_raw_parts(ptr.add(tracking_offset), tracker_size) };
+          (ptr as usize - tracker.orig_ptr as usize, tracker_bytes.to_vec())
+        }
+      };
+
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      let freed = FREED_CONTENTS.with(|freed| freed.take());
+      assert!(freed[offset..offset + layout.size()].iter().all(|byte| *byte == 0));
+      if layout.align() > 8 {
+        //the tracking structure is not scrubbed before it is read.
+        assert_eq!(freed[offset + tracking_offset..offset + tracking_offset + tracker_size], tracker_bytes);
+      }
+    }
+  }
+
+  #[test]
+  fn zero_size_allocations_should_return_aligned_sentinel() {
+    extern "efiapi" fn mock_allocate_pages_unreachable(
+      _allocation_type: AllocateType,
+      _memory_type: r_efi::system::MemoryType,
+      _pages: usize,
+      _memory: *mut PhysicalAddress,
+    ) -> Status {
+      panic!("allocate_pages called for a zero-size allocation");
+    }
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_unreachable;
+    boot_services.allocate_pages = mock_allocate_pages_unreachable;
+    boot_services.free_pool = mock_free_pool_unreachable;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    for align in [0x1, 0x8, 0x100, 0x10000] {
+      let layout = Layout::from_size_align(0, align).unwrap();
+      for ptr in [unsafe { ALLOCATOR.alloc(layout) }, unsafe { ALLOCATOR.alloc_zeroed(layout) }] {
+        assert!(!ptr.is_null());
+        assert_eq!(ptr.align_offset(align), 0);
+        //freeing the sentinel does not reach firmware.
+        unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      }
+    }
+    assert_eq!(ALLOCATOR.stats(), AllocationStats::default());
+  }
+
+  #[test]
+  fn realloc_should_move_between_zero_size_and_real_allocations() {
+    use core::{alloc::Allocator, ptr::NonNull};
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    let empty = Layout::from_size_align(0, 0x100).unwrap();
+    let layout = Layout::from_size_align(0x40, 0x100).unwrap();
+
+    let sentinel = unsafe { ALLOCATOR.alloc(empty) };
+    let ptr = unsafe { ALLOCATOR.realloc(sentinel, empty, layout.size()) };
+    assert!(!ptr.is_null() && ptr != sentinel);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 1);
+
+    let shrunk = unsafe { ALLOCATOR.shrink(NonNull::new(ptr).unwrap(), layout, empty) }.unwrap();
+    assert_eq!(shrunk.len(), 0);
+    assert_eq!(shrunk.cast::<u8>().as_ptr().align_offset(0x100), 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  // trivial RawMutex that counts the times it is acquired.
+  struct CountingLock {
+    locked: AtomicBool,
+    acquired: AtomicUsize,
+  }
+
+  unsafe impl RawMutex for CountingLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = CountingLock { locked: AtomicBool::new(false), acquired: AtomicUsize::new(0) };
+
+    fn lock(&self) {
+      assert!(self.try_lock(), "test lock is not expected to be contended");
+    }
+
+    fn try_lock(&self) -> bool {
+      let acquired = !self.locked.swap(true, Ordering::Acquire);
+      if acquired {
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+      }
+      acquired
+    }
+
+    unsafe fn unlock(&self) {
+      self.locked.store(false, Ordering::Release);
+    }
+  }
+
+  #[test]
+  fn allocator_should_use_custom_lock() {
+    static ALLOCATOR: SpinLockedAllocator<CountingLock> = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let acquired = ALLOCATOR.inner.raw().acquired.load(Ordering::Relaxed);
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    assert!(!ALLOCATOR.inner.is_locked());
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert!(!ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    assert!(ALLOCATOR.inner.raw().acquired.load(Ordering::Relaxed) >= acquired + 2);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn shrinking_page_allocation_should_free_surplus_pages() {
+    use core::alloc::Allocator;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    let check_contents = |ptr: *mut u8, size: usize| {
+      for index in 0..size {
+        assert_eq!(unsafe { ptr.add(index).read() }, index as u8);
+      }
+    };
+
+    //grow a pool allocation into the page path.
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = ALLOCATOR.allocate(layout).unwrap().cast::<u8>();
+    for index in 0..layout.size() {
+      unsafe { ptr.as_ptr().add(index).write(index as u8) };
+    }
+    let grown_layout = Layout::from_size_align(0x3000, UEFI_PAGE_SIZE).unwrap();
+    let grown = unsafe { ALLOCATOR.grow(ptr, layout, grown_layout) }.unwrap().cast::<u8>();
+    let base = grown.as_ptr() as usize;
+    check_contents(grown.as_ptr(), layout.size());
+    for index in 0..grown_layout.size() {
+      unsafe { grown.as_ptr().add(index).write(index as u8) };
+    }
+    assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&base), Some(&(4 + GUARD_PAGES)));
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 4 * UEFI_PAGE_SIZE);
+
+    //shrinking in place returns the pages that are no longer needed.
+    let shrunk_layout = Layout::from_size_align(0x1000, UEFI_PAGE_SIZE).unwrap();
+    let shrunk = unsafe { ALLOCATOR.shrink(grown, grown_layout, shrunk_layout) }.unwrap().cast::<u8>();
+    assert_eq!(shrunk, grown);
+    check_contents(shrunk.as_ptr(), shrunk_layout.size());
+    assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&base), Some(&(2 + GUARD_PAGES)));
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 2 * UEFI_PAGE_SIZE);
+    let tracker = tracker_for(shrunk.as_ptr(), shrunk_layout);
+    assert_eq!((tracker.pages, tracker.size), (2, 2 * UEFI_PAGE_SIZE));
+
+    //shrinking out of the page path moves the allocation back to the pool and frees the pages.
+    let pool_layout = Layout::from_size_align(0x20, 0x8).unwrap();
+    let pool = unsafe { ALLOCATOR.shrink(shrunk, shrunk_layout, pool_layout) }.unwrap().cast::<u8>();
+    check_contents(pool.as_ptr(), pool_layout.size());
+    assert!(!PAGE_ALLOCATION_TRACKER.lock().contains_key(&base));
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(pool.as_ptr() as usize)));
+
+    unsafe { ALLOCATOR.deallocate(pool, pool_layout) };
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn shrinking_page_allocation_to_one_page_should_keep_it_page_backed() {
+    use core::alloc::Allocator;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+
+    let layout = Layout::from_size_align(0x3000, UEFI_PAGE_SIZE).unwrap();
+    let ptr = ALLOCATOR.allocate(layout).unwrap().cast::<u8>();
+    let base = ptr.as_ptr() as usize;
+    for index in 0..0x20 {
+      unsafe { ptr.as_ptr().add(index).write(index as u8) };
+    }
+    assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&base), Some(&(4 + GUARD_PAGES)));
+
+    //the alignment still requires pages, so the allocation stays in place with a single page.
+    let shrunk_layout = Layout::from_size_align(0x20, UEFI_PAGE_SIZE).unwrap();
+    let shrunk = unsafe { ALLOCATOR.shrink(ptr, layout, shrunk_layout) }.unwrap().cast::<u8>();
+    assert_eq!(shrunk, ptr);
+    for index in 0..0x20 {
+      assert_eq!(unsafe { shrunk.as_ptr().add(index).read() }, index as u8);
+    }
+    assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&base), Some(&(1 + GUARD_PAGES)));
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, UEFI_PAGE_SIZE);
+    let tracker = tracker_for(shrunk.as_ptr(), shrunk_layout);
+    assert_eq!((tracker.pages, tracker.size), (1, UEFI_PAGE_SIZE));
+
+    unsafe { ALLOCATOR.deallocate(shrunk, shrunk_layout) };
+    assert!(!PAGE_ALLOCATION_TRACKER.lock().contains_key(&base));
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 0);
+  }
+
+  //with leak-check, every allocation is tracked and aligned by the allocator itself.
+  #[cfg(all(debug_assertions, not(feature = "leak-check")))]
+  #[test]
+  fn misaligned_pool_allocation_should_assert_in_debug_builds() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    // mock AllocatePool() that returns a buffer offset from pool alignment, as a broken firmware pool might.
+    extern "efiapi" fn mock_allocate_pool_misaligned(
+      _pool_type: r_efi::system::MemoryType,
+      size: usize,
+      buffer: *mut *mut c_void,
+    ) -> Status {
+      //the (8-byte aligned) buffer is leaked, since it is never freed through the allocator.
+      let backing = std::boxed::Box::leak(std::vec![0u64; size / 8 + 2].into_boxed_slice());
+      unsafe { buffer.write(backing.as_mut_ptr().cast::<u8>().add(4) as *mut c_void) };
+      Status::SUCCESS
+    }
+
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pool = mock_allocate_pool_misaligned;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let result = std::panic::catch_unwind(|| unsafe { ALLOCATOR.alloc(layout) });
+    assert!(result.is_err());
+    let result = std::panic::catch_unwind(|| unsafe { ALLOCATOR.alloc_zeroed(layout) });
+    assert!(result.is_err());
+
+    //tracked allocations are aligned by the allocator, whatever the pool returns.
+    let aligned = Layout::from_size_align(0x40, 0x100).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(aligned) };
+    assert_eq!(ptr.align_offset(aligned.align()), 0);
+  }
+
+  thread_local! {
+    static OBSERVED_MAX_ADDRESS: Cell<Option<PhysicalAddress>> = Cell::new(None);
+  }
+
+  // mock AllocatePages() supporting only ALLOCATE_MAX_ADDRESS, which fails if the pages allocated from the host are not
+  // at or below the given maximum address.
+  extern "efiapi" fn mock_allocate_pages_max_address(
+    allocation_type: AllocateType,
+    memory_type: MemoryType,
+    pages: usize,
+    memory: *mut PhysicalAddress,
+  ) -> Status {
+    assert_eq!(allocation_type, ALLOCATE_MAX_ADDRESS);
+    let max_address = unsafe { memory.read() };
+    OBSERVED_MAX_ADDRESS.with(|observed| observed.set(Some(max_address)));
+    let status = mock_allocate_pages(ALLOCATE_ANY_PAGES, memory_type, pages, memory);
+    let base = unsafe { memory.read() };
+    if status == Status::SUCCESS && base + (pages * UEFI_PAGE_SIZE - 1) as PhysicalAddress > max_address {
+      testing::mock_free_pages(base, pages);
+      return Status::NOT_FOUND;
+    }
+    status
+  }
+
+  #[test]
+  fn alloc_in_range_should_allocate_pages_below_max_address() {
+    use crate::StaticArena;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    static ARENA: StaticArena<0x2000> = StaticArena::new();
+    let mut boot_services = mock_boot_services();
+    boot_services.allocate_pages = mock_allocate_pages_max_address;
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    ALLOCATOR.set_fallback(&ARENA);
+
+    //the alignment is raised to the page size, and the allocation is freed with the raised alignment.
+    let layout = Layout::from_size_align(0x200, 0x8).unwrap();
+    let max_address = PhysicalAddress::MAX;
+    let ptr = ALLOCATOR.alloc_in_range(layout, max_address);
+    assert!(!ptr.is_null());
+    assert_eq!(OBSERVED_MAX_ADDRESS.with(|observed| observed.take()), Some(max_address));
+    assert_eq!(ptr.align_offset(UEFI_PAGE_SIZE), 0);
+    assert_eq!(PAGE_ALLOCATION_TRACKER.lock().get(&(ptr as usize)), Some(&(1 + GUARD_PAGES)));
+    let page_layout = layout.align_to(UEFI_PAGE_SIZE).unwrap();
+    assert_eq!(tracker_for(ptr, page_layout).pages, 1);
+    unsafe { ALLOCATOR.dealloc(ptr, page_layout) };
+    assert!(!PAGE_ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+
+    //an unsatisfiable range returns null rather than using the fallback.
+    let max_address = 0xFFFF;
+    assert!(ALLOCATOR.alloc_in_range(layout, max_address).is_null());
+    assert_eq!(OBSERVED_MAX_ADDRESS.with(|observed| observed.take()), Some(max_address));
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+  }
+
+  #[test]
+  fn stats_should_be_readable_without_the_lock_during_allocations() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    const SIZE: usize = 0x80;
+    const COUNT: usize = 64;
+    let layout = Layout::from_size_align(SIZE, 0x8).unwrap();
+    let size = if cfg!(feature = "leak-check") { SIZE + core::mem::size_of::<AllocationTracker>() } else { SIZE };
+
+    //stats can be read while the lock is held.
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    {
+      let _guard = ALLOCATOR.inner.lock();
+      assert_eq!(ALLOCATOR.stats().outstanding_allocations, 1);
+    }
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+
+    //a monitor reading stats while allocations are made and freed sees consistent, monotonic snapshots.
+    let done = std::sync::Arc::new(AtomicBool::new(false));
+    let monitor = {
+      let done = done.clone();
+      std::thread::spawn(move || {
+        let mut last_peak = 0;
+        let mut snapshots = 0usize;
+        while !done.load(Ordering::Acquire) || snapshots == 0 {
+          let stats = ALLOCATOR.stats();
+          assert_eq!(stats.outstanding_bytes, stats.outstanding_allocations * size);
+          assert_eq!(stats.requested_bytes, stats.outstanding_allocations * SIZE);
+          assert!(stats.outstanding_allocations <= COUNT);
+          assert!(stats.peak_bytes >= last_peak && stats.peak_bytes >= stats.outstanding_bytes);
+          assert!(stats.peak_bytes <= COUNT * size);
+          last_peak = stats.peak_bytes;
+          snapshots += 1;
+        }
+      })
+    };
+    for _ in 0..16 {
+      let ptrs: std::vec::Vec<_> = (0..COUNT).map(|_| unsafe { ALLOCATOR.alloc(layout) }).collect();
+      for ptr in ptrs {
+        assert!(!ptr.is_null());
+        unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      }
+    }
+    done.store(true, Ordering::Release);
+    monitor.join().unwrap();
+    assert_eq!(
+      ALLOCATOR.stats(),
+      AllocationStats {
+        outstanding_bytes: 0,
+        peak_bytes: COUNT * size,
+        outstanding_allocations: 0,
+        requested_bytes: 0
+      }
+    );
+  }
+
+  #[cfg(not(feature = "leak-check"))]
+  #[test]
+  fn sixteen_byte_requests_should_skip_tracker_when_pool_aligns_them() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let mut boot_services = offset_pool_boot_services();
+    ALLOCATOR.init(&mut boot_services).unwrap();
+    let layout = Layout::from_size_align(0x40, 0x10).unwrap();
+    let outstanding_pool = || OFFSET_POOL.with(|pool| pool.borrow().clone());
+
+    //an aligned probe is kept as the allocation, and freed directly.
+    POOL_OFFSET.with(|offset| offset.set(0));
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    let zeroed = unsafe { ALLOCATOR.alloc_zeroed(layout) };
+    assert_eq!(outstanding_pool(), [ptr as usize, zeroed as usize].into_iter().collect());
+    assert_eq!(ALLOCATOR.stats().overhead_bytes(), 0);
+    let grown = unsafe { ALLOCATOR.realloc(ptr, layout, 0x80) };
+    assert!(!outstanding_pool().contains(&(ptr as usize)));
+    assert!(outstanding_pool().contains(&(grown as usize)));
+    assert_eq!(ALLOCATOR.stats().overhead_bytes(), 0);
+    unsafe {
+      ALLOCATOR.dealloc(grown, Layout::from_size_align(0x80, 0x10).unwrap());
+      ALLOCATOR.dealloc(zeroed, layout);
+    }
+    assert!(outstanding_pool().is_empty());
+    assert_eq!(ALLOCATOR.inner.lock().probed_count, 0);
+
+    //a misaligned probe is freed, and the request is made with a tracking structure instead.
+    POOL_OFFSET.with(|offset| offset.set(8));
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert_eq!(ptr as usize % 0x10, 0);
+    let tracker = tracker_for(ptr, layout);
+    assert_eq!(tracker.signature, ALLOC_TRACKER_SIG);
+    assert_eq!(outstanding_pool(), [tracker.orig_ptr as usize].into_iter().collect());
+    assert!(ALLOCATOR.stats().overhead_bytes() > 0);
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    assert!(outstanding_pool().is_empty());
+
+    //once every probed allocation slot is in use, requests are tracked.
+    POOL_OFFSET.with(|offset| offset.set(0));
+    let probed: std::vec::Vec<_> = (0..crate::PROBED_SLOTS).map(|_| unsafe { ALLOCATOR.alloc(layout) }).collect();
+    assert_eq!(ALLOCATOR.stats().overhead_bytes(), 0);
+    let tracked = unsafe { ALLOCATOR.alloc(layout) };
+    assert_eq!(tracker_for(tracked, layout).signature, ALLOC_TRACKER_SIG);
+    assert!(ALLOCATOR.stats().overhead_bytes() > 0);
+    for ptr in probed.into_iter().chain([tracked]) {
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+    assert!(outstanding_pool().is_empty());
+    assert_eq!(ALLOCATOR.stats(), AllocationStats { peak_bytes: ALLOCATOR.stats().peak_bytes, ..Default::default() });
+  }
+
+  #[cfg(not(feature = "leak-check"))]
+  #[test]
+  fn arena_mode_should_carve_small_allocations_out_of_one_block() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    ALLOCATOR.enable_arena(0x1000).unwrap();
+    assert_eq!(ALLOCATOR.enable_arena(0x1000), Err(Status::ALREADY_STARTED));
+
+    let block = ALLOCATOR.inner.lock().arena.as_ref().unwrap().block as usize;
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&block));
+    let arena = block..block + 0x1000;
+
+    //small allocations come from the block, with freed blocks reused.
+    let small = Layout::from_size_align(0x40, 0x8).unwrap();
+    let first = unsafe { ALLOCATOR.alloc(small) };
+    let second = unsafe { ALLOCATOR.alloc(small) };
+    assert!(arena.contains(&(first as usize)) && arena.contains(&(second as usize)));
+    unsafe { ALLOCATOR.dealloc(first, small) };
+    assert_eq!(unsafe { ALLOCATOR.alloc(small) }, first);
+
+    //larger requests bypass the arena.
+    let large = Layout::from_size_align(0x200, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(large) };
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 3);
+    unsafe { ALLOCATOR.dealloc(ptr, large) };
+
+    //once disabled, the block is kept until its last allocation is freed, and new allocations come from the pool.
+    ALLOCATOR.disable_arena();
+    let ptr = unsafe { ALLOCATOR.alloc(small) };
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    unsafe { ALLOCATOR.dealloc(ptr, small) };
+    unsafe { ALLOCATOR.dealloc(first, small) };
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&block));
+    unsafe { ALLOCATOR.dealloc(second, small) };
+    assert!(!ALLOCATION_TRACKER.lock().contains_key(&block));
+    assert!(ALLOCATOR.inner.lock().arena.is_none());
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+    assert_eq!(ALLOCATOR.stats().outstanding_bytes, 0);
+  }
+
+  #[cfg(not(feature = "leak-check"))]
+  #[test]
+  fn disabling_empty_arena_should_free_block() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    assert_eq!(ALLOCATOR.enable_arena(0x100), Err(Status::NOT_READY));
+    ALLOCATOR.init(&mut mock_boot_services()).unwrap();
+    assert_eq!(ALLOCATOR.enable_arena(0), Err(Status::INVALID_PARAMETER));
+    ALLOCATOR.enable_arena(0x100).unwrap();
+
+    //allocations are served from the pool once the block is exhausted.
+    let block = ALLOCATOR.inner.lock().arena.as_ref().unwrap().block as usize;
+    let layout = Layout::from_size_align(0x100, 0x8).unwrap();
+    let ptrs = [unsafe { ALLOCATOR.alloc(layout) }, unsafe { ALLOCATOR.alloc(layout) }];
+    assert_eq!(ptrs[0] as usize, block);
+    assert!(ALLOCATION_TRACKER.lock().contains_key(&(ptrs[1] as usize)));
+    for ptr in ptrs {
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+    }
+
+    ALLOCATOR.disable_arena();
+    assert!(!ALLOCATION_TRACKER.lock().contains_key(&block));
+    assert!(ALLOCATOR.inner.lock().arena.is_none());
+    ALLOCATOR.enable_arena(0x100).unwrap();
+  }
+
+  #[cfg(all(feature = "validate-free", not(feature = "leak-check")))]
+  #[test]
+  fn validate_free_should_skip_pointers_not_allocated_directly() {
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_logger;
+    rust_advanced_logger_dxe::reinit_debug(&mut boot_services).unwrap();
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    //the mock free_pool panics on pointers it did not allocate, so a foreign pointer reaching it fails the test.
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let foreign = unsafe { System.alloc(layout) };
+    let ptrs: std::vec::Vec<_> = (0..16).map(|_| unsafe { ALLOCATOR.alloc(layout) }).collect();
+    unsafe { ALLOCATOR.dealloc(foreign, layout) };
+    let expected =
+      std::format!("free of {:p} skipped: not allocated by this allocator (size 0x40, align 0x8)\n", foreign);
+    let output: std::string::String = LOGGED
+      .with(|logged| logged.take())
+      .into_iter()
+      .map(|(level, text)| {
+        assert_eq!(level, rust_advanced_logger_dxe::DEBUG_ERROR);
+        text
+      })
+      .collect();
+    assert_eq!(output, expected);
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, ptrs.len());
+
+    //legitimate frees still reach firmware.
+    for ptr in ptrs {
+      unsafe { ALLOCATOR.dealloc(ptr, layout) };
+      assert!(!ALLOCATION_TRACKER.lock().contains_key(&(ptr as usize)));
+    }
+    assert!(LOGGED.with(|logged| logged.take()).is_empty());
+    assert_eq!(ALLOCATOR.stats().outstanding_allocations, 0);
+    unsafe { System.dealloc(foreign, layout) };
+  }
+
+  #[cfg(feature = "alloc-site")]
+  #[test]
+  fn alloc_site_should_be_recorded_and_dumped() {
+    use rust_advanced_logger_dxe::DEBUG_WARN;
+    static ALLOCATOR: SpinLockedAllocator = SpinLockedAllocator::new();
+    let _lock = GLOBAL_LOGGER_TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut boot_services = mock_boot_services();
+    boot_services.locate_protocol = mock_locate_logger;
+    rust_advanced_logger_dxe::reinit_debug(&mut boot_services).unwrap();
+    ALLOCATOR.init(&mut boot_services).unwrap();
+
+    //small (pool aligned) allocations are tracked too, and resizing records the site of the resize.
+    let layout = Layout::from_size_align(0x40, 0x8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    let site = tracker_for(ptr, layout).site;
+    assert_ne!(site, 0);
+    let ptr = unsafe { ALLOCATOR.realloc(ptr, layout, 0x80) };
+    let layout = Layout::from_size_align(0x80, 0x8).unwrap();
+    assert_ne!(tracker_for(ptr, layout).site, 0);
+    assert_ne!(tracker_for(ptr, layout).site, site);
+
+    ALLOCATOR.dump_outstanding(DEBUG_WARN);
+    let output: std::string::String = LOGGED.with(|logged| logged.take()).into_iter().map(|(_, text)| text).collect();
+    let expected = std::format!(", allocated from {:#x}", tracker_for(ptr, layout).site);
+    assert!(output.starts_with(&std::format!("outstanding allocation {:p}", ptr)));
+    assert!(output.contains(&expected), "{:}", output);
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+  }
 }
@@ -0,0 +1,143 @@
+//! Allocator Lock
+//!
+//! The [`RawMutex`] trait used to synchronize access to a [`SpinLockedAllocator`](crate::SpinLockedAllocator), shared
+//! with the logger, and the crate-internal mutex that pairs a raw mutex with the allocator state it protects.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+use core::{
+  cell::UnsafeCell,
+  ops::{Deref, DerefMut},
+};
+
+/// The lock of a [`SpinLockedAllocator`](crate::SpinLockedAllocator): the logger's
+/// [`RawMutex`](rust_advanced_logger_dxe::RawMutex), so that a custom lock (e.g. one that disables interrupts) is
+/// implemented once and can be used for both the allocator and the logger.
+///
+/// Implemented for `spin::Mutex<()>` (the default) and [`UnsyncRawMutex`](crate::UnsyncRawMutex).
+///
+/// ## Example
+/// ```no_run
+/// use core::sync::atomic::{AtomicBool, Ordering};
+/// use rust_boot_services_allocator_dxe::{RawMutex, SpinLockedAllocator};
+///
+/// struct FlagLock(AtomicBool);
+///
+/// unsafe impl RawMutex for FlagLock {
+///   #[allow(clippy::declare_interior_mutable_const)]
+///   const INIT: Self = FlagLock(AtomicBool::new(false));
+///
+///   fn lock(&self) {
+///     while !self.try_lock() {
+///       core::hint::spin_loop();
+///     }
+///   }
+///
+///   fn try_lock(&self) -> bool {
+///     self.0.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+///   }
+///
+///   unsafe fn unlock(&self) {
+///     self.0.store(false, Ordering::Release);
+///   }
+/// }
+///
+/// static ALLOCATOR: SpinLockedAllocator<FlagLock> = SpinLockedAllocator::new();
+/// ```
+pub use rust_advanced_logger_dxe::RawMutex;
+
+// Mutex protecting a value of type T with a raw mutex of type R.
+pub(crate) struct Mutex<R, T> {
+  raw: R,
+  data: UnsafeCell<T>,
+}
+
+impl<R: RawMutex, T> Mutex<R, T> {
+  // creates a new, unlocked mutex. const fn to allow static initialization.
+  pub(crate) const fn new(value: T) -> Self {
+    Mutex { raw: R::INIT, data: UnsafeCell::new(value) }
+  }
+
+  // locks the mutex, providing exclusive access to the value until the returned guard is dropped.
+  pub(crate) fn lock(&self) -> MutexGuard<'_, R, T> {
+    self.raw.lock();
+    MutexGuard { mutex: self }
+  }
+
+  // locks the mutex if it is not already locked.
+  pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, R, T>> {
+    //the guard must only be created once the lock is held, since dropping it releases the lock.
+    match self.raw.try_lock() {
+      true => Some(MutexGuard { mutex: self }),
+      false => None,
+    }
+  }
+
+  // returns the raw mutex.
+  #[cfg(test)]
+  pub(crate) fn raw(&self) -> &R {
+    &self.raw
+  }
+
+  // returns true if the mutex is currently locked.
+  #[cfg(test)]
+  pub(crate) fn is_locked(&self) -> bool {
+    match self.raw.try_lock() {
+      true => {
+        unsafe { self.raw.unlock() };
+        false
+      }
+      false => true,
+    }
+  }
+}
+
+// Guard providing access to the value protected by a Mutex. The mutex is unlocked when the guard is dropped.
+pub(crate) struct MutexGuard<'a, R: RawMutex, T> {
+  mutex: &'a Mutex<R, T>,
+}
+
+impl<R: RawMutex, T> Deref for MutexGuard<'_, R, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    unsafe { &*self.mutex.data.get() }
+  }
+}
+
+impl<R: RawMutex, T> DerefMut for MutexGuard<'_, R, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { &mut *self.mutex.data.get() }
+  }
+}
+
+impl<R: RawMutex, T> Drop for MutexGuard<'_, R, T> {
+  fn drop(&mut self) {
+    unsafe { self.mutex.raw.unlock() };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+  use super::Mutex;
+
+  #[test]
+  fn spin_mutex_should_provide_exclusive_access() {
+    let mutex: Mutex<spin::Mutex<()>, usize> = Mutex::new(1);
+    assert!(!mutex.is_locked());
+    {
+      let mut guard = mutex.lock();
+      *guard += 1;
+      assert!(mutex.is_locked());
+      assert!(mutex.try_lock().is_none());
+    }
+    assert!(!mutex.is_locked());
+    assert_eq!(*mutex.try_lock().unwrap(), 2);
+  }
+}
@@ -0,0 +1,73 @@
+//! Single-Threaded Lock
+//!
+//! Unsynchronized [`RawMutex`] used as the allocator lock when the `single-threaded` feature is enabled (and available
+//! to select explicitly otherwise). Locking sets a `Cell` flag rather than performing an atomic operation, so it is
+//! cheaper on the single-processor DXE phase, and re-entrant locking (e.g. freeing from an event notification that
+//! interrupted an allocation) panics rather than corrupting the allocator state. Re-entrant allocations do not lock;
+//! the allocator fails them first, see [`SpinLockedAllocator`](crate::SpinLockedAllocator).
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+use core::cell::Cell;
+
+use crate::RawMutex;
+
+/// A [`RawMutex`] that does not synchronize between processors, for allocators that are only used from a single
+/// processor, see [`SpinLockedAllocator`](crate::SpinLockedAllocator).
+///
+/// Locking an `UnsyncRawMutex` that is already locked panics rather than waiting, since on a single processor the lock
+/// can only be held by code that was interrupted (or re-entered) and cannot release it.
+pub struct UnsyncRawMutex {
+  locked: Cell<bool>,
+}
+
+unsafe impl RawMutex for UnsyncRawMutex {
+  #[allow(clippy::declare_interior_mutable_const)]
+  const INIT: Self = UnsyncRawMutex { locked: Cell::new(false) };
+
+  fn lock(&self) {
+    assert!(self.try_lock(), "single-threaded allocator lock re-entered");
+  }
+
+  fn try_lock(&self) -> bool {
+    !self.locked.replace(true)
+  }
+
+  unsafe fn unlock(&self) {
+    self.locked.set(false);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+  use super::UnsyncRawMutex;
+  use crate::lock::Mutex;
+
+  #[test]
+  fn lock_should_provide_exclusive_access() {
+    let lock: Mutex<UnsyncRawMutex, usize> = Mutex::new(1);
+    assert!(!lock.is_locked());
+    {
+      let mut guard = lock.lock();
+      *guard += 1;
+      assert!(lock.is_locked());
+      assert!(lock.try_lock().is_none());
+    }
+    assert!(!lock.is_locked());
+    assert_eq!(*lock.lock(), 2);
+  }
+
+  #[test]
+  fn reentrant_lock_should_panic() {
+    let lock: Mutex<UnsyncRawMutex, ()> = Mutex::new(());
+    let _guard = lock.lock();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(lock.lock())));
+    assert!(result.is_err());
+  }
+}
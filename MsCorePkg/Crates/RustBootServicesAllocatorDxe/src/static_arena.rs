@@ -0,0 +1,268 @@
+//! Static Arena Allocator
+//!
+//! Fixed-capacity allocator over a statically allocated buffer, intended as a fallback (see
+//! [`SpinLockedAllocator::set_fallback()`](crate::SpinLockedAllocator::set_fallback)) for when the boot services pool
+//! is exhausted. Memory is handed out from the unused end of the buffer (bump allocation), and freed blocks are kept on
+//! a free list for reuse. The same allocation scheme backs the arena mode of the boot services allocator (see
+//! [`SpinLockedAllocator::enable_arena()`](crate::SpinLockedAllocator::enable_arena)), over a block of pool memory.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+use core::{
+  alloc::{GlobalAlloc, Layout},
+  cell::UnsafeCell,
+};
+
+// header written into each freed block to link it into the free list. Offsets are relative to the start of the arena.
+#[derive(Clone, Copy)]
+struct FreeBlock {
+  size: usize,
+  next: usize,
+}
+
+// offset marking the end of the free list.
+const END_OF_LIST: usize = usize::MAX;
+// every block is aligned to, and at least as large as, a FreeBlock, so that it can be linked into the free list.
+const BLOCK_ALIGN: usize = core::mem::align_of::<FreeBlock>();
+const MIN_BLOCK_SIZE: usize = core::mem::size_of::<FreeBlock>();
+
+// arena buffer, aligned so that block offsets that are multiples of BLOCK_ALIGN are aligned addresses.
+#[repr(C, align(16))]
+struct ArenaMemory<const N: usize>([u8; N]);
+
+// bump and free-list allocation state for an arena of `len` bytes at `base`. The arena memory is passed to each call
+// rather than stored, so that a StaticArena (whose address is only known once it is in place) can be moved during
+// construction.
+pub(crate) struct ArenaState {
+  //offset of the unused end of the arena.
+  next: usize,
+  //offset of the first free block.
+  free: usize,
+}
+
+impl ArenaState {
+  // creates the state for an empty arena. const fn to allow static initialization.
+  pub(crate) const fn new() -> Self {
+    ArenaState { next: 0, free: END_OF_LIST }
+  }
+
+  // size of the block used for an allocation with the given layout.
+  pub(crate) fn block_size(layout: Layout) -> usize {
+    layout.size().max(MIN_BLOCK_SIZE).next_multiple_of(BLOCK_ALIGN)
+  }
+
+  // pointer to the free block header at `offset`.
+  fn block(base: usize, offset: usize) -> *mut FreeBlock {
+    (base + offset) as *mut FreeBlock
+  }
+
+  // pushes the block at `offset` onto the free list.
+  unsafe fn push_free(&mut self, base: usize, offset: usize, size: usize) {
+    Self::block(base, offset).write(FreeBlock { size, next: self.free });
+    self.free = offset;
+  }
+
+  // allocates a block for `layout` from the arena of `len` bytes at `base`, which must be BLOCK_ALIGN aligned. Returns
+  // null if the arena is exhausted.
+  pub(crate) unsafe fn alloc(&mut self, base: usize, len: usize, layout: Layout) -> *mut u8 {
+    if layout.size() > len {
+      return core::ptr::null_mut();
+    }
+    let size = Self::block_size(layout);
+    let align = layout.align().max(BLOCK_ALIGN);
+
+    //use the first suitably aligned free block that is large enough, returning any usable remainder to the list.
+    let mut prev = END_OF_LIST;
+    let mut offset = self.free;
+    while offset != END_OF_LIST {
+      let block = Self::block(base, offset).read();
+      if (base + offset) % align == 0 && block.size >= size {
+        let remainder = block.size - size;
+        if remainder == 0 || remainder >= MIN_BLOCK_SIZE {
+          let next = match remainder {
+            0 => block.next,
+            _ => {
+              Self::block(base, offset + size).write(FreeBlock { size: remainder, next: block.next });
+              offset + size
+            }
+          };
+          match prev {
+            END_OF_LIST => self.free = next,
+            prev => (*Self::block(base, prev)).next = next,
+          }
+          return (base + offset) as *mut u8;
+        }
+      }
+      prev = offset;
+      offset = block.next;
+    }
+
+    //otherwise allocate from the unused end of the arena. Padding needed for alignment is kept for reuse if it is large
+    //enough to be a block.
+    let start = (base + self.next).next_multiple_of(align) - base;
+    match start.checked_add(size) {
+      Some(end) if end <= len => {
+        let padding = start - self.next;
+        if padding >= MIN_BLOCK_SIZE {
+          let padding_offset = self.next;
+          self.push_free(base, padding_offset, padding);
+        }
+        self.next = end;
+        (base + start) as *mut u8
+      }
+      _ => core::ptr::null_mut(),
+    }
+  }
+
+  // frees a block allocated with `layout` from the arena at `base`.
+  pub(crate) unsafe fn dealloc(&mut self, base: usize, ptr: *mut u8, layout: Layout) {
+    let size = Self::block_size(layout);
+    let offset = ptr as usize - base;
+    if offset + size == self.next {
+      //the block is at the end of the allocated region, so it can be returned to the unused end of the arena.
+      self.next = offset;
+    } else {
+      self.push_free(base, offset, size);
+    }
+  }
+}
+
+/// A [`GlobalAlloc`] over a fixed `N`-byte buffer, e.g. as the fallback for a [`SpinLockedAllocator`].
+///
+/// Allocations are served first-fit from previously freed blocks (splitting off any unused remainder), and otherwise
+/// from the unused end of the buffer. Blocks are rounded up to a multiple of 8 bytes (minimum 16). Freed blocks are
+/// not merged with each other, so a long-lived arena can fragment; it is intended to tide a driver over until pool
+/// memory is available again rather than as a general-purpose heap. When the arena is exhausted, allocations return
+/// null.
+///
+/// The arena state is protected by a [`spin::Mutex`], so the arena may be used from a `static`.
+///
+/// ## Example
+/// ```no_run
+/// use rust_boot_services_allocator_dxe::{StaticArena, GLOBAL_ALLOCATOR};
+///
+/// static FALLBACK_ARENA: StaticArena<0x10000> = StaticArena::new();
+///
+/// GLOBAL_ALLOCATOR.set_fallback(&FALLBACK_ARENA);
+/// ```
+///
+/// [`SpinLockedAllocator`]: crate::SpinLockedAllocator
+pub struct StaticArena<const N: usize> {
+  memory: UnsafeCell<ArenaMemory<N>>,
+  state: spin::Mutex<ArenaState>,
+}
+
+impl<const N: usize> StaticArena<N> {
+  /// Creates a new, empty arena. const fn to allow static initialization.
+  pub const fn new() -> Self {
+    StaticArena { memory: UnsafeCell::new(ArenaMemory([0; N])), state: spin::Mutex::new(ArenaState::new()) }
+  }
+
+  // address of the start of the arena.
+  fn base(&self) -> usize {
+    self.memory.get() as usize
+  }
+}
+
+impl<const N: usize> Default for StaticArena<N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for StaticArena<N> {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    self.state.lock().alloc(self.base(), N, layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    self.state.lock().dealloc(self.base(), ptr, layout)
+  }
+}
+
+unsafe impl<const N: usize> Sync for StaticArena<N> {}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+  use core::alloc::{GlobalAlloc, Layout};
+
+  use super::StaticArena;
+
+  // returns true if the `size` bytes at `ptr` lie within `arena`.
+  fn in_arena<const N: usize>(arena: &StaticArena<N>, ptr: *mut u8, size: usize) -> bool {
+    let base = arena.base();
+    ptr as usize >= base && ptr as usize + size <= base + N
+  }
+
+  #[test]
+  fn arena_should_allocate_distinct_blocks() {
+    static ARENA: StaticArena<0x100> = StaticArena::new();
+    let layout = Layout::from_size_align(0x20, 0x8).unwrap();
+    let first = unsafe { ARENA.alloc(layout) };
+    let second = unsafe { ARENA.alloc(layout) };
+    assert!(!first.is_null() && !second.is_null());
+    assert!(in_arena(&ARENA, first, layout.size()) && in_arena(&ARENA, second, layout.size()));
+    assert!(first as usize + layout.size() <= second as usize);
+
+    unsafe {
+      first.write_bytes(0x11, layout.size());
+      second.write_bytes(0x22, layout.size());
+      assert!(core::slice::from_raw_parts(first, layout.size()).iter().all(|byte| *byte == 0x11));
+    }
+  }
+
+  #[test]
+  fn arena_should_align_allocations() {
+    static ARENA: StaticArena<0x4000> = StaticArena::new();
+    for align in [0x1, 0x8, 0x40, 0x1000] {
+      let layout = Layout::from_size_align(0x3, align).unwrap();
+      let ptr = unsafe { ARENA.alloc(layout) };
+      assert!(!ptr.is_null());
+      assert_eq!(ptr.align_offset(align), 0);
+      assert!(in_arena(&ARENA, ptr, layout.size()));
+    }
+  }
+
+  #[test]
+  fn exhausted_arena_should_return_null() {
+    static ARENA: StaticArena<0x100> = StaticArena::new();
+    let layout = Layout::from_size_align(0x80, 0x8).unwrap();
+    let first = unsafe { ARENA.alloc(layout) };
+    let second = unsafe { ARENA.alloc(layout) };
+    assert!(!first.is_null() && !second.is_null());
+    assert!(unsafe { ARENA.alloc(layout) }.is_null());
+    assert!(unsafe { ARENA.alloc(Layout::from_size_align(0x200, 0x8).unwrap()) }.is_null());
+
+    //freeing makes room again.
+    unsafe { ARENA.dealloc(first, layout) };
+    assert_eq!(unsafe { ARENA.alloc(layout) }, first);
+  }
+
+  #[test]
+  fn freed_blocks_should_be_reused() {
+    static ARENA: StaticArena<0x200> = StaticArena::new();
+    let large = Layout::from_size_align(0x40, 0x8).unwrap();
+    let small = Layout::from_size_align(0x10, 0x8).unwrap();
+    let first = unsafe { ARENA.alloc(large) };
+    let guard = unsafe { ARENA.alloc(small) };
+
+    //a freed block is split to satisfy smaller requests, and the remainder remains available.
+    unsafe { ARENA.dealloc(first, large) };
+    let reused = unsafe { ARENA.alloc(small) };
+    assert_eq!(reused, first);
+    let remainder = unsafe { ARENA.alloc(Layout::from_size_align(0x30, 0x8).unwrap()) };
+    assert_eq!(remainder as usize, first as usize + small.size());
+
+    //freeing the last block returns it to the unused end of the arena.
+    let last = unsafe { ARENA.alloc(large) };
+    unsafe { ARENA.dealloc(last, large) };
+    assert_eq!(unsafe { ARENA.alloc(large) }, last);
+    assert!(!guard.is_null());
+  }
+}
@@ -0,0 +1,174 @@
+//! Host Test Doubles
+//!
+//! Mock boot services backed by the host (`std`) allocator, for host-based (`cargo test`) tests of crates that
+//! allocate with the [`GLOBAL_ALLOCATOR`](crate::GLOBAL_ALLOCATOR). Available with the `testing` feature, which
+//! requires `std`. With this feature enabled, [`GLOBAL_ALLOCATOR`](crate::GLOBAL_ALLOCATOR) is not registered as the
+//! `#[global_allocator]`, so test binaries keep using the host allocator.
+//!
+//! The mocks track every outstanding pool and page allocation, and panic on frees of memory they did not allocate.
+//! Tests can wrap the services in [`mock_boot_services`], e.g. to inject failures.
+//!
+//! ## Example
+//! ```
+//! use core::alloc::{GlobalAlloc, Layout};
+//! use rust_boot_services_allocator_dxe::{testing, GLOBAL_ALLOCATOR};
+//!
+//! let mut boot_services = testing::mock_boot_services();
+//! GLOBAL_ALLOCATOR.init(&mut boot_services).unwrap();
+//!
+//! let layout = Layout::from_size_align(0x100, 8).unwrap();
+//! let ptr = unsafe { GLOBAL_ALLOCATOR.alloc(layout) };
+//! assert_eq!(testing::outstanding_pool_allocations(), 1);
+//! unsafe { GLOBAL_ALLOCATOR.dealloc(ptr, layout) };
+//! assert_eq!(testing::outstanding_pool_allocations(), 0);
+//! ```
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+
+extern crate std;
+
+use core::{
+  alloc::{GlobalAlloc, Layout},
+  cell::Cell,
+  ffi::c_void,
+  mem::MaybeUninit,
+};
+use r_efi::{
+  efi::{PhysicalAddress, Status},
+  system::{AllocateType, BootServices, MemoryType, ALLOCATE_ANY_PAGES},
+};
+use std::{alloc::System, collections::BTreeMap, thread_local};
+
+use crate::UEFI_PAGE_SIZE;
+
+//pool allocations made by mock_allocate_pool (address to host layout).
+pub(crate) static ALLOCATION_TRACKER: spin::Mutex<BTreeMap<usize, Layout>> = spin::Mutex::new(BTreeMap::new());
+//page ranges allocated by mock_allocate_pages (first page address to page count). Ranges may be freed in part.
+pub(crate) static PAGE_ALLOCATION_TRACKER: spin::Mutex<BTreeMap<usize, usize>> = spin::Mutex::new(BTreeMap::new());
+//host allocations backing mock_allocate_pages (first page address to host address, host layout, and pages in use).
+pub(crate) static PAGE_BACKING_TRACKER: spin::Mutex<BTreeMap<usize, (usize, Layout, usize)>> =
+  spin::Mutex::new(BTreeMap::new());
+//alignment of the host allocations backing mock_allocate_pages.
+const MOCK_PAGE_ALIGNMENT: usize = 0x10000;
+
+thread_local! {
+  //number of pages by which mock_allocate_pages offsets allocations from MOCK_PAGE_ALIGNMENT.
+  static PAGE_BASE_OFFSET: Cell<usize> = Cell::new(0);
+}
+
+// mock AllocatePool(): allocates `size` bytes (8-byte aligned) of any memory type from the host allocator.
+pub(crate) extern "efiapi" fn mock_allocate_pool(
+  _pool_type: MemoryType,
+  size: usize,
+  buffer: *mut *mut c_void,
+) -> Status {
+  unsafe {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let ptr = System.alloc(layout) as *mut c_void;
+    buffer.write(ptr);
+    let existing_key = ALLOCATION_TRACKER.lock().insert(ptr as usize, layout);
+    assert!(existing_key.is_none());
+  }
+
+  Status::SUCCESS
+}
+
+// mock FreePool(): frees a buffer allocated by mock_allocate_pool.
+pub(crate) extern "efiapi" fn mock_free_pool(buffer: *mut c_void) -> Status {
+  let layout = ALLOCATION_TRACKER.lock().remove(&(buffer as usize)).expect("freeing an un-allocated pointer");
+  unsafe {
+    System.dealloc(buffer as *mut u8, layout);
+  }
+
+  Status::SUCCESS
+}
+
+// mock AllocatePages(): allocates `pages` pages of any memory type from the host allocator. Only ALLOCATE_ANY_PAGES is
+// supported. Allocations are 64 KiB aligned, unless offset with set_page_base_offset.
+pub(crate) extern "efiapi" fn mock_allocate_pages(
+  allocation_type: AllocateType,
+  _memory_type: MemoryType,
+  pages: usize,
+  memory: *mut PhysicalAddress,
+) -> Status {
+  assert_eq!(allocation_type, ALLOCATE_ANY_PAGES);
+
+  let offset = PAGE_BASE_OFFSET.with(Cell::get);
+  let layout = Layout::from_size_align((pages + offset) * UEFI_PAGE_SIZE, MOCK_PAGE_ALIGNMENT).unwrap();
+  let host = unsafe { System.alloc(layout) } as usize;
+  let base = host + offset * UEFI_PAGE_SIZE;
+  unsafe { memory.write(base as PhysicalAddress) };
+  let existing_key = PAGE_ALLOCATION_TRACKER.lock().insert(base, pages);
+  assert!(existing_key.is_none());
+  PAGE_BACKING_TRACKER.lock().insert(base, (host, layout, pages));
+
+  Status::SUCCESS
+}
+
+// mock FreePages(): frees pages allocated by mock_allocate_pages. As with firmware, any range of allocated pages may be
+// freed, not only whole allocations.
+pub(crate) extern "efiapi" fn mock_free_pages(memory: PhysicalAddress, pages: usize) -> Status {
+  let start = memory as usize;
+  let end = start + pages * UEFI_PAGE_SIZE;
+
+  //split the allocated range containing the freed pages, keeping any pages either side of them.
+  let mut tracker = PAGE_ALLOCATION_TRACKER.lock();
+  let (range_start, range_pages) =
+    tracker.range(..=start).next_back().map(|(&base, &pages)| (base, pages)).expect("freeing un-allocated pages");
+  let range_end = range_start + range_pages * UEFI_PAGE_SIZE;
+  assert!(end <= range_end, "freeing un-allocated pages");
+  tracker.remove(&range_start);
+  if start > range_start {
+    tracker.insert(range_start, (start - range_start) / UEFI_PAGE_SIZE);
+  }
+  if end < range_end {
+    tracker.insert(end, (range_end - end) / UEFI_PAGE_SIZE);
+  }
+  drop(tracker);
+
+  //release the backing host allocation once all of its pages are freed.
+  let mut backing = PAGE_BACKING_TRACKER.lock();
+  let (&base, (host, layout, in_use)) = backing.range_mut(..=start).next_back().expect("freeing un-allocated pages");
+  *in_use -= pages;
+  if *in_use == 0 {
+    unsafe { System.dealloc(*host as *mut u8, *layout) };
+    backing.remove(&base);
+  }
+
+  Status::SUCCESS
+}
+
+/// Returns boot services implementing AllocatePool(), FreePool(), AllocatePages() and FreePages() with the host
+/// allocator. Any memory type is accepted, and only `ALLOCATE_ANY_PAGES` page allocations are supported. Page
+/// allocations are 64 KiB aligned unless offset with [`set_page_base_offset`], and any range of allocated pages may be
+/// freed, as with firmware. All other services are null and must not be called; callers may install their own.
+pub fn mock_boot_services() -> BootServices {
+  let boot_services = MaybeUninit::zeroed();
+  let mut boot_services: BootServices = unsafe { boot_services.assume_init() };
+  boot_services.allocate_pool = mock_allocate_pool;
+  boot_services.free_pool = mock_free_pool;
+  boot_services.allocate_pages = mock_allocate_pages;
+  boot_services.free_pages = mock_free_pages;
+  boot_services
+}
+
+/// Sets the number of pages by which the mock AllocatePages() offsets allocations on the calling thread from 64 KiB
+/// alignment (default 0), to test handling of bases with less alignment than requested.
+pub fn set_page_base_offset(pages: usize) {
+  PAGE_BASE_OFFSET.with(|offset| offset.set(pages));
+}
+
+/// Returns the number of outstanding mock AllocatePool() allocations, across all threads.
+pub fn outstanding_pool_allocations() -> usize {
+  ALLOCATION_TRACKER.lock().len()
+}
+
+/// Returns the number of outstanding mock AllocatePages() pages, across all threads.
+pub fn outstanding_pages() -> usize {
+  PAGE_ALLOCATION_TRACKER.lock().values().sum()
+}
@@ -0,0 +1,40 @@
+//! Exercises the public host test doubles the way a consumer crate's integration tests would.
+//!
+//! ## License
+//!
+//! Copyright (C) Microsoft Corporation. All rights reserved.
+//!
+//! SPDX-License-Identifier: BSD-2-Clause-Patent
+//!
+#![cfg(feature = "testing")]
+
+use core::alloc::{GlobalAlloc, Layout};
+
+use rust_boot_services_allocator_dxe::{testing, GLOBAL_ALLOCATOR};
+
+#[test]
+fn mock_boot_services_should_back_allocations() {
+  let mut boot_services = testing::mock_boot_services();
+  GLOBAL_ALLOCATOR.init(&mut boot_services).unwrap();
+
+  let pool_layout = Layout::from_size_align(0x100, 8).unwrap();
+  let pool_ptr = unsafe { GLOBAL_ALLOCATOR.alloc(pool_layout) };
+  assert!(!pool_ptr.is_null());
+  assert_eq!(testing::outstanding_pool_allocations(), 1);
+
+  //a base that is not 64 KiB aligned exercises the large-alignment trimming.
+  testing::set_page_base_offset(3);
+  let page_layout = Layout::from_size_align(0x2000, 0x10000).unwrap();
+  let page_ptr = unsafe { GLOBAL_ALLOCATOR.alloc(page_layout) };
+  testing::set_page_base_offset(0);
+  assert_eq!(page_ptr.align_offset(0x10000), 0);
+  //the guard-pages feature adds a guard page after the allocation.
+  assert_eq!(testing::outstanding_pages(), if cfg!(feature = "guard-pages") { 4 } else { 3 });
+
+  unsafe {
+    GLOBAL_ALLOCATOR.dealloc(pool_ptr, pool_layout);
+    GLOBAL_ALLOCATOR.dealloc(page_ptr, page_layout);
+  }
+  assert_eq!(testing::outstanding_pool_allocations(), 0);
+  assert_eq!(testing::outstanding_pages(), 0);
+}
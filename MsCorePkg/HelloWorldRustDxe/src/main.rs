@@ -28,8 +28,8 @@ mod uefi_entry {
     _image_handle: *const core::ffi::c_void,
     _system_table: *const r_efi::system::SystemTable,
   ) -> u64 {
-    rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR.init(unsafe { (*_system_table).boot_services });
-    init_debug(unsafe { (*_system_table).boot_services });
+    let _ = rust_boot_services_allocator_dxe::GLOBAL_ALLOCATOR.init(unsafe { (*_system_table).boot_services });
+    let _ = init_debug(unsafe { (*_system_table).boot_services });
 
     debugln!(DEBUG_INFO, "Hello, World. This is Rust in UEFI.");
 